@@ -1,89 +1,500 @@
-use axum::{Json, http::StatusCode};
+use axum::{Json, extract::State, http::StatusCode, response::{IntoResponse, Response}};
 use serde::{Deserialize, Serialize};
-use tracing::{info, error};
+use std::sync::Arc;
+use tracing::{info, error, warn};
 
-use crate::rag::{retriever, generator};
+use crate::api::upload::{self, FromUpload, Payload, TempFile, Upload};
+use crate::api::voice;
+use crate::rag::{citations, confidence, knowledge_base, retriever, generator, grounding, rewriter};
+use crate::rag::agent::ToolCall;
+use crate::rag::citations::Citation;
+use crate::rag::grounding::GroundingMode;
+use crate::rag::locality::PhotoContext;
+use crate::services::generation::{GenerationOverrides, ResponseMode};
+use crate::services::image_intake::{self, ImageError, Photo};
+use crate::services::image_quality::{self, QualityIssue};
+use crate::services::jobs::JobStatus;
+use crate::services::llm::{LlmRequest, PredictionTracker};
+use crate::services::sessions::{Role, Turn};
 use crate::services::translator;
+use crate::state::AppState;
+use crate::utils::logger;
 
 #[derive(Deserialize)]
 pub struct ChatRequest {
+    #[serde(default)]
     pub query: String,
     pub language: Option<String>, // "en", "hi", "mr"
-    pub image: Option<String>,    // Base64 encoded image or URL
+    pub image: Option<Photo>,     // Base64 encoded image
+    pub session_id: Option<String>, // continue a conversation; a new one is started if absent
+    #[serde(default)]
+    pub mode: ResponseMode,       // "brief" (SMS-sized), "normal" or "detailed"
+    #[serde(default)]
+    pub params: GenerationOverrides, // max_tokens / temperature / top_p, capped per mode
+    #[serde(default)]
+    pub async_job: bool,          // return a job ID at once instead of waiting for the answer
+    #[serde(default)]
+    pub share_location: bool,     // consent to use the photo's GPS and date; they are never sent upstream
+    #[serde(skip)]
+    pub voice_note: Option<TempFile>, // multipart uploads only
+    #[serde(skip)]
+    pub photo_context: Option<PhotoContext>, // from the photo's EXIF, with consent
+    #[serde(skip)]
+    pub transcribed: bool,        // `query` came from the voice note
 }
 
-#[derive(Serialize)]
+impl FromUpload for ChatRequest {
+    fn from_upload(mut upload: Upload) -> Result<Self, ApiError> {
+        if upload.images.len() > 1 {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse { error: "Chat takes one photo; use /api/diagnose for several".to_string() })
+            ));
+        }
+        let object = upload.to_json(&["async_job", "params", "share_location"])?;
+        let mut request: ChatRequest = upload::from_json(object)?;
+        request.image = upload.images.pop().map(Photo::Raw);
+        request.voice_note = upload.voice_note.take();
+        Ok(request)
+    }
+}
+
+#[derive(Clone, Serialize)]
 pub struct ChatResponse {
     pub answer: String,
     pub sources: Vec<String>,
+    pub citations: Vec<Citation>,     // what each [n] marker in `answer` refers to
     pub confidence: String, // "low", "medium", "high"
+    pub confidence_score: f32, // 0-1; `confidence` is its band
+    pub unverified_figures: Vec<String>, // doses/prices/dates not found in the sources
     pub detected_language: String,
+    pub degraded: bool,               // true when `answer` is a fallback, not model output
+    pub fallback_reason: Option<String>,
+    pub answered_by: String,          // chain link that produced the answer, or "extractive"
+    pub session_id: String,
+    pub rewritten_query: Option<String>, // standalone form of a follow-up, used for retrieval
+    pub cached: bool,                 // served from the response cache without calling a model
+    pub tool_calls: Vec<ToolCall>,    // tools the model called (prices, weather, calculators), in order
+    pub photo_issues: Vec<QualityIssue>, // why the photo must be retaken; `answer` then asks for it
+    pub photo_context: Option<PhotoContext>, // district and season inferred from the photo, with consent
+    pub transcript: Option<String>,   // what was heard, when the question was a voice note
 }
 
+/// Returned with 202 when `async_job` is set
 #[derive(Serialize)]
+pub struct JobAccepted {
+    pub job_id: String,
+    pub status: JobStatus,
+    pub status_url: String,
+}
+
+#[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
 }
 
-pub async fn chat_handler(
-    Json(payload): Json<ChatRequest>,
-) -> Result<Json<ChatResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let original_query = payload.query.trim().to_string();
-    let user_lang = payload.language.unwrap_or_else(|| "en".to_string());
-    
-    info!("Received query: '{}' in language: {}", original_query, user_lang);
+pub type ApiError = (StatusCode, Json<ErrorResponse>);
+
+/// 413/415/400 with a message the farmer's app can show
+pub fn image_error(e: ImageError) -> ApiError {
+    warn!("Rejected photo: {}", e);
+    (e.status_code(), Json(ErrorResponse { error: e.to_string() }))
+}
 
-    if original_query.is_empty() {
+pub async fn chat_handler(
+    State(state): State<AppState>,
+    Payload(mut payload): Payload<ChatRequest>,
+) -> Result<Response, ApiError> {
+    // A voice note is the question unless one was also typed
+    if let Some(note) = payload.voice_note.take() {
+        if payload.query.trim().is_empty() {
+            let heard = voice::transcribe(&state, &note, payload.language.as_deref()).await?;
+            payload.query = heard.text;
+            // Without a pick, answer in the language that was spoken
+            payload.language = payload.language.or(heard.language);
+            payload.transcribed = true;
+        } else {
+            info!("Query typed as well as recorded; the voice note is not transcribed");
+        }
+    }
+    if payload.query.trim().is_empty() {
         return Err((
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse { error: "Query cannot be empty".to_string() })
         ));
     }
 
+    // Shrink and clean the photo before it is queued or sent anywhere
+    if let Some(image) = payload.image.take().filter(|i| !i.is_empty()) {
+        let prepared = image_intake::prepare_blocking(image, state.image_limits, payload.share_location)
+            .await
+            .map_err(image_error)?;
+        let issues = prepared.stats.map(|stats| state.photo_quality.issues(&stats)).unwrap_or_default();
+        if !issues.is_empty() {
+            return Ok(Json(retake_photo(&state, payload, issues).await).into_response());
+        }
+        payload.photo_context = prepared.metadata.as_ref().and_then(PhotoContext::from_metadata);
+        payload.image = Some(Photo::Encoded(prepared.data_url));
+    }
+
+    if !payload.async_job {
+        return answer_query(&state, payload, None).await.map(|r| Json(r).into_response());
+    }
+
+    // Run the pipeline in the background and hand back a job ID immediately
+    let (job_id, tracker) = state.jobs.create().await;
+    let task_state = state.clone();
+    let task_job_id = job_id.clone();
+    let task = tokio::spawn(async move {
+        let outcome = match answer_query(&task_state, payload, Some(tracker)).await {
+            Ok(response) => serde_json::to_value(response).map_err(|e| e.to_string()),
+            Err((_, Json(e))) => Err(e.error),
+        };
+        task_state.jobs.finish(&task_job_id, outcome).await;
+    });
+    state.jobs.attach_task(&job_id, task.abort_handle()).await;
+
+    info!("Queued chat job {}", job_id);
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(JobAccepted {
+            status_url: format!("/api/jobs/{}", job_id),
+            job_id,
+            status: JobStatus::Pending,
+        }),
+    ).into_response())
+}
+
+/// Reply to a query whose photo failed the quality check with a retake request, without
+/// spending a vision prediction on it
+async fn retake_photo(state: &AppState, payload: ChatRequest, issues: Vec<QualityIssue>) -> ChatResponse {
+    let user_lang = payload.language.unwrap_or_else(|| "en".to_string());
+    let session_id = state.sessions.resume(payload.session_id.as_deref()).await;
+    info!("Photo failed quality check ({:?}); asking for a retake", issues);
+    state.metrics.chat_request();
+
+    // Not an answer, so it stays out of the history; the retaken photo asks again
+    let answer = image_quality::retake_message(&issues, &user_lang);
+    ChatResponse {
+        answer,
+        sources: Vec::new(),
+        citations: Vec::new(),
+        confidence: "low".to_string(),
+        confidence_score: 0.0,
+        unverified_figures: Vec::new(),
+        detected_language: translator::detect_language(&payload.query),
+        degraded: false,
+        fallback_reason: None,
+        answered_by: image_quality::PHOTO_CHECK.to_string(),
+        session_id,
+        rewritten_query: None,
+        cached: false,
+        tool_calls: Vec::new(),
+        photo_issues: issues,
+        photo_context: None,
+        transcript: payload.transcribed.then(|| payload.query.trim().to_string()),
+    }
+}
+
+/// Record a turn in the session. Degraded answers are left out so later turns aren't built
+/// on a knowledge-base extract the model never wrote.
+async fn remember(state: &AppState, session_id: &str, query: &str, response: &ChatResponse) {
+    if response.degraded {
+        return;
+    }
+    state.sessions.append(session_id, vec![
+        Turn::new(Role::User, query),
+        Turn::new(Role::Assistant, &response.answer),
+    ]).await;
+}
+
+/// Full RAG pipeline for one query
+pub async fn answer_query(
+    state: &AppState,
+    payload: ChatRequest,
+    tracker: Option<Arc<PredictionTracker>>,
+) -> Result<ChatResponse, ApiError> {
+    let original_query = payload.query.trim().to_string();
+    let transcribed = payload.transcribed;
+    let transcript = transcribed.then(|| original_query.clone());
+    let user_lang = payload.language.unwrap_or_else(|| "en".to_string());
+    let session_id = state.sessions.resume(payload.session_id.as_deref()).await;
+    
+    info!("Received query: {} in language: {} (session {})", logger::query_text(&original_query, transcribed), user_lang, session_id);
+    state.metrics.chat_request();
+
     // Step 1: Detect language and translate to English if needed
-    let detected_lang = translator::detect_language(&original_query);
+    let (detected_lang, language_certainty) = translator::detect_language_with_certainty(&original_query);
     let query_in_english = if detected_lang != "en" {
         translator::translate_to_english(&original_query, &detected_lang)
     } else {
         original_query.clone()
     };
 
-    info!("Detected language: {}, Query in English: '{}'", detected_lang, query_in_english);
+    info!("Detected language: {}, Query in English: {}", detected_lang, logger::query_text(&query_in_english, transcribed));
 
-    // Step 2: Retrieve relevant context from knowledge base
-    let (context_docs, sources) = retriever::retrieve(&query_in_english).await;
-    let context = context_docs.join("\n\n");
+    // Step 2: Retrieve relevant context from knowledge base, using a standalone
+    // rewrite of follow-ups so "and how much water?" still finds the wheat documents
+    let history = state.sessions.window(&session_id, state.sessions.history_tokens).await;
+    let rewrite = rewriter::standalone_query(state, &query_in_english, &history, transcribed).await;
+
+    // Answers depend on the knowledge base revision, the question, language and mode; image
+    // queries and custom sampling always go to the model
+    let cacheable = state.response_cache.is_enabled() && payload.image.is_none() && payload.params.is_empty();
+    let cache_scope = format!("{:016x}|{}|{}", knowledge_base::revision(), user_lang, payload.mode.as_str());
+    if cacheable {
+        let hit = state.response_cache.get(&cache_scope, &rewrite.query).await;
+        state.metrics.cache_lookup(hit.is_some());
+        if let Some(cached) = hit {
+            info!("Serving cached answer for {}", logger::query_text(&rewrite.query, transcribed));
+            remember(state, &session_id, &original_query, &cached).await;
+            return Ok(ChatResponse {
+                detected_language: detected_lang,
+                session_id,
+                rewritten_query: (rewrite.method != "none").then_some(rewrite.query),
+                cached: true,
+                transcript,
+                ..cached
+            });
+        }
+    }
+
+    // A located photo ranks documents for its state and season higher
+    let boost_terms = payload.photo_context.as_ref().map(PhotoContext::boost_terms).unwrap_or_default();
+    let passages = retriever::retrieve_boosted(&rewrite.query, &boost_terms).await;
+    let sources: Vec<String> = passages.iter().map(|p| p.doc.source.clone()).collect();
+    let passage_texts: Vec<String> = passages.iter().enumerate().map(|(i, p)| p.cited_text(i + 1)).collect();
 
     info!("Retrieved {} relevant documents", sources.len());
 
     // Step 3: Generate response using IBM Granite (via RAG generator)
     // We pass the desired language directly to the LLM
-    let final_response = match generator::generate(&query_in_english, &context, payload.image, &user_lang).await {
-        Ok(response) => response,
+    let request = LlmRequest {
+        system_prompt: state.system_prompt.text().to_string(),
+        history,
+        query: match payload.photo_context.as_ref().map(PhotoContext::describe).filter(|d| !d.is_empty()) {
+            Some(context) => format!("{}\n\n{}", query_in_english, context),
+            None => query_in_english.clone(),
+        },
+        passages: passage_texts.clone(),
+        image: payload.image.and_then(Photo::into_encoded),
+        target_lang: user_lang.clone(),
+        mode: payload.mode,
+        params: state.generation.resolve(payload.mode, &payload.params),
+        output_schema: None,
+        tracker,
+    };
+    let generation = match generator::generate(state, request).await {
+        Ok(generation) => generation,
         Err(e) => {
-            error!("IBM Granite error: {:?}", e);
+            error!("IBM Granite error: {}", e);
             return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse { error: "Failed to generate response".to_string() })
+                e.status_code(),
+                Json(ErrorResponse { error: format!("Failed to generate response ({})", e.reason()) })
             ));
         }
     };
 
-    // Step 4: No post-translation needed, LLM generates in target language directly
+    // Step 4: No post-translation needed, LLM generates in target language directly.
+    // Check doses, prices and dates against the sources (tool results and the persona,
+    // which gives out contact details, count), then keep only citation markers that point
+    // at a retrieved passage. The knowledge-base fallback is passages and fixed text, so
+    // there is nothing to check.
+    let mut grounding_sources: Vec<&str> = passage_texts.iter().map(String::as_str).collect();
+    grounding_sources.extend([original_query.as_str(), query_in_english.as_str(), state.system_prompt.text()]);
+    grounding_sources.extend(generation.tool_calls.iter().filter_map(|call| call.output.as_deref()));
+    let grounding_mode = if generation.is_degraded() { GroundingMode::Off } else { GroundingMode::from_env() };
+    let grounding = grounding::verify(&generation.answer, &grounding_sources, grounding_mode, &user_lang);
+    if !grounding.unsupported.is_empty() {
+        warn!(
+            "{:.0}% of {} figures in answer supported; unsupported: {:?}",
+            grounding.supported_ratio() * 100.0, grounding.checked, grounding.unsupported
+        );
+    }
+    let cited = citations::resolve(&grounding.answer, &passages, generation.passages_used);
 
-    // Step 5: Calculate confidence based on context matches
-    let confidence = if sources.len() >= 3 {
-        "high"
-    } else if sources.len() >= 1 {
-        "medium"
-    } else {
-        "low"
-    };
+    // Step 5: Calibrated confidence from retrieval strength, grounding and language certainty
+    let scores: Vec<f32> = passages.iter().map(|p| p.score).collect();
+    let confidence = confidence::score(&confidence::Signals {
+        scores: &scores,
+        grounding: grounding.supported_ratio(),
+        language_certainty,
+        fallback: generation.is_degraded(),
+    });
 
-    Ok(Json(ChatResponse {
-        answer: final_response,
+    let response = ChatResponse {
+        degraded: generation.is_degraded(),
+        fallback_reason: generation.fallback_reason.map(str::to_string),
+        answered_by: generation.answered_by,
+        tool_calls: generation.tool_calls,
+        answer: cited.answer,
         sources,
-        confidence: confidence.to_string(),
+        citations: cited.citations,
+        confidence: confidence.band.to_string(),
+        confidence_score: confidence.value,
+        unverified_figures: grounding.unsupported,
         detected_language: detected_lang,
-    }))
+        session_id,
+        rewritten_query: (rewrite.method != "none").then(|| rewrite.query.clone()),
+        cached: false,
+        photo_issues: Vec::new(),
+        photo_context: payload.photo_context,
+        transcript,
+    };
+
+    remember(state, &response.session_id, &original_query, &response).await;
+    if response.degraded {
+        state.metrics.degraded_answer();
+    } else if cacheable && response.unverified_figures.is_empty() {
+        // Live prices and forecasts expire like the documents they replace
+        let used = |tool: &str| response.tool_calls.iter().any(|call| call.tool == tool && call.output.is_some());
+        let category = if used("mandi_price") {
+            Some("market_prices")
+        } else if used("weather_forecast") {
+            Some("weather")
+        } else {
+            passages.first().map(|p| p.doc.category.as_str())
+        };
+        state.response_cache.put(&cache_scope, &rewrite.query, category, response.clone()).await;
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::error::LlmError;
+    use crate::test_support::{mock_chain, state};
+    use std::time::Duration;
+
+    fn chat(body: serde_json::Value) -> ChatRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_answer_from_model_with_citation() {
+        let state = state(mock_chain("test", Vec::new()));
+        let response = answer_query(&state, chat(serde_json::json!({ "query": "When should I sow wheat?" })), None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.answered_by, "mock:test");
+        assert!(!response.degraded);
+        assert_eq!(response.citations.len(), 1);
+        assert_eq!(response.citations[0].source, response.sources[0]);
+        let turns = state.sessions.history(&response.session_id).await.unwrap();
+        assert_eq!(turns.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_session_ids_are_minted_by_the_server() {
+        let state = state(mock_chain("test", Vec::new()));
+        let request = serde_json::json!({ "query": "When should I sow wheat?", "session_id": "my-own-id" });
+        let first = answer_query(&state, chat(request), None).await.unwrap();
+        assert_ne!(first.session_id, "my-own-id");
+
+        let request = serde_json::json!({ "query": "and how much water?", "session_id": first.session_id });
+        let second = answer_query(&state, chat(request), None).await.unwrap();
+        assert_eq!(second.session_id, first.session_id);
+        assert_eq!(state.sessions.history(&first.session_id).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_transcribed_queries_stay_out_of_the_logs() {
+        use crate::services::response_cache::ResponseCache;
+        use crate::test_support::CapturedLogs;
+
+        let mut state = state(mock_chain("test", Vec::new()));
+        let hour = Duration::from_secs(3600);
+        state.response_cache = Arc::new(ResponseCache::new(hour, hour, hour, 1.0, 100));
+        answer_query(&state, chat(serde_json::json!({ "query": "When should I sow wheat?" })), None).await.unwrap();
+
+        let (logs, _guard) = CapturedLogs::start();
+        let mut spoken = chat(serde_json::json!({ "query": "When should I sow wheat?" }));
+        spoken.transcribed = true;
+        let first = answer_query(&state, spoken, None).await.unwrap();
+        assert!(first.cached);
+        let mut follow_up = chat(serde_json::json!({ "query": "and how much water?", "session_id": first.session_id }));
+        follow_up.transcribed = true;
+        let second = answer_query(&state, follow_up, None).await.unwrap();
+        assert!(second.rewritten_query.is_some());
+
+        let logs = logs.text();
+        assert!(logs.contains("Serving cached answer for <24 transcribed chars>"), "{}", logs);
+        assert!(logs.contains("Rewrote follow-up <19 transcribed chars>"), "{}", logs);
+        assert!(!logs.contains("wheat") && !logs.contains("water"), "{}", logs);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_falls_back_to_knowledge_base() {
+        let state = state(mock_chain("test", vec![Err(LlmError::Timeout { secs: 5 })]));
+        let response = answer_query(&state, chat(serde_json::json!({ "query": "wheat sowing time" })), None)
+            .await
+            .unwrap();
+
+        assert!(response.degraded);
+        assert_eq!(response.fallback_reason.as_deref(), Some("timeout"));
+        assert_eq!(response.answered_by, generator::EXTRACTIVE);
+        assert_eq!(response.confidence, "low");
+        assert!(state.sessions.history(&response.session_id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_config_error_is_not_masked() {
+        let state = state(mock_chain("test", vec![Err(LlmError::Config("REPLICATE_API_TOKEN not set".to_string()))]));
+        let Err((status, _)) = answer_query(&state, chat(serde_json::json!({ "query": "wheat" })), None).await else {
+            panic!("expected an error");
+        };
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_chat_handler_rejects_empty_query_and_queues_jobs() {
+        let state = state(mock_chain("test", Vec::new()));
+        let Err((status, _)) = chat_handler(State(state.clone()), Payload(chat(serde_json::json!({ "query": "  " })))).await else {
+            panic!("expected 400");
+        };
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let accepted = chat_handler(State(state.clone()), Payload(chat(serde_json::json!({ "query": "wheat", "async_job": true }))))
+            .await
+            .unwrap();
+        assert_eq!(accepted.status(), StatusCode::ACCEPTED);
+        let body = axum::body::to_bytes(accepted.into_body(), usize::MAX).await.unwrap();
+        let job_id = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["job_id"].as_str().unwrap().to_string();
+
+        for _ in 0..100 {
+            if state.jobs.get(&job_id).await.unwrap().status == JobStatus::Succeeded {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job did not finish");
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_photo_is_415() {
+        let gif = serde_json::json!({ "query": "what is this pest?", "image": "data:image/gif;base64,R0lGODlhAQABAAAAACw=" });
+        let Err((status, Json(body))) = chat_handler(State(state(mock_chain("test", Vec::new()))), Payload(chat(gif))).await else {
+            panic!("expected 415");
+        };
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(body.error.contains("JPEG, PNG or WebP"));
+    }
+
+    #[tokio::test]
+    async fn test_blurred_photo_gets_retake_request() {
+        let flat = image::RgbImage::from_pixel(640, 480, image::Rgb([70, 130, 60]));
+        let mut jpeg = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgb8(flat).write_to(&mut jpeg, image::ImageFormat::Jpeg).unwrap();
+        let image = crate::test_support::data_uri(&jpeg.into_inner());
+
+        let request = chat(serde_json::json!({ "query": "पत्तों पर क्या है?", "language": "hi", "image": image }));
+        let response = chat_handler(State(state(mock_chain("test", Vec::new()))), Payload(request)).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["answered_by"], "photo_check");
+        assert_eq!(body["photo_issues"], serde_json::json!(["blurry"]));
+        assert!(body["answer"].as_str().unwrap().starts_with("कृपया फोटो दोबारा लें।"));
+    }
 }
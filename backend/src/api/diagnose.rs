@@ -0,0 +1,331 @@
+use axum::{Json, extract::State, http::StatusCode};
+use serde::Deserialize;
+use tracing::{info, error, warn};
+
+use crate::api::chat::{image_error, ApiError, ErrorResponse};
+use crate::api::upload::{self, FromUpload, Payload, TempFile, Upload};
+use crate::api::voice;
+use crate::rag::diagnosis::{self, Diagnosis, DiagnosisStatus};
+use crate::rag::locality::PhotoContext;
+use crate::services::image_intake::{self, Photo};
+use crate::services::image_quality::RejectedPhoto;
+use crate::services::pest_reports::{PestReport, PestReportStore};
+use crate::state::AppState;
+
+#[derive(Deserialize)]
+pub struct DiagnoseRequest {
+    pub crop: String,
+    pub images: Vec<Photo>,         // base64 encoded photos, one leaf/fruit per photo
+    pub notes: Option<String>,      // what the farmer has noticed, in their own words
+    pub language: Option<String>,   // "en", "hi", "mr"
+    #[serde(default)]
+    pub share_location: bool,       // consent to use the photos' GPS and date for advice and pest reports
+    #[serde(skip)]
+    pub voice_note: Option<TempFile>, // multipart uploads only
+}
+
+impl FromUpload for DiagnoseRequest {
+    fn from_upload(mut upload: Upload) -> Result<Self, ApiError> {
+        let mut object = upload.to_json(&["share_location"])?;
+        // Filled in below; the form's photos are bytes, not JSON
+        object.insert("images".to_string(), serde_json::Value::Array(Vec::new()));
+        let mut request: DiagnoseRequest = upload::from_json(object)?;
+        request.images = std::mem::take(&mut upload.images).into_iter().map(Photo::Raw).collect();
+        request.voice_note = upload.voice_note.take();
+        Ok(request)
+    }
+}
+
+fn bad_request(message: &str) -> ApiError {
+    (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: message.to_string() }))
+}
+
+/// Log confident findings from a located diagnosis so outbreaks can be tracked by district
+async fn record_pest_reports(state: &AppState, crop: &str, diagnosis: &Diagnosis) {
+    let Some(context) = &diagnosis.photo_context else { return };
+    let (Some(latitude), Some(longitude)) = (context.latitude, context.longitude) else { return };
+    if diagnosis.status != DiagnosisStatus::Diagnosed {
+        return;
+    }
+    let min_confidence = diagnosis::min_confidence_from_env();
+    for finding in diagnosis.findings.iter().filter(|f| f.confidence >= min_confidence) {
+        info!("Pest report: {} on {} near {:?}", finding.name, crop, context.district);
+        state.pest_reports.record(PestReport {
+            crop: crop.to_string(),
+            name: finding.name.clone(),
+            kind: finding.kind.clone(),
+            confidence: finding.confidence,
+            latitude,
+            longitude,
+            district: context.district.clone(),
+            state: context.state.clone(),
+            season: context.season,
+            observed_on: context.taken_on.clone(),
+            reported_at: PestReportStore::now(),
+        }).await;
+    }
+}
+
+pub async fn diagnose_handler(
+    State(state): State<AppState>,
+    Payload(payload): Payload<DiagnoseRequest>,
+) -> Result<Json<Diagnosis>, ApiError> {
+    let crop = payload.crop.trim();
+    if crop.is_empty() {
+        return Err(bad_request("Crop cannot be empty"));
+    }
+    let images: Vec<Photo> = payload.images.into_iter().filter(|i| !i.is_empty()).collect();
+    if images.is_empty() {
+        return Err(bad_request("At least one photo is required"));
+    }
+    if images.len() > state.upload_limits.max_images {
+        return Err(bad_request(&format!("At most {} photos can be diagnosed at once", state.upload_limits.max_images)));
+    }
+    // Spoken notes are added to any typed ones; the photos are enough without them, so a
+    // note that can't be transcribed is left out rather than failing the diagnosis
+    let mut notes = payload.notes.as_deref().map(str::trim).unwrap_or_default().to_string();
+    let mut language = payload.language;
+    if let Some(note) = &payload.voice_note {
+        match voice::transcribe(&state, note, language.as_deref()).await {
+            Ok(heard) => {
+                notes = if notes.is_empty() { heard.text } else { format!("{} {}", notes, heard.text) };
+                language = language.or(heard.language);
+            }
+            Err((_, Json(e))) => warn!("Voice note not used ({}); using the typed notes only", e.error),
+        }
+    }
+    let lang = language.unwrap_or_else(|| "en".to_string());
+    info!("Diagnosing {} from {} photo(s)", crop, images.len());
+
+    // Blurred, dark or tiny photos are left out rather than paid for
+    let mut prepared = Vec::with_capacity(images.len());
+    let mut numbers = Vec::with_capacity(images.len());
+    let mut rejected = Vec::new();
+    let mut context = None;
+    for (i, image) in images.into_iter().enumerate() {
+        let photo = image_intake::prepare_blocking(image, state.image_limits, payload.share_location)
+            .await
+            .map_err(image_error)?;
+        let issues = photo.stats.map(|stats| state.photo_quality.issues(&stats)).unwrap_or_default();
+        if issues.is_empty() {
+            // The first usable photo that says where and when is taken for all of them
+            if context.is_none() {
+                context = photo.metadata.as_ref().and_then(PhotoContext::from_metadata);
+            }
+            prepared.push(photo.data_url);
+            numbers.push(i + 1);
+        } else {
+            info!("Photo {} failed quality check: {:?}", i + 1, issues);
+            rejected.push(RejectedPhoto { photo: i + 1, issues, error: None });
+        }
+    }
+    if prepared.is_empty() {
+        return Ok(Json(diagnosis::retake(rejected, &lang)));
+    }
+    let images = prepared;
+
+    let notes = Some(notes.as_str()).filter(|n| !n.is_empty());
+    match diagnosis::diagnose(&state, crop, &images, notes, context.as_ref(), &lang).await {
+        Ok(mut diagnosis) => {
+            record_pest_reports(&state, crop, &diagnosis).await;
+            // Photos the model failed on are numbered among the usable ones; renumber them
+            // in upload order alongside the ones that failed the quality check
+            for failed in diagnosis.rejected_photos.drain(..) {
+                rejected.push(RejectedPhoto { photo: numbers[failed.photo - 1], ..failed });
+            }
+            rejected.sort_by_key(|r| r.photo);
+            Ok(Json(Diagnosis { rejected_photos: rejected, ..diagnosis }))
+        }
+        Err(e) => {
+            error!("Diagnosis error: {}", e);
+            Err((
+                e.status_code(),
+                Json(ErrorResponse { error: format!("Failed to diagnose ({})", e.reason()) })
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::llm_chain::LlmChain;
+    use crate::test_support::{data_uri, leaf_jpeg, mock_chain, replies};
+    use std::sync::Arc;
+
+    fn state(vision_script: &[&str]) -> AppState {
+        let mut state = crate::test_support::state(LlmChain::new(Vec::new()));
+        state.vision_chain = Arc::new(mock_chain("vision", replies(vision_script)));
+        state
+    }
+
+    fn photo_with(light: f32) -> Photo {
+        Photo::Encoded(data_uri(&leaf_jpeg(light)))
+    }
+
+    fn photo() -> Photo {
+        photo_with(1.0)
+    }
+
+    fn request(images: usize) -> DiagnoseRequest {
+        DiagnoseRequest {
+            crop: "tomato".to_string(),
+            images: vec![photo(); images],
+            notes: Some("Brown spots on lower leaves".to_string()),
+            language: None,
+            share_location: false,
+            voice_note: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_diagnosis_from_two_photos() {
+        let first = r#"{"healthy": false, "candidates": [{"name": "Early blight", "kind": "disease", "confidence": 0.8, "symptoms": ["concentric rings"]}]}"#;
+        let second = r#"{"candidates": [{"name": "Early Blight", "kind": "disease", "confidence": 0.9, "symptoms": ["yellow halo"]}]}"#;
+        let Json(diagnosis) = diagnose_handler(State(state(&[first, second])), Payload(request(2))).await.unwrap();
+
+        assert_eq!(diagnosis.status, DiagnosisStatus::Diagnosed);
+        assert_eq!(diagnosis.answered_by, "mock:vision");
+        assert_eq!(diagnosis.findings.len(), 1);
+        assert_eq!(diagnosis.findings[0].confidence, 0.9);
+        assert_eq!(diagnosis.findings[0].kb_title.as_deref(), Some("Early Blight in Tomato and Potato"));
+        assert!(diagnosis.findings[0].controls.iter().any(|c| c.contains("Mancozeb")));
+    }
+
+    #[tokio::test]
+    async fn test_failed_photo_is_reported_and_the_others_kept() {
+        use crate::services::error::LlmError;
+
+        let reply = r#"{"candidates": [{"name": "Early blight", "kind": "disease", "confidence": 0.8, "symptoms": []}]}"#;
+        let with_vision = |script| {
+            let mut state = state(&[]);
+            state.vision_chain = Arc::new(mock_chain("vision", script));
+            state
+        };
+        let state = with_vision(vec![Ok(reply.to_string()), Err(LlmError::Timeout { secs: 5 })]);
+        let mut mixed = request(3);
+        mixed.images[0] = photo_with(0.1);
+        let Json(diagnosis) = diagnose_handler(State(state), Payload(mixed)).await.unwrap();
+
+        assert_eq!(diagnosis.status, DiagnosisStatus::Diagnosed);
+        assert_eq!(diagnosis.images_analyzed, 1);
+        assert_eq!(diagnosis.findings[0].name, "Early blight");
+        let rejected: Vec<(usize, Option<&str>)> =
+            diagnosis.rejected_photos.iter().map(|r| (r.photo, r.error.as_deref())).collect();
+        assert_eq!(rejected, vec![(1, None), (3, Some("timeout"))]);
+
+        let state = with_vision(vec![Err(LlmError::Timeout { secs: 5 }), Err(LlmError::Timeout { secs: 5 })]);
+        let (status, _) = diagnose_handler(State(state), Payload(request(2))).await.err().unwrap();
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_photo_count_is_checked() {
+        let (status, _) = diagnose_handler(State(state(&[])), Payload(request(0))).await.err().unwrap();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, Json(body)) = diagnose_handler(State(state(&[])), Payload(request(5))).await.err().unwrap();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body.error, "At most 4 photos can be diagnosed at once");
+    }
+
+    #[tokio::test]
+    async fn test_poor_photos_are_not_sent_upstream() {
+        use crate::services::image_quality::QualityIssue;
+
+        let reply = r#"{"candidates": [{"name": "Early blight", "kind": "disease", "confidence": 0.8, "symptoms": []}]}"#;
+        let mut mixed = request(2);
+        mixed.images[0] = photo_with(0.1);
+        let Json(diagnosis) = diagnose_handler(State(state(&[reply])), Payload(mixed)).await.unwrap();
+        assert_eq!(diagnosis.images_analyzed, 1);
+        assert_eq!(diagnosis.rejected_photos[0].photo, 1);
+        assert_eq!(diagnosis.rejected_photos[0].issues, vec![QualityIssue::TooDark]);
+
+        let mut dark = request(1);
+        dark.images[0] = photo_with(0.1);
+        dark.language = Some("mr".to_string());
+        let Json(diagnosis) = diagnose_handler(State(state(&[reply])), Payload(dark)).await.unwrap();
+        assert_eq!(diagnosis.status, DiagnosisStatus::RetakePhoto);
+        assert_eq!(diagnosis.answered_by, "photo_check");
+        assert!(diagnosis.clarifying_questions[0].contains("दिवसाच्या प्रकाशात"));
+    }
+
+    #[tokio::test]
+    async fn test_shared_location_is_used_and_reported() {
+        use crate::rag::locality::Season;
+        use crate::services::photo_metadata::tests::with_gps;
+
+        let located = Photo::Encoded(data_uri(&with_gps(&leaf_jpeg(1.0), 20.01, 73.75, "2024:08:02 10:15:00")));
+        let reply = r#"{"candidates": [{"name": "Early blight", "kind": "disease", "confidence": 0.8, "symptoms": []}]}"#;
+
+        let state = state(&[reply, reply]);
+        let mut private = request(1);
+        private.images[0] = located.clone();
+        let Json(diagnosis) = diagnose_handler(State(state.clone()), Payload(private)).await.unwrap();
+        assert!(diagnosis.photo_context.is_none());
+        assert!(state.pest_reports.summary(None).await.is_empty());
+
+        let mut shared = request(1);
+        shared.images[0] = located;
+        shared.share_location = true;
+        let Json(diagnosis) = diagnose_handler(State(state.clone()), Payload(shared)).await.unwrap();
+        let context = diagnosis.photo_context.unwrap();
+        assert_eq!(context.district.as_deref(), Some("Nashik"));
+        assert_eq!(context.season, Some(Season::Kharif));
+
+        let reports = state.pest_reports.summary(Some("Nashik")).await;
+        assert_eq!(reports.len(), 1);
+        assert_eq!((reports[0].name.as_str(), reports[0].reports), ("Early blight", 1));
+        assert_eq!(reports[0].season, Some(Season::Kharif));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_photo_gives_no_location() {
+        use crate::services::photo_metadata::tests::with_gps;
+
+        let reply = r#"{"candidates": [{"name": "Early blight", "kind": "disease", "confidence": 0.8, "symptoms": []}]}"#;
+        let state = state(&[reply]);
+        let mut request = request(2);
+        request.images[0] = Photo::Encoded(data_uri(&with_gps(&leaf_jpeg(0.1), 20.01, 73.75, "2024:08:02 10:15:00")));
+        request.share_location = true;
+        let Json(diagnosis) = diagnose_handler(State(state.clone()), Payload(request)).await.unwrap();
+        assert_eq!(diagnosis.rejected_photos.len(), 1);
+        assert!(diagnosis.photo_context.is_none());
+        assert!(state.pest_reports.summary(None).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_diagnosis_from_form() {
+        use crate::test_support::{form, form_request};
+        use axum::extract::FromRequest;
+
+        let reply = r#"{"candidates": [{"name": "Blast", "kind": "disease", "confidence": 0.85, "symptoms": ["spindle-shaped spots"]}]}"#;
+        let state = state(&[reply, reply]);
+        let jpeg = leaf_jpeg(1.0);
+        let body = form(&[
+            ("crop", None, b"rice"),
+            ("image", Some("1.jpg"), &jpeg),
+            ("image", Some("2.jpg"), &jpeg),
+        ]);
+        let payload = Payload::<DiagnoseRequest>::from_request(form_request(body), &state).await.ok().unwrap();
+        let Json(diagnosis) = diagnose_handler(State(state), payload).await.unwrap();
+
+        assert_eq!(diagnosis.images_analyzed, 2);
+        assert_eq!(diagnosis.findings[0].kb_title.as_deref(), Some("Blast in Rice"));
+    }
+
+    #[tokio::test]
+    async fn test_voice_note_without_speech_backend_is_skipped() {
+        use crate::test_support::{form, form_request};
+        use axum::extract::FromRequest;
+
+        let reply = r#"{"candidates": [{"name": "Blast", "kind": "disease", "confidence": 0.85, "symptoms": []}]}"#;
+        let state = state(&[reply]);
+        assert!(state.speech.is_none());
+        let jpeg = leaf_jpeg(1.0);
+        let body = form(&[("crop", None, b"rice"), ("image", Some("1.jpg"), &jpeg), ("voice", Some("note.ogg"), b"OggS\0\x02")]);
+        let payload = Payload::<DiagnoseRequest>::from_request(form_request(body), &state).await.ok().unwrap();
+        let Json(diagnosis) = diagnose_handler(State(state), payload).await.unwrap();
+
+        assert_eq!(diagnosis.status, DiagnosisStatus::Diagnosed);
+    }
+}
@@ -0,0 +1,40 @@
+use axum::{Json, extract::{Path, State}, http::StatusCode};
+use tracing::{info, warn};
+
+use crate::api::chat::{ApiError, ErrorResponse};
+use crate::services::ibm_granite;
+use crate::services::jobs::{JobStatus, JobView};
+use crate::state::AppState;
+
+fn not_found(id: &str) -> ApiError {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse { error: format!("Job {} not found or expired", id) })
+    )
+}
+
+pub async fn get_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<JobView>, ApiError> {
+    state.jobs.get(&id).await.map(Json).ok_or_else(|| not_found(&id))
+}
+
+/// Cancel a pending job and any upstream prediction it started
+pub async fn cancel_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<JobView>, ApiError> {
+    let (view, tracker) = state.jobs.cancel(&id).await.ok_or_else(|| not_found(&id))?;
+
+    if view.status == JobStatus::Canceled {
+        for prediction in tracker.cancel() {
+            info!("Canceling upstream prediction {} for job {}", prediction.id, id);
+            if let Err(e) = ibm_granite::cancel_prediction(&state.http, &prediction).await {
+                warn!("Failed to cancel prediction {}: {}", prediction.id, e);
+            }
+        }
+    }
+
+    Ok(Json(view))
+}
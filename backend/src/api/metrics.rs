@@ -0,0 +1,8 @@
+use axum::{Json, extract::State};
+
+use crate::services::metrics::MetricsSnapshot;
+use crate::state::AppState;
+
+pub async fn metrics_handler(State(state): State<AppState>) -> Json<MetricsSnapshot> {
+    Json(state.metrics.snapshot())
+}
@@ -1,11 +1,35 @@
 use axum::Router;
-use axum::routing::post;
+use axum::extract::DefaultBodyLimit;
+use axum::routing::{get, post};
+
+use crate::state::AppState;
 
 pub mod chat;
+pub mod diagnose;
+pub mod jobs;
+pub mod metrics;
+pub mod pest_reports;
+pub mod sessions;
+pub mod structured;
 pub mod translate;
+pub mod upload;
+pub mod voice;
+pub mod webhooks;
 
-pub fn router() -> Router {
+/// `upload_limit` is the largest body accepted by the routes that take photos or voice notes;
+/// the rest keep axum's 2 MB default
+pub fn router(upload_limit: usize) -> Router<AppState> {
+    // Base64 photos are far bigger than 2 MB; they are size-checked per photo
+    let uploads = DefaultBodyLimit::max(upload_limit);
     Router::new()
-        .route("/chat", post(chat::chat_handler))
+        .route("/chat", post(chat::chat_handler).layer(uploads))
+        .route("/diagnose", post(diagnose::diagnose_handler).layer(uploads))
+        .route("/metrics", get(metrics::metrics_handler))
+        .route("/pest-reports", get(pest_reports::list_pest_reports_handler))
+        .route("/jobs/:id", get(jobs::get_job_handler).delete(jobs::cancel_job_handler))
+        .route("/sessions/:id", get(sessions::get_session_handler).delete(sessions::delete_session_handler))
+        .route("/structured", post(structured::structured_handler))
         .route("/translate", post(translate::translate_handler))
+        .route("/voice", post(voice::voice_handler).layer(uploads))
+        .route("/webhooks/replicate", post(webhooks::replicate_webhook_handler))
 }
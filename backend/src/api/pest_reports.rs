@@ -0,0 +1,23 @@
+use axum::{Json, extract::{Query, State}};
+use serde::Deserialize;
+
+use crate::services::pest_reports::PestSummary;
+use crate::state::AppState;
+
+#[derive(Deserialize)]
+pub struct PestReportQuery {
+    pub district: Option<String>,
+    pub limit: Option<usize>, // default 100, at most 1000
+}
+
+/// Sightings from photo diagnoses counted per district, crop, pest and season, most reported
+/// first; individual reports and their positions are not shared
+pub async fn list_pest_reports_handler(
+    State(state): State<AppState>,
+    Query(query): Query<PestReportQuery>,
+) -> Json<Vec<PestSummary>> {
+    let limit = query.limit.unwrap_or(100).min(1000);
+    let mut summary = state.pest_reports.summary(query.district.as_deref()).await;
+    summary.truncate(limit);
+    Json(summary)
+}
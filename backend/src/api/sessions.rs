@@ -0,0 +1,38 @@
+use axum::{Json, extract::{Path, State}, http::StatusCode};
+use serde::Serialize;
+
+use crate::api::chat::{ApiError, ErrorResponse};
+use crate::services::sessions::Turn;
+use crate::state::AppState;
+
+#[derive(Serialize)]
+pub struct SessionHistory {
+    pub session_id: String,
+    pub turns: Vec<Turn>,
+}
+
+fn not_found(id: &str) -> ApiError {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse { error: format!("Session {} not found or expired", id) })
+    )
+}
+
+pub async fn get_session_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<SessionHistory>, ApiError> {
+    let turns = state.sessions.history(&id).await.ok_or_else(|| not_found(&id))?;
+    Ok(Json(SessionHistory { session_id: id, turns }))
+}
+
+pub async fn delete_session_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if state.sessions.delete(&id).await {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found(&id))
+    }
+}
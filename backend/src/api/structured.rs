@@ -0,0 +1,203 @@
+use axum::{Json, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, error};
+
+use crate::api::chat::{ApiError, ErrorResponse};
+use crate::rag::{citations, confidence, grounding, retriever};
+use crate::rag::citations::Citation;
+use crate::rag::grounding::GroundingMode;
+use crate::rag::structured::{self, CropPlan, FertilizerSchedule, OutputSchema};
+use crate::services::error::LlmError;
+use crate::services::generation::{GenerationOverrides, ResponseMode};
+use crate::services::llm::LlmRequest;
+use crate::services::translator;
+use crate::state::AppState;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKind {
+    CropPlan,
+    FertilizerSchedule,
+}
+
+impl OutputKind {
+    /// Name as used in requests
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OutputKind::CropPlan => "crop_plan",
+            OutputKind::FertilizerSchedule => "fertilizer_schedule",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StructuredRequest {
+    pub kind: OutputKind,
+    pub query: String,
+    pub language: Option<String>, // language of free-text values; keys stay English
+}
+
+#[derive(Serialize)]
+pub struct StructuredResponse {
+    pub kind: OutputKind,
+    pub data: Value,                  // a `CropPlan` or `FertilizerSchedule`, validated against its schema
+    pub sources: Vec<String>,
+    pub answered_by: String,
+    pub attempts: u32,                // model calls it took to get valid JSON
+    pub citations: Vec<Citation>,     // passages cited by markers in the free-text values
+    pub confidence: String,           // "low", "medium" or "high", as for chat answers
+    pub confidence_score: f32,
+    pub unverified_figures: Vec<String>, // doses and dates not found in the sources
+}
+
+/// A validated result in JSON form, with the claims it makes
+struct Generated {
+    data: Value,
+    claims: String,
+    answered_by: String,
+    attempts: u32,
+    passages_used: usize,
+}
+
+/// Run the structured generator for `T` and hand back its JSON form
+async fn generate<T: OutputSchema + Serialize>(state: &AppState, request: &LlmRequest) -> Result<Generated, LlmError> {
+    let result = structured::generate::<T>(&state.text_chain, &state.http, request, structured::max_attempts_from_env()).await?;
+    let data = serde_json::to_value(&result.value).map_err(|e| LlmError::InvalidOutput {
+        attempts: result.attempts,
+        message: e.to_string(),
+    })?;
+    Ok(Generated {
+        data,
+        claims: result.value.claims().join("\n"),
+        answered_by: result.answered_by,
+        attempts: result.attempts,
+        passages_used: result.passages_used,
+    })
+}
+
+/// Machine-readable answers (crop plans, fertilizer schedules) from the same retrieval and
+/// model chain as `/api/chat`, for dashboard panels
+pub async fn structured_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<StructuredRequest>,
+) -> Result<Json<StructuredResponse>, ApiError> {
+    if payload.query.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "Query cannot be empty".to_string() })
+        ));
+    }
+    let user_lang = payload.language.unwrap_or_else(|| "en".to_string());
+    info!("Structured {} request: '{}'", payload.kind.as_str(), payload.query);
+
+    let (detected_lang, language_certainty) = translator::detect_language_with_certainty(&payload.query);
+    let query = if detected_lang != "en" {
+        translator::translate_to_english(&payload.query, &detected_lang)
+    } else {
+        payload.query.trim().to_string()
+    };
+    let passages = retriever::retrieve(&query).await;
+    let passage_texts: Vec<String> = passages.iter().enumerate().map(|(i, p)| p.cited_text(i + 1)).collect();
+
+    // JSON is verbose, so allow the detailed token budget but keep sampling conservative;
+    // the mode itself stays normal so no prose formatting instruction is added
+    let overrides = GenerationOverrides { temperature: Some(0.2), ..GenerationOverrides::default() };
+    let request = LlmRequest {
+        system_prompt: state.system_prompt.text().to_string(),
+        history: Vec::new(),
+        query: query.clone(),
+        passages: passage_texts.clone(),
+        image: None,
+        target_lang: user_lang,
+        mode: ResponseMode::Normal,
+        params: state.generation.resolve(ResponseMode::Detailed, &overrides),
+        output_schema: None,
+        tracker: None,
+    };
+
+    let result = match payload.kind {
+        OutputKind::CropPlan => generate::<CropPlan>(&state, &request).await,
+        OutputKind::FertilizerSchedule => generate::<FertilizerSchedule>(&state, &request).await,
+    };
+    let generated = match result {
+        Ok(generated) => generated,
+        Err(e) => {
+            error!("Structured generation error: {}", e);
+            return Err((
+                e.status_code(),
+                Json(ErrorResponse { error: format!("Failed to generate {} ({})", payload.kind.as_str(), e.reason()) })
+            ));
+        }
+    };
+
+    // The same checks as a chat answer, run on what the result claims. Doses can't be cut
+    // out of a schedule, so unsupported figures are reported whatever `GROUNDING_MODE` says.
+    let mode = match GroundingMode::from_env() {
+        GroundingMode::Off => GroundingMode::Off,
+        _ => GroundingMode::Flag,
+    };
+    let mut grounding_sources: Vec<&str> = passage_texts.iter().map(String::as_str).collect();
+    grounding_sources.extend([payload.query.as_str(), query.as_str()]);
+    let grounding = grounding::verify(&generated.claims, &grounding_sources, mode, &request.target_lang);
+    let cited = citations::resolve(&generated.claims, &passages, generated.passages_used);
+    let scores: Vec<f32> = passages.iter().map(|p| p.score).collect();
+    let confidence = confidence::score(&confidence::Signals {
+        scores: &scores,
+        grounding: grounding.supported_ratio(),
+        language_certainty,
+        fallback: false,
+    });
+
+    Ok(Json(StructuredResponse {
+        kind: payload.kind,
+        data: generated.data,
+        sources: passages.iter().map(|p| p.doc.source.clone()).collect(),
+        answered_by: generated.answered_by,
+        attempts: generated.attempts,
+        citations: cited.citations,
+        confidence: confidence.band.to_string(),
+        confidence_score: confidence.value,
+        unverified_figures: grounding.unsupported,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{mock_chain, replies, state};
+
+    fn request(kind: &str) -> StructuredRequest {
+        serde_json::from_value(serde_json::json!({ "kind": kind, "query": "Plan for wheat this rabi season" })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_crop_plan_is_returned_as_data() {
+        let plan = r#"{"crop": "wheat", "season": "rabi", "stages": [{"stage": "Sowing", "timing": "October-November", "tasks": ["Seed at 100 kg/ha"]}]}"#;
+        let Json(response) = structured_handler(State(state(mock_chain("json", replies(&[plan])))), Json(request("crop_plan"))).await.unwrap();
+        assert_eq!(response.attempts, 1);
+        assert_eq!(response.data["stages"][0]["timing"], "October-November");
+        assert!(!response.sources.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_doses_are_reported() {
+        let schedule = r#"{"crop": "wheat", "area_unit": "hectare", "applications": [{"timing": "At sowing [1]", "fertilizer": "Urea", "quantity_kg": 900}]}"#;
+        let Json(response) = structured_handler(State(state(mock_chain("json", replies(&[schedule])))), Json(request("fertilizer_schedule")))
+            .await
+            .unwrap();
+        assert_eq!(response.unverified_figures, vec!["900 kg/hectare"]);
+        assert_eq!(response.confidence, "low");
+        assert_eq!(response.citations.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_persistently_invalid_output_is_502() {
+        let (status, Json(body)) = structured_handler(State(state(mock_chain("json", Vec::new()))), Json(request("fertilizer_schedule")))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body.error, "Failed to generate fertilizer_schedule (invalid_output)");
+    }
+}
@@ -1,7 +1,9 @@
-use axum::{Json, http::StatusCode};
+use axum::{Json, extract::State, http::StatusCode};
 use serde::{Deserialize, Serialize};
 use tracing::{info, error};
-use crate::services::ibm_granite;
+use crate::services::generation::{GenerationParams, ResponseMode};
+use crate::services::llm::LlmRequest;
+use crate::state::AppState;
 
 #[derive(Deserialize)]
 pub struct TranslateRequest {
@@ -20,10 +22,11 @@ pub struct ErrorResponse {
 }
 
 pub async fn translate_handler(
+    State(state): State<AppState>,
     Json(payload): Json<TranslateRequest>,
 ) -> Result<Json<TranslateResponse>, (StatusCode, Json<ErrorResponse>)> {
     
-    info!("Translating text to: {}", payload.target_lang);
+    info!("Translating text to: {} (prompt {})", payload.target_lang, state.system_prompt.label());
 
     // We can reuse the generate_response function but with empty context
     // and a specific prompt to just translate
@@ -39,15 +42,27 @@ pub async fn translate_handler(
 
     // We leverage the generalized model for translation
     // Passing None for image and the target lang
-    let result = ibm_granite::generate_response(&prompt, "", None, &payload.target_lang).await;
+    let request = LlmRequest {
+        system_prompt: state.system_prompt.text().to_string(),
+        history: Vec::new(),
+        query: prompt,
+        passages: Vec::new(),
+        image: None,
+        target_lang: payload.target_lang.clone(),
+        mode: ResponseMode::Normal,
+        params: GenerationParams::default(),
+        output_schema: None,
+        tracker: None,
+    };
+    let result = state.text_chain.generate(&state.http, &request).await;
 
     match result {
-        Ok(translated) => Ok(Json(TranslateResponse { translated_text: translated })),
+        Ok(translated) => Ok(Json(TranslateResponse { translated_text: translated.text })),
         Err(e) => {
-            error!("Translation error: {:?}", e);
+            error!("Translation error: {}", e);
             Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse { error: "Translation failed".to_string() })
+                e.status_code(),
+                Json(ErrorResponse { error: format!("Translation failed ({})", e.reason()) })
             ))
         }
     }
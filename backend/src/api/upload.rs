@@ -0,0 +1,342 @@
+//! `multipart/form-data` uploads.
+//! The chat and diagnosis endpoints take either their JSON body or a form with the same
+//! text fields, photo files under `image` (repeatable) and an optional `voice` note. Every
+//! part is size-capped as it streams in, so an oversized upload is refused before it is held
+//! in full. Photos are kept in memory and go to the image intake as bytes; the voice note is
+//! written to temp storage until it is transcribed.
+
+use axum::async_trait;
+use axum::extract::{FromRequest, Multipart, Request};
+use axum::extract::multipart::Field;
+use axum::http::{header, StatusCode};
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::api::chat::{ApiError, ErrorResponse};
+use crate::services::image_intake::ImageLimits;
+use crate::state::AppState;
+
+/// Longest accepted text field
+const MAX_FIELD_BYTES: usize = 16 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct UploadLimits {
+    /// Largest photo part, the same cap as a base64 photo
+    pub max_image_bytes: usize,
+    /// Most photos in one request, JSON or form; every photo costs a vision prediction
+    pub max_images: usize,
+    pub max_voice_bytes: usize,
+    /// Where parts are written while the rest of the form arrives
+    pub dir: PathBuf,
+}
+
+impl UploadLimits {
+    /// `DIAGNOSE_MAX_IMAGES` (4), `VOICE_MAX_BYTES` (5 MB) and `UPLOAD_DIR` (the system temp
+    /// dir); photos share `IMAGE_MAX_UPLOAD_BYTES`
+    pub fn from_env(images: &ImageLimits) -> Self {
+        let defaults = Self::new(images);
+        let var = |name: &str, default: usize| env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+        Self {
+            max_images: var("DIAGNOSE_MAX_IMAGES", defaults.max_images),
+            max_voice_bytes: var("VOICE_MAX_BYTES", defaults.max_voice_bytes),
+            dir: env::var("UPLOAD_DIR").map(PathBuf::from).unwrap_or(defaults.dir),
+            ..defaults
+        }
+    }
+
+    /// The defaults, with photos capped like `images`
+    pub fn new(images: &ImageLimits) -> Self {
+        Self {
+            max_image_bytes: images.max_upload_bytes,
+            max_images: 4,
+            max_voice_bytes: 5 * 1024 * 1024,
+            dir: env::temp_dir(),
+        }
+    }
+}
+
+/// An uploaded part on disk, deleted when dropped
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    /// Sniffed from the content, not taken from the client
+    pub content_type: &'static str,
+    pub bytes: usize,
+}
+
+impl TempFile {
+    pub async fn read(&self) -> std::io::Result<Vec<u8>> {
+        tokio::fs::read(&self.path).await
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        let remove = move || {
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("Could not remove upload {}: {}", path.display(), e);
+            }
+        };
+        // Deleting can block on a slow disk, so on the runtime it goes to the blocking pool
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(remove)),
+            Err(_) => remove(),
+        }
+    }
+}
+
+/// A received form: text fields, photo bytes and the voice note still on disk
+pub struct Upload {
+    pub fields: HashMap<String, String>,
+    pub images: Vec<Vec<u8>>,
+    pub voice_note: Option<TempFile>,
+}
+
+impl Upload {
+    /// The text fields as a JSON object; `json_fields` hold JSON (booleans, objects) rather than text
+    pub fn to_json(&self, json_fields: &[&str]) -> Result<Map<String, Value>, ApiError> {
+        let mut object = Map::new();
+        for (name, value) in &self.fields {
+            let value = if json_fields.contains(&name.as_str()) {
+                serde_json::from_str(value).map_err(|e| bad_request(format!("Field '{}' is not valid JSON ({})", name, e)))?
+            } else {
+                Value::String(value.clone())
+            };
+            object.insert(name.clone(), value);
+        }
+        Ok(object)
+    }
+}
+
+/// A request type that can also be filled from a form
+pub trait FromUpload: Sized {
+    fn from_upload(upload: Upload) -> Result<Self, ApiError>;
+}
+
+/// Deserialize `object` into a request type, as a 400 on failure
+pub fn from_json<T: DeserializeOwned>(object: Map<String, Value>) -> Result<T, ApiError> {
+    serde_json::from_value(Value::Object(object)).map_err(|e| bad_request(format!("Invalid form: {}", e)))
+}
+
+/// A JSON body or a `multipart/form-data` form, whichever the client sent
+pub struct Payload<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned + FromUpload + Send> FromRequest<AppState> for Payload<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &AppState) -> Result<Self, Self::Rejection> {
+        let is_form = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("multipart/form-data"));
+        if !is_form {
+            let Json(value) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(|e| (e.status(), Json(ErrorResponse { error: e.body_text() })))?;
+            return Ok(Payload(value));
+        }
+        let multipart = Multipart::from_request(req, state)
+            .await
+            .map_err(|e| (e.status(), Json(ErrorResponse { error: e.body_text() })))?;
+        let upload = receive(multipart, &state.upload_limits).await?;
+        T::from_upload(upload).map(Payload)
+    }
+}
+
+fn bad_request(error: String) -> ApiError {
+    (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }))
+}
+
+fn too_large(what: &str, limit: usize) -> ApiError {
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(ErrorResponse { error: format!("{} is larger than {} bytes", what, limit) }))
+}
+
+fn form_error(e: axum::extract::multipart::MultipartError) -> ApiError {
+    (e.status(), Json(ErrorResponse { error: e.body_text() }))
+}
+
+/// Read every part of the form, writing files to `limits.dir` as they arrive
+pub async fn receive(mut multipart: Multipart, limits: &UploadLimits) -> Result<Upload, ApiError> {
+    let mut fields = HashMap::new();
+    let mut images = Vec::new();
+    let mut voice_note = None;
+
+    while let Some(mut field) = multipart.next_field().await.map_err(form_error)? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "image" => {
+                if images.len() >= limits.max_images {
+                    return Err(bad_request(format!("At most {} photos can be uploaded at once", limits.max_images)));
+                }
+                images.push(read(&mut field, limits.max_image_bytes, "Photo").await?);
+            }
+            "voice" => {
+                if voice_note.is_some() {
+                    return Err(bad_request("Only one voice note can be uploaded".to_string()));
+                }
+                let file = save(&mut field, limits, limits.max_voice_bytes, "Voice note").await?;
+                if !file.content_type.starts_with("audio/") {
+                    return Err((
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        Json(ErrorResponse { error: "Unsupported voice note; send Ogg/Opus or WAV audio".to_string() }),
+                    ));
+                }
+                voice_note = Some(file);
+            }
+            _ if field.file_name().is_some() => {
+                return Err(bad_request(format!("Unexpected file field '{}'; send photos as 'image' and audio as 'voice'", name)));
+            }
+            _ => {
+                let text = read(&mut field, MAX_FIELD_BYTES, &format!("Field '{}'", name)).await?;
+                let text = String::from_utf8(text).map_err(|_| bad_request(format!("Field '{}' is not UTF-8 text", name)))?;
+                fields.insert(name, text);
+            }
+        }
+    }
+
+    info!(
+        "Received form with {} field(s), {} photo(s){}",
+        fields.len(),
+        images.len(),
+        if voice_note.is_some() { " and a voice note" } else { "" }
+    );
+    Ok(Upload { fields, images, voice_note })
+}
+
+/// Read one part into memory, giving up as soon as it passes `limit`
+async fn read(field: &mut Field<'_>, limit: usize, what: &str) -> Result<Vec<u8>, ApiError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(form_error)? {
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large(what, limit));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Stream one part to disk, giving up as soon as it passes `limit`
+async fn save(field: &mut Field<'_>, limits: &UploadLimits, limit: usize, what: &str) -> Result<TempFile, ApiError> {
+    let path = limits.dir.join(format!("upload-{}", uuid::Uuid::new_v4()));
+    let mut out = tokio::fs::File::create(&path)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("Could not store upload: {}", e) })))?;
+    // Owns the path from here on, so every early return below deletes the partial file
+    let mut file = TempFile { path, content_type: "application/octet-stream", bytes: 0 };
+    let mut head = Vec::with_capacity(16);
+
+    while let Some(chunk) = field.chunk().await.map_err(form_error)? {
+        file.bytes += chunk.len();
+        if file.bytes > limit {
+            return Err(too_large(what, limit));
+        }
+        if head.len() < 16 {
+            head.extend(chunk.iter().take(16 - head.len()));
+        }
+        out.write_all(&chunk)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("Could not store upload: {}", e) })))?;
+    }
+    out.flush().await.ok();
+    file.content_type = sniff(&head);
+    Ok(file)
+}
+
+/// Audio type from the magic bytes
+fn sniff(head: &[u8]) -> &'static str {
+    if head.starts_with(b"OggS") {
+        "audio/ogg"
+    } else if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WAVE" {
+        "audio/wav"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::chat::ChatRequest;
+    use crate::services::image_intake::Photo;
+    use crate::services::llm_chain::LlmChain;
+    use crate::test_support::{form, form_request};
+
+    /// State writing uploads to a fresh directory, so tests can check what is left behind
+    fn state(name: &str) -> AppState {
+        let mut state = crate::test_support::state(LlmChain::new(Vec::new()));
+        state.upload_limits.dir = env::temp_dir().join(format!("kisan-upload-test-{}-{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&state.upload_limits.dir).unwrap();
+        state
+    }
+
+    fn leftovers(state: &AppState) -> usize {
+        std::fs::read_dir(&state.upload_limits.dir).unwrap().count()
+    }
+
+    /// Dropped files are removed on the blocking pool, so give it a moment
+    async fn leftovers_after_cleanup(state: &AppState) -> usize {
+        for _ in 0..100 {
+            if leftovers(state) == 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        leftovers(state)
+    }
+
+    #[tokio::test]
+    async fn test_form_becomes_chat_request() {
+        let state = state("chat");
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 1, 2, 3];
+        let body = form(&[
+            ("query", None, "Why are my tomato leaves curling?".as_bytes()),
+            ("language", None, b"hi"),
+            ("async_job", None, b"true"),
+            ("params", None, br#"{"temperature": 0.3}"#),
+            ("image", Some("leaf.jpg"), &jpeg),
+        ]);
+        let Payload(request) = Payload::<ChatRequest>::from_request(form_request(body), &state).await.ok().unwrap();
+
+        assert_eq!(request.query, "Why are my tomato leaves curling?");
+        assert_eq!(request.language.as_deref(), Some("hi"));
+        assert!(request.async_job);
+        assert_eq!(request.params.temperature, Some(0.3));
+        assert!(matches!(request.image, Some(Photo::Raw(bytes)) if bytes == jpeg));
+        assert_eq!(leftovers(&state), 0);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_part_is_413_and_removed() {
+        let mut state = state("large");
+        state.upload_limits.max_voice_bytes = 1024;
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wav.resize(4096, 0);
+        let body = form(&[("query", None, b"wheat"), ("voice", Some("note.wav"), &wav)]);
+
+        let (status, _) = Payload::<ChatRequest>::from_request(form_request(body), &state).await.err().unwrap();
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(leftovers_after_cleanup(&state).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_voice_note_is_kept_on_disk() {
+        let state = state("voice");
+        let body = form(&[("voice", Some("note.ogg"), b"OggS\0\x02 opus payload")]);
+        let Payload(request) = Payload::<ChatRequest>::from_request(form_request(body), &state).await.ok().unwrap();
+
+        let voice = request.voice_note.as_ref().unwrap();
+        assert_eq!(voice.content_type, "audio/ogg");
+        assert_eq!(leftovers(&state), 1);
+        drop(request);
+        assert_eq!(leftovers_after_cleanup(&state).await, 0);
+    }
+}
@@ -0,0 +1,129 @@
+use axum::{Json, extract::State, http::StatusCode, response::Response};
+use tracing::{debug, error};
+
+use crate::api::chat::{self, ApiError, ChatRequest, ErrorResponse};
+use crate::api::upload::{Payload, TempFile};
+use crate::services::error::LlmError;
+use crate::services::speech::{Audio, Transcript};
+use crate::state::AppState;
+
+/// Text and language of a voice note, from the configured speech-to-text backend
+pub async fn transcribe(state: &AppState, note: &TempFile, language: Option<&str>) -> Result<Transcript, ApiError> {
+    let Some(speech) = &state.speech else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse { error: "Voice notes are not enabled on this server (STT_BACKEND)".to_string() })
+        ));
+    };
+    let bytes = note.read().await.map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: format!("Voice note could not be read back: {}", e) }))
+    })?;
+    let audio = Audio { bytes: &bytes, content_type: note.content_type };
+    match speech.transcribe(&state.http, audio, language).await {
+        Ok(transcript) => {
+            // The farmer's own words stay out of the logs
+            debug!("{} heard {} chars ({:?})", speech.name(), transcript.text.chars().count(), transcript.language);
+            Ok(transcript)
+        }
+        Err(LlmError::EmptyOutput) => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse { error: "No speech was recognised in the voice note; please record it again".to_string() })
+        )),
+        Err(e) => {
+            error!("Transcription error: {}", e);
+            Err((
+                e.status_code(),
+                Json(ErrorResponse { error: format!("Failed to transcribe voice note ({})", e.reason()) })
+            ))
+        }
+    }
+}
+
+/// Ask by voice: a form with the recording under `voice` and the usual chat fields but no
+/// `query`. The transcript goes through the chat pipeline and comes back as `transcript` with
+/// the answer.
+pub async fn voice_handler(
+    State(state): State<AppState>,
+    Payload(payload): Payload<ChatRequest>,
+) -> Result<Response, ApiError> {
+    if payload.voice_note.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "Send the recording as a 'voice' file (Ogg/Opus or WAV)".to_string() })
+        ));
+    }
+    if !payload.query.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "Send either the recording or a typed query, not both".to_string() })
+        ));
+    }
+    chat::chat_handler(State(state), Payload(payload)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::speech::MockSpeechToText;
+    use crate::test_support::{form, form_request, mock_chain};
+    use axum::extract::FromRequest;
+    use std::sync::Arc;
+
+    fn state(transcript: Option<&str>) -> AppState {
+        let mut state = crate::test_support::state(mock_chain("test", Vec::new()));
+        state.speech = transcript.map(|t| Arc::new(MockSpeechToText::new(t)) as _);
+        state
+    }
+
+    async fn json(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn ask(state: AppState, parts: &[(&str, Option<&str>, &[u8])]) -> Result<Response, ApiError> {
+        let payload = Payload::<ChatRequest>::from_request(form_request(form(parts)), &state).await?;
+        voice_handler(State(state), payload).await
+    }
+
+    #[tokio::test]
+    async fn test_transcript_is_answered_and_returned() {
+        let clip = b"RIFF\x24\0\0\0WAVEfmt \x10\0\0\0";
+        let state = state(Some("गेहूं की बुवाई कब करें?"));
+        let response = ask(state, &[("language", None, b"hi"), ("voice", Some("q.wav"), clip)]).await.ok().unwrap();
+
+        let body = json(response).await;
+        assert_eq!(body["transcript"], "गेहूं की बुवाई कब करें?");
+        assert_eq!(body["answered_by"], "mock:test");
+        assert_eq!(body["detected_language"], "hi");
+    }
+
+    #[tokio::test]
+    async fn test_missing_clip_or_backend() {
+        let (status, _) = ask(state(Some("wheat")), &[("language", None, b"hi")]).await.err().unwrap();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = ask(state(None), &[("voice", Some("q.ogg"), b"OggS\0\x02")]).await.err().unwrap();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_typed_query_with_clip_is_refused() {
+        let parts: &[(&str, Option<&str>, &[u8])] = &[("query", None, b"wheat"), ("voice", Some("q.ogg"), b"OggS\0\x02")];
+        let (status, Json(body)) = ask(state(Some("rice")), parts).await.err().unwrap();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body.error, "Send either the recording or a typed query, not both");
+    }
+
+    #[tokio::test]
+    async fn test_spoken_language_is_used_without_a_hint() {
+        use crate::test_support::leaf_jpeg;
+
+        // A dark photo is answered with a retake request in the farmer's language
+        let mut state = state(None);
+        state.speech = Some(Arc::new(MockSpeechToText::new("पानांवर डाग आहेत").heard_in("mr")));
+        let photo = leaf_jpeg(0.1);
+        let body = json(ask(state, &[("voice", Some("q.ogg"), b"OggS\0\x02"), ("image", Some("leaf.jpg"), &photo)]).await.ok().unwrap()).await;
+        assert_eq!(body["answered_by"], "photo_check");
+        assert!(body["answer"].as_str().unwrap().contains("दिवसाच्या प्रकाशात"), "{}", body);
+    }
+}
@@ -0,0 +1,40 @@
+use axum::{Json, body::Bytes, extract::State, http::{HeaderMap, StatusCode}};
+use tracing::{info, warn};
+
+use crate::api::chat::{ApiError, ErrorResponse};
+use crate::state::AppState;
+
+fn reject(status: StatusCode, error: &str) -> ApiError {
+    (status, Json(ErrorResponse { error: error.to_string() }))
+}
+
+/// Receives prediction completion from Replicate and wakes the waiting request or job
+pub async fn replicate_webhook_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    let hooks = state.replicate_webhooks.as_ref()
+        .ok_or_else(|| reject(StatusCode::NOT_FOUND, "Replicate webhooks are not enabled"))?;
+
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Err(e) = hooks.verify(header("webhook-id"), header("webhook-timestamp"), header("webhook-signature"), &body) {
+        warn!("Rejected Replicate webhook: {}", e);
+        return Err(reject(StatusCode::UNAUTHORIZED, "Invalid webhook signature"));
+    }
+
+    let prediction: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|_| reject(StatusCode::BAD_REQUEST, "Malformed webhook body"))?;
+    let id = prediction["id"].as_str()
+        .ok_or_else(|| reject(StatusCode::BAD_REQUEST, "Webhook body has no prediction id"))?
+        .to_string();
+
+    let status = prediction["status"].as_str().unwrap_or("unknown");
+    info!("Webhook: prediction {} is {}", id, status);
+
+    // Only final states complete a prediction; other events are acknowledged and ignored
+    if matches!(status, "succeeded" | "failed" | "canceled") {
+        hooks.complete(&id, prediction);
+    }
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,384 @@
+//! End-to-end tests: the full axum app on a real socket, talking to an in-process stand-in
+//! for the Replicate API. Each test scripts the stand-in's replies to prediction creation
+//! and polling, then checks what `POST /api/chat` returns.
+
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::path::Path as FsPath;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::rag::templates::TemplateRegistry;
+use crate::services::circuit_breaker::CircuitBreaker;
+use crate::services::http::{HttpClient, RetryPolicy};
+use crate::services::ibm_granite::{ReplicateApi, ReplicateProvider, DEFAULT_TEXT_MODEL};
+use crate::services::llm_chain::{ChainLink, LlmChain};
+use crate::state::AppState;
+
+/// One scripted reply from the stand-in
+#[derive(Clone)]
+struct Reply {
+    status: u16,
+    body: Value,
+    retry_after: Option<&'static str>,
+    delay: Duration,
+}
+
+impl Reply {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body, retry_after: None, delay: Duration::ZERO }
+    }
+
+    fn status(status: u16, body: Value) -> Self {
+        Self { status, ..Self::ok(body) }
+    }
+
+    fn prediction(status: &str) -> Self {
+        Self::ok(json!({ "id": "p1", "status": status }))
+    }
+
+    fn succeeded(output: Value) -> Self {
+        Self::ok(json!({ "id": "p1", "status": "succeeded", "output": output }))
+    }
+}
+
+/// Scripted Replicate; the last reply in a queue repeats once the others are used up
+#[derive(Default)]
+struct FakeReplicate {
+    base: Mutex<String>,
+    creates: Mutex<VecDeque<Reply>>,
+    polls: Mutex<VecDeque<Reply>>,
+    create_calls: Mutex<usize>,
+    poll_calls: Mutex<usize>,
+    authorization: Mutex<Vec<String>>,
+    /// IDs of predictions the app cancelled
+    canceled: Mutex<Vec<String>>,
+    cancel_authorization: Mutex<Vec<String>>,
+}
+
+fn next(queue: &Mutex<VecDeque<Reply>>) -> Reply {
+    let mut queue = queue.lock().unwrap();
+    if queue.len() > 1 {
+        queue.pop_front().unwrap()
+    } else {
+        queue.front().cloned().unwrap_or_else(|| Reply::status(500, json!({ "detail": "unscripted" })))
+    }
+}
+
+impl FakeReplicate {
+    async fn respond(&self, reply: Reply) -> Response {
+        tokio::time::sleep(reply.delay).await;
+        let mut body = reply.body;
+        if let Some(id) = body["id"].as_str().map(str::to_string) {
+            let base = self.base.lock().unwrap().clone();
+            body["urls"] = json!({
+                "get": format!("{}/predictions/{}", base, id),
+                "cancel": format!("{}/predictions/{}/cancel", base, id),
+            });
+        }
+        let mut headers = HeaderMap::new();
+        if let Some(retry_after) = reply.retry_after {
+            headers.insert("retry-after", retry_after.parse().unwrap());
+        }
+        (StatusCode::from_u16(reply.status).unwrap(), headers, Json(body)).into_response()
+    }
+}
+
+async fn create(State(fake): State<Arc<FakeReplicate>>, headers: HeaderMap, Json(_body): Json<Value>) -> Response {
+    *fake.create_calls.lock().unwrap() += 1;
+    if let Some(auth) = headers.get("authorization").and_then(|v| v.to_str().ok()) {
+        fake.authorization.lock().unwrap().push(auth.to_string());
+    }
+    let reply = next(&fake.creates);
+    fake.respond(reply).await
+}
+
+async fn poll(State(fake): State<Arc<FakeReplicate>>, Path(_id): Path<String>) -> Response {
+    *fake.poll_calls.lock().unwrap() += 1;
+    let reply = next(&fake.polls);
+    fake.respond(reply).await
+}
+
+async fn cancel(State(fake): State<Arc<FakeReplicate>>, Path(id): Path<String>, headers: HeaderMap) -> Json<Value> {
+    if let Some(auth) = headers.get("authorization").and_then(|v| v.to_str().ok()) {
+        fake.cancel_authorization.lock().unwrap().push(auth.to_string());
+    }
+    fake.canceled.lock().unwrap().push(id.clone());
+    Json(json!({ "id": id, "status": "canceled" }))
+}
+
+async fn serve(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+/// Starts the stand-in and the app pointed at it; returns the app's base URL
+async fn start(fake: Arc<FakeReplicate>, link_deadline: Duration) -> String {
+    let replicate = serve(
+        Router::new()
+            .route("/v1/models/:owner/:name/predictions", post(create))
+            .route("/v1/predictions/:id", get(poll))
+            .route("/v1/predictions/:id/cancel", post(cancel))
+            .with_state(fake.clone()),
+    )
+    .await;
+    let base_url = format!("{}/v1", replicate);
+    *fake.base.lock().unwrap() = base_url.clone();
+
+    let api = ReplicateApi {
+        base_url,
+        token: Some("test-token".to_string()),
+        poll_interval: Duration::from_millis(5),
+    };
+    let provider = ReplicateProvider::new(DEFAULT_TEXT_MODEL, false, &TemplateRegistry::load(FsPath::new("/nonexistent")).unwrap(), None, api);
+    let link = ChainLink::new(Arc::new(provider), CircuitBreaker::new(3, Duration::from_secs(30)), link_deadline);
+    let retry = RetryPolicy { max_retries: 1, base_delay: Duration::from_millis(10), max_delay: Duration::from_millis(50) };
+    let state = AppState::for_tests(HttpClient::new(Duration::from_secs(1), retry).unwrap(), LlmChain::new(vec![link]));
+    serve(crate::app(state)).await
+}
+
+fn fake(creates: Vec<Reply>, polls: Vec<Reply>) -> Arc<FakeReplicate> {
+    Arc::new(FakeReplicate {
+        creates: Mutex::new(creates.into()),
+        polls: Mutex::new(polls.into()),
+        ..FakeReplicate::default()
+    })
+}
+
+async fn ask(app: &str) -> (StatusCode, Value) {
+    let res = reqwest::Client::new()
+        .post(format!("{}/api/chat", app))
+        .json(&json!({ "query": "When should I sow wheat?" }))
+        .send()
+        .await
+        .unwrap();
+    let status = StatusCode::from_u16(res.status().as_u16()).unwrap();
+    (status, res.json().await.unwrap())
+}
+
+const ANSWER: &str = "Sow wheat between October and November [1].";
+
+fn assert_degraded(body: &Value, reason: &str) {
+    assert_eq!(body["degraded"], true, "{}", body);
+    assert_eq!(body["fallback_reason"], reason);
+    assert_eq!(body["answered_by"], "extractive");
+    assert!(!body["answer"].as_str().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_prediction_succeeds_on_create() {
+    let fake = fake(vec![Reply::succeeded(json!(ANSWER))], Vec::new());
+    let app = start(fake.clone(), Duration::from_secs(5)).await;
+
+    let (status, body) = ask(&app).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["answer"], ANSWER);
+    assert_eq!(body["degraded"], false);
+    assert_eq!(body["answered_by"], format!("replicate:{}", DEFAULT_TEXT_MODEL));
+    assert_eq!(body["citations"][0]["id"], 1);
+    assert_eq!(*fake.authorization.lock().unwrap(), vec!["Bearer test-token".to_string()]);
+    assert_eq!(*fake.poll_calls.lock().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_polls_from_starting_through_processing_to_succeeded() {
+    let fake = fake(
+        vec![Reply::prediction("starting")],
+        vec![
+            Reply::prediction("starting"),
+            Reply::prediction("processing"),
+            Reply::succeeded(json!(["Sow wheat ", "between October ", "and November [1]."])),
+        ],
+    );
+    let app = start(fake.clone(), Duration::from_secs(5)).await;
+
+    let (status, body) = ask(&app).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["answer"], ANSWER);
+    assert_eq!(body["degraded"], false);
+    assert_eq!(*fake.poll_calls.lock().unwrap(), 3);
+}
+
+#[tokio::test]
+async fn test_failed_prediction_falls_back() {
+    let failed = Reply::ok(json!({ "id": "p1", "status": "failed", "error": "CUDA out of memory" }));
+    let app = start(fake(vec![Reply::prediction("starting")], vec![failed]), Duration::from_secs(5)).await;
+
+    let (status, body) = ask(&app).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_degraded(&body, "prediction_failed");
+}
+
+#[tokio::test]
+async fn test_canceled_prediction_falls_back() {
+    let fake = fake(vec![Reply::prediction("starting")], vec![Reply::prediction("processing"), Reply::prediction("canceled")]);
+    let app = start(fake, Duration::from_secs(5)).await;
+
+    let (status, body) = ask(&app).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_degraded(&body, "prediction_failed");
+}
+
+#[tokio::test]
+async fn test_unauthorized_is_an_error_not_a_fallback() {
+    let fake = fake(vec![Reply::status(401, json!({ "detail": "Invalid token" }))], Vec::new());
+    let app = start(fake.clone(), Duration::from_secs(5)).await;
+
+    let (status, body) = ask(&app).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["error"], "Failed to generate response (auth)");
+    assert_eq!(*fake.create_calls.lock().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_rate_limit_is_retried_after_retry_after() {
+    let limited = Reply { retry_after: Some("0"), ..Reply::status(429, json!({ "detail": "Request was throttled" })) };
+    let fake = fake(vec![limited, Reply::succeeded(json!(ANSWER))], Vec::new());
+    let app = start(fake.clone(), Duration::from_secs(5)).await;
+
+    let (status, body) = ask(&app).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["answer"], ANSWER);
+    assert_eq!(*fake.create_calls.lock().unwrap(), 2);
+}
+
+#[tokio::test]
+async fn test_persistent_rate_limit_falls_back() {
+    let limited = Reply { retry_after: Some("0"), ..Reply::status(429, json!({ "detail": "Request was throttled" })) };
+    let app = start(fake(vec![limited], Vec::new()), Duration::from_secs(5)).await;
+
+    let (status, body) = ask(&app).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_degraded(&body, "upstream_http");
+}
+
+#[tokio::test]
+async fn test_malformed_output_falls_back() {
+    for output in [json!([null, 5]), json!({ "text": ANSWER }), json!(["", "  "])] {
+        let app = start(fake(vec![Reply::succeeded(output)], Vec::new()), Duration::from_secs(5)).await;
+        let (status, body) = ask(&app).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_degraded(&body, "empty_output");
+    }
+}
+
+#[tokio::test]
+async fn test_non_string_chunks_are_skipped() {
+    let output = json!(["Sow wheat between October ", null, "and November [1]."]);
+    let app = start(fake(vec![Reply::succeeded(output)], Vec::new()), Duration::from_secs(5)).await;
+
+    let (_, body) = ask(&app).await;
+    assert_eq!(body["answer"], ANSWER);
+    assert_eq!(body["degraded"], false);
+}
+
+#[tokio::test]
+async fn test_slow_create_times_out() {
+    let slow = Reply { delay: Duration::from_secs(2), ..Reply::succeeded(json!(ANSWER)) };
+    let app = start(fake(vec![slow], Vec::new()), Duration::from_millis(300)).await;
+
+    let (status, body) = ask(&app).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_degraded(&body, "timeout");
+}
+
+#[tokio::test]
+async fn test_prediction_stuck_processing_times_out() {
+    let app = start(fake(vec![Reply::prediction("starting")], vec![Reply::prediction("processing")]), Duration::from_millis(300)).await;
+
+    let (status, body) = ask(&app).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_degraded(&body, "timeout");
+}
+
+#[tokio::test]
+async fn test_job_canceled_while_prediction_is_created_cancels_it_upstream() {
+    let slow_start = Reply { delay: Duration::from_millis(300), ..Reply::prediction("starting") };
+    let fake = fake(vec![slow_start], vec![Reply::prediction("processing")]);
+    let app = start(fake.clone(), Duration::from_secs(5)).await;
+    let client = reqwest::Client::new();
+
+    let accepted: Value = client
+        .post(format!("{}/api/chat", app))
+        .json(&json!({ "query": "When should I sow wheat?", "async_job": true }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let job_url = format!("{}/api/jobs/{}", app, accepted["job_id"].as_str().unwrap());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let canceled: Value = client.delete(&job_url).send().await.unwrap().json().await.unwrap();
+    assert_eq!(canceled["status"], "canceled");
+
+    // The create was still in flight when the job was cancelled
+    for _ in 0..100 {
+        if !fake.canceled.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(*fake.canceled.lock().unwrap(), vec!["p1".to_string()]);
+    assert_eq!(*fake.poll_calls.lock().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_canceling_a_running_job_uses_the_provider_token() {
+    let fake = fake(vec![Reply::prediction("starting")], vec![Reply::prediction("processing")]);
+    let app = start(fake.clone(), Duration::from_secs(5)).await;
+    let client = reqwest::Client::new();
+
+    let accepted: Value = client
+        .post(format!("{}/api/chat", app))
+        .json(&json!({ "query": "When should I sow wheat?", "async_job": true }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let job_url = format!("{}/api/jobs/{}", app, accepted["job_id"].as_str().unwrap());
+    for _ in 0..100 {
+        if *fake.poll_calls.lock().unwrap() > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let canceled: Value = client.delete(&job_url).send().await.unwrap().json().await.unwrap();
+    assert_eq!(canceled["status"], "canceled");
+
+    assert_eq!(*fake.canceled.lock().unwrap(), vec!["p1".to_string()]);
+    assert_eq!(*fake.cancel_authorization.lock().unwrap(), vec!["Bearer test-token".to_string()]);
+}
+
+#[tokio::test]
+async fn test_only_upload_routes_take_large_bodies() {
+    let app = start(fake(Vec::new(), Vec::new()), Duration::from_secs(5)).await;
+    let client = reqwest::Client::new();
+    let photo = format!("data:image/png;base64,{}", "A".repeat(3 * 1024 * 1024));
+
+    let translate = client
+        .post(format!("{}/api/translate", app))
+        .json(&json!({ "text": photo, "target_lang": "hi" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(translate.status().as_u16(), 413);
+
+    // Past the body limit, so the photo itself is checked (zero bytes are no known format)
+    let chat = client
+        .post(format!("{}/api/chat", app))
+        .json(&json!({ "query": "What is wrong with this leaf?", "image": photo }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(chat.status().as_u16(), 415);
+}
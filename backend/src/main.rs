@@ -1,21 +1,49 @@
+// Several modules open with a `///` note set apart from the code by a blank line
+#![allow(clippy::empty_line_after_doc_comments)]
+
 use axum::{
     Router,
     routing::get,
     http::Method,
 };
 use tower_http::cors::{CorsLayer, Any};
-use tracing::info;
+use tracing::{error, info};
 use std::net::SocketAddr;
 
 mod api;
 mod rag;
 mod services;
+mod state;
 mod utils;
 
+use state::AppState;
+
+#[cfg(test)]
+mod e2e_tests;
+#[cfg(test)]
+mod test_support;
+
 async fn health_check() -> &'static str {
     "Smart Farming AI Agent Backend is Running 🚀"
 }
 
+/// Routes, CORS and state; shared by `main` and the end-to-end tests
+fn app(state: AppState) -> Router {
+    // CORS configuration
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::OPTIONS])
+        .allow_headers(Any);
+
+    let upload_limit = std::env::var("REQUEST_MAX_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(64 * 1024 * 1024);
+
+    Router::new()
+        .route("/health", get(health_check))
+        .nest("/api", api::router(upload_limit))
+        .layer(cors)
+        .with_state(state)
+}
+
 #[tokio::main]
 async fn main() {
     // Load environment variables
@@ -29,17 +57,15 @@ async fn main() {
         )
         .init();
 
-    // CORS configuration
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers(Any);
-
     // Build router
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .nest("/api", api::router())
-        .layer(cors);
+    let state = match AppState::from_env() {
+        Ok(state) => state,
+        Err(e) => {
+            error!("Cannot start: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let app = app(state);
 
     // Get port from env or default
     let port: u16 = std::env::var("BACKEND_PORT")
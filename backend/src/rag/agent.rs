@@ -0,0 +1,233 @@
+//! Tool-calling agent loop.
+//! The model is shown the registered tools and may reply with a JSON tool call instead of an
+//! answer. The tool's output is added under the question and the model is asked again, for at
+//! most `AGENT_MAX_STEPS` calls; then it has to answer from what it has. Any reply that isn't a
+//! tool call is the final answer, so a model that ignores the tools behaves as before.
+//! Tools are off unless `AGENT_MAX_STEPS` is set, and each result is capped at
+//! `MAX_OUTPUT_CHARS` so a large API response can't crowd the passages out of the prompt.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::env;
+use std::time::Instant;
+use tracing::{info, warn};
+
+use crate::services::error::LlmError;
+use crate::services::http::HttpClient;
+use crate::services::llm::LlmRequest;
+use crate::services::llm_chain::{ChainAnswer, LlmChain};
+use crate::services::tools::ToolRegistry;
+
+/// Longest tool output handed back to the model
+const MAX_OUTPUT_CHARS: usize = 1500;
+
+/// One tool call made while answering, as reported in the chat response
+#[derive(Clone, Debug, Serialize)]
+pub struct ToolCall {
+    pub step: usize,
+    pub tool: String,
+    pub arguments: Value,
+    /// Observation given back to the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// How a run ended, with the tool calls made on the way even when it failed
+pub struct AgentRun {
+    pub result: Result<ChainAnswer, LlmError>,
+    pub trace: Vec<ToolCall>,
+}
+
+pub struct Agent {
+    tools: ToolRegistry,
+    max_steps: usize,
+}
+
+impl Agent {
+    pub fn new(tools: ToolRegistry, max_steps: usize) -> Self {
+        Self { tools, max_steps }
+    }
+
+    /// `AGENT_MAX_STEPS` tool calls per question (default 0, tools off)
+    pub fn from_env() -> Self {
+        let max_steps = env::var("AGENT_MAX_STEPS").ok().and_then(|v| v.parse().ok()).unwrap_or(0);
+        Self::new(ToolRegistry::from_env(), max_steps)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_steps > 0 && !self.tools.is_empty()
+    }
+
+    /// Run the chain, executing tool calls until the model answers or the step limit is hit
+    pub async fn run(&self, chain: &LlmChain, http: &HttpClient, request: &LlmRequest) -> AgentRun {
+        let mut trace: Vec<ToolCall> = Vec::new();
+        loop {
+            let tools_allowed = trace.len() < self.max_steps;
+            let answer = match chain.generate(http, &self.step_request(request, &trace, tools_allowed)).await {
+                Ok(answer) => answer,
+                Err(e) => return AgentRun { result: Err(e), trace },
+            };
+
+            let Some((tool, arguments)) = parse_tool_call(&answer.text) else {
+                return AgentRun { result: Ok(answer), trace };
+            };
+            if !tools_allowed {
+                warn!("Model asked for {} after the {}-step limit", tool, self.max_steps);
+                let result = Err(LlmError::InvalidOutput {
+                    attempts: trace.len() as u32 + 1,
+                    message: format!("still calling tools ({}) after the {}-step limit", tool, self.max_steps),
+                });
+                return AgentRun { result, trace };
+            }
+
+            let started = Instant::now();
+            let result = self.tools.call(http, &tool, &arguments).await;
+            info!("Tool {} ({}) -> {}", tool, arguments, if result.is_ok() { "ok" } else { "error" });
+            let (output, error) = match result {
+                Ok(output) => (Some(cap_output(output)), None),
+                Err(e) => (None, Some(e)),
+            };
+            trace.push(ToolCall {
+                step: trace.len() + 1,
+                tool,
+                arguments,
+                output,
+                error,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+    }
+
+    /// The request for one step: tool instructions in the system prompt, results under the question
+    fn step_request(&self, request: &LlmRequest, trace: &[ToolCall], tools_allowed: bool) -> LlmRequest {
+        let mut step = request.clone();
+        if tools_allowed {
+            step.system_prompt = format!(
+                "{}\n\nYou can look things up with these tools:\n{}\n\
+                 To use one, reply with only a JSON object such as \
+                 {{\"tool\": \"mandi_price\", \"arguments\": {{\"commodity\": \"onion\"}}}} and nothing else. \
+                 Use a tool when the question needs live prices, the weather forecast, a calculation or more \
+                 knowledge-base detail; otherwise answer directly.",
+                request.system_prompt,
+                self.tools.describe()
+            );
+        }
+        if !trace.is_empty() {
+            let results: Vec<String> = trace
+                .iter()
+                .map(|call| match (&call.output, &call.error) {
+                    (Some(output), _) => format!("{} {}:\n{}", call.tool, call.arguments, output),
+                    (None, error) => format!("{} {} failed: {}", call.tool, call.arguments, error.as_deref().unwrap_or("unknown error")),
+                })
+                .collect();
+            step.query = format!("{}\n\nTool results:\n{}", request.query, results.join("\n\n"));
+            if !tools_allowed {
+                step.query.push_str("\n\nNo more tools are available; answer now from the results above.");
+            }
+        }
+        step
+    }
+}
+
+/// `output`, cut to `MAX_OUTPUT_CHARS` with a note saying so
+fn cap_output(output: String) -> String {
+    if output.chars().count() <= MAX_OUTPUT_CHARS {
+        return output;
+    }
+    let kept: String = output.chars().take(MAX_OUTPUT_CHARS).collect();
+    format!("{}\n[output truncated]", kept.trim_end())
+}
+
+/// `{"tool": ..., "arguments": {...}}`, optionally in a code fence; `None` for a normal answer
+fn parse_tool_call(text: &str) -> Option<(String, Value)> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("```json")
+        .or_else(|| text.strip_prefix("```"))
+        .and_then(|t| t.strip_suffix("```"))
+        .unwrap_or(text)
+        .trim();
+    if !text.starts_with('{') || !text.ends_with('}') {
+        return None;
+    }
+    let call: Value = serde_json::from_str(text).ok()?;
+    let tool = call["tool"].as_str()?.to_string();
+    let arguments = match &call["arguments"] {
+        Value::Null => Value::Object(Default::default()),
+        args => args.clone(),
+    };
+    Some((tool, arguments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{http, llm_request, mock_chain, replies};
+
+    fn chain(script: &[&str]) -> LlmChain {
+        mock_chain("agent", replies(script))
+    }
+
+    fn request() -> LlmRequest {
+        llm_request("How much is 2 acres in hectares?")
+    }
+
+    #[test]
+    fn test_parse_tool_call() {
+        let fenced = "```json\n{\"tool\": \"kb_search\", \"arguments\": {\"query\": \"aphids\"}}\n```";
+        assert_eq!(parse_tool_call(fenced), Some(("kb_search".to_string(), serde_json::json!({ "query": "aphids" }))));
+        assert_eq!(parse_tool_call("Sow wheat in November [1]."), None);
+        assert_eq!(parse_tool_call("{\"answer\": \"no tool\"}"), None);
+    }
+
+    #[tokio::test]
+    async fn test_tool_result_feeds_the_answer() {
+        let chain = chain(&[
+            r#"{"tool": "unit_converter", "arguments": {"value": 2, "from": "acre", "to": "hectare"}}"#,
+            "2 acres is about 0.81 hectare.",
+        ]);
+        let agent = Agent::new(ToolRegistry::default(), 4);
+        let run = agent.run(&chain, &http(), &request()).await;
+
+        assert_eq!(run.result.unwrap().text, "2 acres is about 0.81 hectare.");
+        assert_eq!(run.trace.len(), 1);
+        assert_eq!(run.trace[0].tool, "unit_converter");
+        assert_eq!(run.trace[0].output.as_deref(), Some("2 acre = 0.809 hectare"));
+    }
+
+    #[tokio::test]
+    async fn test_bad_calls_are_reported_and_steps_are_limited() {
+        let confused = chain(&[
+            r#"{"tool": "unit_converter", "arguments": {"value": "two"}}"#,
+            r#"{"tool": "crystal_ball"}"#,
+            "About 0.81 hectare.",
+        ]);
+        let agent = Agent::new(ToolRegistry::default(), 2);
+        let run = agent.run(&confused, &http(), &request()).await;
+        assert!(run.result.is_ok());
+        assert_eq!(run.trace.len(), 2);
+        assert!(run.trace[0].error.as_deref().unwrap().starts_with("invalid arguments"));
+        assert_eq!(run.trace[1].error.as_deref(), Some("unknown tool 'crystal_ball'"));
+
+        let step = agent.step_request(&request(), &run.trace, false);
+        assert!(step.query.ends_with("answer now from the results above."));
+        assert_eq!(step.system_prompt, "You are Kisan Mitra.");
+
+        let call = r#"{"tool": "kb_search", "arguments": {"query": "wheat"}}"#;
+        let stubborn = chain(&[call, call]);
+        let run = Agent::new(ToolRegistry::default(), 1).run(&stubborn, &http(), &request()).await;
+        assert!(matches!(run.result, Err(LlmError::InvalidOutput { attempts: 2, .. })));
+        assert_eq!(run.trace.len(), 1);
+    }
+
+    #[test]
+    fn test_long_tool_output_is_capped() {
+        let capped = cap_output("₹1,800 per quintal at Lasalgaon. ".repeat(200));
+        assert!(capped.ends_with("[output truncated]"));
+        assert!(capped.chars().count() < MAX_OUTPUT_CHARS + 20);
+        assert_eq!(cap_output("short".to_string()), "short");
+    }
+}
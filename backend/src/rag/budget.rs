@@ -0,0 +1,163 @@
+//! Fits system prompt, history, retrieved passages and question into a model's
+//! context window while reserving room for the answer.
+//! When over budget, the lowest-ranked passages go first, then the oldest turns,
+//! then the one remaining passage is truncated and finally the question itself.
+//! Token counts come from `tokens::Tokenizer`, a characters-per-token approximation,
+//! so a fitted prompt can still be a little over or under the real count.
+
+use super::templates::{PromptParts, PromptTemplate};
+use super::tokens::Tokenizer;
+use crate::services::error::LlmError;
+use crate::services::sessions::Turn;
+
+pub struct Fitted {
+    pub prompt: String,
+    pub passages_used: usize,
+    pub history_used: usize,
+    /// The one remaining passage had to be cut short
+    pub truncated: bool,
+    /// The question had to be cut short (e.g. long tool output appended to it)
+    pub question_truncated: bool,
+}
+
+/// Render `template` with as much context as fits in `context_window - answer_tokens`.
+/// `passages` must be ordered best first. Fails only when the system prompt and
+/// template leave no room at all.
+pub fn fit_prompt(
+    template: &PromptTemplate,
+    system: &str,
+    history: &[Turn],
+    passages: &[String],
+    question: &str,
+    language_instruction: &str,
+    answer_tokens: usize,
+) -> Result<Fitted, LlmError> {
+    let tokenizer = template.tokenizer();
+    let budget = template.context_window.saturating_sub(answer_tokens);
+
+    let mut passages: Vec<String> = passages.to_vec();
+    let mut question = question.to_string();
+    let mut history_start = 0;
+    let mut truncated = false;
+    let mut question_truncated = false;
+
+    loop {
+        let context = passages.join("\n\n");
+        let prompt = template.render(&PromptParts {
+            system,
+            history: &history[history_start..],
+            context: &context,
+            question: &question,
+            language_instruction,
+        });
+        let used = tokenizer.count(&prompt);
+
+        if used <= budget {
+            return Ok(Fitted {
+                prompt,
+                passages_used: passages.len(),
+                history_used: history.len() - history_start,
+                truncated,
+                question_truncated,
+            });
+        }
+
+        let overflow = used - budget;
+        if passages.len() > 1 {
+            passages.pop();
+        } else if history_start < history.len() {
+            history_start += 1;
+        } else if let Some(passage) = passages.first() {
+            // Only the best passage is left: keep as much of it as fits
+            match shorten(&tokenizer, passage, overflow) {
+                Some(kept) => {
+                    passages[0] = kept;
+                    truncated = true;
+                }
+                None => passages.clear(),
+            }
+        } else if !question.is_empty() {
+            question = shorten(&tokenizer, &question, overflow).unwrap_or_default();
+            question_truncated = true;
+        } else {
+            return Err(LlmError::Config(format!(
+                "system prompt needs {} tokens but the {} context window leaves {} after the answer",
+                used, template.family, budget
+            )));
+        }
+    }
+}
+
+/// The start of `text`, about `overflow` tokens shorter; `None` when nothing would be left
+fn shorten(tokenizer: &Tokenizer, text: &str, overflow: usize) -> Option<String> {
+    let tokens = tokenizer.count(text).max(1);
+    if overflow >= tokens {
+        return None;
+    }
+    let keep_chars = text.chars().count() * (tokens - overflow) / tokens;
+    Some(text.chars().take(keep_chars).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::templates::TemplateRegistry;
+    use crate::services::sessions::Role;
+    use std::path::Path;
+
+    fn template(window: usize) -> PromptTemplate {
+        let mut t = TemplateRegistry::load(Path::new("/nonexistent")).unwrap().for_model("granite");
+        t.context_window = window;
+        t
+    }
+
+    fn passages() -> Vec<String> {
+        vec![
+            "[Wheat] best passage ".repeat(10),
+            "[Rice] second passage ".repeat(10),
+            "[Soil] third passage ".repeat(10),
+        ]
+    }
+
+    #[test]
+    fn test_everything_fits() {
+        let fitted = fit_prompt(&template(8192), "sys", &[], &passages(), "q", "en", 500).unwrap();
+        assert_eq!(fitted.passages_used, 3);
+    }
+
+    #[test]
+    fn test_drops_lowest_ranked_passages_first() {
+        let history = vec![Turn::new(Role::User, "earlier question")];
+        let fitted = fit_prompt(&template(240), "sys", &history, &passages(), "q", "en", 50).unwrap();
+        assert_eq!(fitted.passages_used, 2);
+        assert_eq!(fitted.history_used, 1);
+        assert!(fitted.prompt.contains("[Wheat]"));
+        assert!(!fitted.prompt.contains("[Soil]"));
+    }
+
+    #[test]
+    fn test_truncates_last_passage_after_dropping_history() {
+        let history = vec![Turn::new(Role::User, &"long history ".repeat(20))];
+        let fitted = fit_prompt(&template(150), "sys", &history, &passages(), "q", "en", 50).unwrap();
+        assert_eq!(fitted.passages_used, 1);
+        assert_eq!(fitted.history_used, 0);
+        assert!(fitted.truncated);
+        assert!(template(150).tokenizer().count(&fitted.prompt) <= 100);
+    }
+
+    #[test]
+    fn test_oversized_question_is_trimmed_to_budget() {
+        let question = format!("how much urea? {}", "tool output line ".repeat(100));
+        let fitted = fit_prompt(&template(150), "sys", &[], &passages(), &question, "en", 50).unwrap();
+        assert_eq!(fitted.passages_used, 0);
+        assert!(fitted.question_truncated);
+        assert!(fitted.prompt.contains("how much urea?"));
+        assert!(template(150).tokenizer().count(&fitted.prompt) <= 100);
+    }
+
+    #[test]
+    fn test_system_prompt_larger_than_window_is_an_error() {
+        let system = "You are Kisan Mitra. ".repeat(100);
+        assert!(fit_prompt(&template(150), &system, &[], &passages(), "q", "en", 50).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Inline citations: the model marks claims with `[1]`, `[2]` referring to the numbered
+//! passages in its prompt. Markers are checked against the passages that fit in the
+//! prompt (numbers of passages it never saw, or that don't exist, are removed) and resolved
+//! into structured citations for the UI. Other bracketed numbers, like "[2024]", are left alone.
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::Serialize;
+use std::collections::HashSet;
+
+use super::knowledge_base::source_url;
+use super::retriever::Passage;
+
+/// Retrieval returns a handful of passages, so a bracketed number up to this is always read
+/// as a citation; larger ones ("[2024]", "[10, 20]") only next to a valid passage number
+const MAX_CITATION_NUMBER: usize = 9;
+
+/// Prompt instruction added whenever passages are supplied
+pub const CITATION_INSTRUCTION: &str =
+    "Cite the documents you rely on by their number in square brackets, e.g. [1] or [1][2], right after the claim.";
+
+lazy_static! {
+    // "[1]", "[1, 3]" with any whitespace before it
+    static ref MARKER: Regex = Regex::new(r"(\s*)\[(\d+(?:\s*,\s*\d+)*)\]").unwrap();
+    static ref SENTENCE_END: Regex = Regex::new(r"[.!?।]+(?:\s+|$)|\n+").unwrap();
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct Citation {
+    /// Marker number used in the answer
+    pub id: usize,
+    pub title: String,
+    pub source: String,
+    /// Sentence of the passage that best matches the cited claim
+    pub snippet: String,
+    pub url: Option<String>,
+}
+
+pub struct Cited {
+    /// Answer with invalid markers removed and groups written as `[1][2]`
+    pub answer: String,
+    /// In order of first use
+    pub citations: Vec<Citation>,
+}
+
+/// Validate the markers in `answer` against the first `kept` of `passages` (numbered
+/// from 1, best first)
+pub fn resolve(answer: &str, passages: &[Passage], kept: usize) -> Cited {
+    let kept = kept.min(passages.len());
+    let mut order: Vec<usize> = Vec::new();
+    let cleaned = MARKER.replace_all(answer, |caps: &Captures| {
+        let numbers: Vec<usize> = caps[2].split(',').filter_map(|n| n.trim().parse().ok()).collect();
+        let looks_cited = numbers.iter().all(|n| (1..=MAX_CITATION_NUMBER).contains(n))
+            || numbers.iter().any(|n| (1..=passages.len()).contains(n));
+        if !looks_cited {
+            return caps[0].to_string();
+        }
+        let ids: Vec<usize> = numbers.into_iter().filter(|id| (1..=kept).contains(id)).collect();
+        if ids.is_empty() {
+            return String::new();
+        }
+        for id in &ids {
+            if !order.contains(id) {
+                order.push(*id);
+            }
+        }
+        let markers: String = ids.iter().map(|id| format!("[{}]", id)).collect();
+        format!("{}{}", &caps[1], markers)
+    }).to_string();
+
+    let citations = order
+        .into_iter()
+        .map(|id| {
+            let passage = &passages[id - 1];
+            let claims = claims_citing(&cleaned, id);
+            Citation {
+                id,
+                title: passage.doc.title.clone(),
+                source: passage.doc.source.clone(),
+                snippet: best_sentence(&passage.doc.content, &claims),
+                url: source_url(&passage.doc.source).map(str::to_string),
+            }
+        })
+        .collect();
+
+    Cited { answer: cleaned, citations }
+}
+
+/// Answer sentences carrying the marker `[id]`
+fn claims_citing(answer: &str, id: usize) -> String {
+    let marker = format!("[{}]", id);
+    // Markers usually follow the full stop, so attach each to the sentence before it
+    let mut claims = String::new();
+    let mut previous = "";
+    for sentence in SENTENCE_END.split(answer) {
+        if sentence.trim_start().starts_with(&marker) {
+            claims.push_str(previous);
+            claims.push(' ');
+        }
+        if sentence.contains(&marker) {
+            claims.push_str(sentence);
+            claims.push(' ');
+        }
+        previous = sentence;
+    }
+    claims
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() > 3 || w.chars().any(|c| c.is_ascii_digit()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Passage sentence sharing the most words with `claims`, or its first sentence
+fn best_sentence(content: &str, claims: &str) -> String {
+    let claim_words = words(claims);
+    let sentences: Vec<&str> = SENTENCE_END.split(content).map(str::trim).filter(|s| !s.is_empty()).collect();
+    let best = sentences
+        .iter()
+        .enumerate()
+        .max_by_key(|(i, s)| (words(s).intersection(&claim_words).count(), std::cmp::Reverse(*i)))
+        .map(|(_, s)| *s)
+        .unwrap_or(content);
+    format!("{}.", best.trim_end_matches('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::knowledge_base::Document;
+
+    fn passage(title: &str, content: &str, source: &str) -> Passage {
+        Passage {
+            doc: Document {
+                title: title.to_string(),
+                content: content.to_string(),
+                category: "crops".to_string(),
+                source: source.to_string(),
+            },
+            score: 1.0,
+        }
+    }
+
+    fn passages() -> Vec<Passage> {
+        vec![
+            passage("Wheat Cultivation", "Wheat is a rabi crop. Best sowing time is October to November. Requires 4-5 irrigations.", "ICAR Wheat Guidelines"),
+            passage("Winter Season Farming Tips", "Protect crops from frost. Irrigate during evening.", "IMD Advisory"),
+        ]
+    }
+
+    #[test]
+    fn test_valid_markers_become_citations() {
+        let cited = resolve("Sow wheat between October and November [1]. Irrigate in the evening to avoid frost [2, 1].", &passages(), 2);
+        assert_eq!(cited.answer, "Sow wheat between October and November [1]. Irrigate in the evening to avoid frost [2][1].");
+        assert_eq!(cited.citations.len(), 2);
+        assert_eq!(cited.citations[0].id, 1);
+        assert_eq!(cited.citations[0].snippet, "Best sowing time is October to November.");
+        assert_eq!(cited.citations[0].url.as_deref(), Some("https://icar.org.in"));
+        assert_eq!(cited.citations[1].source, "IMD Advisory");
+    }
+
+    #[test]
+    fn test_markers_for_passages_the_model_never_saw_are_removed() {
+        let cited = resolve("Use 5 ml/L neem oil [2]. Sow in October [1, 2].", &passages(), 1);
+        assert_eq!(cited.answer, "Use 5 ml/L neem oil. Sow in October [1].");
+        assert_eq!(cited.citations.len(), 1);
+    }
+
+    #[test]
+    fn test_numbers_past_the_last_passage_are_removed() {
+        let cited = resolve("Sow in October [7]. Irrigate in the evening [2, 7]. Use certified seed [1, 12].", &passages(), 2);
+        assert_eq!(cited.answer, "Sow in October. Irrigate in the evening [2]. Use certified seed [1].");
+        let ids: Vec<usize> = cited.citations.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_other_bracketed_numbers_are_left_alone() {
+        let answer = "Yields rose in [2024]. Space rows [10, 20] cm apart. [0]";
+        let cited = resolve(answer, &passages(), 2);
+        assert_eq!(cited.answer, answer);
+        assert!(cited.citations.is_empty());
+    }
+
+    #[test]
+    fn test_marker_after_full_stop_cites_previous_sentence() {
+        let cited = resolve("It needs 4-5 irrigations. [1]", &passages(), 2);
+        assert_eq!(cited.citations[0].snippet, "Requires 4-5 irrigations.");
+    }
+}
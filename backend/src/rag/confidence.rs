@@ -0,0 +1,92 @@
+//! Calibrated answer confidence.
+//! Combines how well retrieval matched, how clearly the best passage beat the rest,
+//! whether the answer's figures were grounded, how sure language detection was and
+//! whether a fallback answered, into a 0-1 score with a low/medium/high band.
+
+/// Keyword score at which retrieval counts as a ~63% match
+const SCORE_SCALE: f32 = 5.0;
+
+const WEIGHT_RETRIEVAL: f32 = 0.65;
+const WEIGHT_MARGIN: f32 = 0.2;
+const WEIGHT_LANGUAGE: f32 = 0.15;
+
+/// Ceiling for fallback answers and answers with unverified figures
+const LOW_CEILING: f32 = 0.35;
+
+pub struct Signals<'a> {
+    /// Retrieval scores, best first
+    pub scores: &'a [f32],
+    /// Share of figures in the answer found in the sources
+    pub grounding: f32,
+    pub language_certainty: f32,
+    /// The answer is the extractive fallback
+    pub fallback: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Confidence {
+    pub value: f32,
+    /// "low", "medium" or "high"
+    pub band: &'static str,
+}
+
+pub fn score(signals: &Signals) -> Confidence {
+    let top = signals.scores.first().copied().unwrap_or(0.0);
+    let retrieval = 1.0 - (-top / SCORE_SCALE).exp();
+    // A clear winner is more trustworthy than three near-equal weak matches
+    let margin = match signals.scores.get(1) {
+        _ if top <= 0.0 => 0.0,
+        Some(second) => (top - second) / top,
+        None => 1.0,
+    };
+
+    let mut value = (WEIGHT_RETRIEVAL * retrieval
+        + WEIGHT_MARGIN * margin
+        + WEIGHT_LANGUAGE * signals.language_certainty)
+        * signals.grounding;
+    if signals.fallback || signals.grounding < 1.0 {
+        value = value.min(LOW_CEILING);
+    }
+    let value = (value.clamp(0.0, 1.0) * 100.0).round() / 100.0;
+
+    let band = if value >= 0.7 {
+        "high"
+    } else if value >= 0.4 {
+        "medium"
+    } else {
+        "low"
+    };
+    Confidence { value, band }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signals(scores: &[f32]) -> Signals<'_> {
+        Signals { scores, grounding: 1.0, language_certainty: 0.9, fallback: false }
+    }
+
+    #[test]
+    fn test_strong_clear_match_is_high() {
+        let confidence = score(&signals(&[12.0, 3.0, 1.0]));
+        assert_eq!(confidence.band, "high");
+        assert!(confidence.value > 0.8);
+    }
+
+    #[test]
+    fn test_weak_even_matches_are_not_high() {
+        assert_eq!(score(&signals(&[1.0, 1.0, 1.0])).band, "low");
+        assert_eq!(score(&signals(&[3.0, 2.0, 1.0])).band, "medium");
+        assert_eq!(score(&signals(&[])).band, "low");
+        assert!(score(&signals(&[1.0, 1.0, 1.0])).value < score(&signals(&[6.0, 1.0])).value);
+    }
+
+    #[test]
+    fn test_fallback_and_unverified_figures_are_low() {
+        let fallback = Signals { fallback: true, ..signals(&[12.0]) };
+        assert_eq!(score(&fallback).band, "low");
+        let unverified = Signals { grounding: 0.5, ..signals(&[12.0]) };
+        assert_eq!(score(&unverified).band, "low");
+    }
+}
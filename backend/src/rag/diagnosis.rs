@@ -0,0 +1,426 @@
+//! Plant disease and pest diagnosis from photos.
+//! Each photo goes to the vision chain with the knowledge-base pest documents as reference and
+//! comes back as structured JSON (see `structured`). Findings from all photos are merged, the
+//! control measures come from the matching pest document rather than the model, and an unsure
+//! or split result turns into clarifying questions for the farmer.
+
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use tracing::{info, warn};
+
+use super::knowledge_base::{get_all_documents, source_url, Document};
+use super::locality::PhotoContext;
+use super::rewriter::crop_names;
+use super::structured::{self, OutputSchema};
+use crate::services::error::LlmError;
+use crate::services::generation::{GenerationOverrides, ResponseMode};
+use crate::services::image_quality::{self, QualityIssue, RejectedPhoto};
+use crate::services::llm::LlmRequest;
+use crate::state::AppState;
+
+/// Top findings closer than this are treated as a toss-up
+const AMBIGUITY_MARGIN: f32 = 0.15;
+
+/// What the model reports for one photo
+#[derive(Debug, Deserialize)]
+pub struct PhotoDiagnosis {
+    #[serde(default)]
+    pub healthy: bool,
+    pub candidates: Vec<Candidate>,
+    #[serde(default)]
+    pub clarifying_questions: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Candidate {
+    pub name: String,
+    pub kind: String,
+    pub confidence: f32,
+    #[serde(default)]
+    pub symptoms: Vec<String>,
+}
+
+impl OutputSchema for PhotoDiagnosis {
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "healthy": { "type": "boolean" },
+                "candidates": {
+                    "type": "array",
+                    "maxItems": 5,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string", "minLength": 2 },
+                            "kind": { "enum": ["disease", "pest", "deficiency", "other"] },
+                            "confidence": { "type": "number", "minimum": 0, "maximum": 1 },
+                            "symptoms": { "type": "array", "items": { "type": "string" } }
+                        },
+                        "required": ["name", "kind", "confidence"]
+                    }
+                },
+                "clarifying_questions": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["candidates"]
+        })
+    }
+
+    fn claims(&self) -> Vec<String> {
+        self.candidates
+            .iter()
+            .flat_map(|c| c.symptoms.iter().map(move |symptom| format!("{}: {}.", c.name, symptom)))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosisStatus {
+    Diagnosed,
+    NeedsClarification,
+    Healthy,
+    /// No photo passed the quality check; `clarifying_questions` says how to retake them
+    RetakePhoto,
+}
+
+/// A likely disease or pest, merged across photos
+#[derive(Clone, Debug, Serialize)]
+pub struct Finding {
+    pub name: String,
+    pub kind: String,
+    pub confidence: f32,
+    pub symptoms: Vec<String>,
+    /// From the matching knowledge-base document; empty when none matched
+    pub controls: Vec<String>,
+    pub kb_title: Option<String>,
+    pub source: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnosis {
+    pub status: DiagnosisStatus,
+    /// Most likely first
+    pub findings: Vec<Finding>,
+    pub clarifying_questions: Vec<String>,
+    pub answered_by: String,
+    pub images_analyzed: usize,
+    /// Photos left out for being blurred, badly exposed or too small, or that the vision
+    /// model could not analyse
+    pub rejected_photos: Vec<RejectedPhoto>,
+    /// District and season inferred from the photos, when the farmer shared their location
+    pub photo_context: Option<PhotoContext>,
+}
+
+/// `DIAGNOSE_MIN_CONFIDENCE` (default 0.5): below it the farmer is asked for more detail
+pub fn min_confidence_from_env() -> f32 {
+    env::var("DIAGNOSE_MIN_CONFIDENCE").ok().and_then(|v| v.parse().ok()).unwrap_or(0.5)
+}
+
+fn pest_documents() -> Vec<Document> {
+    get_all_documents().into_iter().filter(|d| d.category == "pest_control").collect()
+}
+
+/// Lowercased words with a plural 's' dropped, so "Aphids" matches "Aphid"
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.len() > 1)
+        .map(|w| w.strip_suffix('s').filter(|s| s.len() > 2).unwrap_or(w).to_string())
+        .collect()
+}
+
+/// Crops named after " in " in a document title ("Tomato and Potato"); empty for a
+/// document about a group such as "Vegetables"
+fn document_crops(doc: &Document) -> Vec<&'static str> {
+    let scope = words(doc.title.split_once(" in ").map_or("", |(_, scope)| scope));
+    crop_names().filter(|crop| scope.iter().any(|w| w == crop)).collect()
+}
+
+/// The pest document for a finding on `crop`: every word of the document's subject
+/// ("Stem Borer" in "Stem Borer in Rice", "Aphid" in "Aphid Control in Vegetables") appears
+/// in the name, and a document written for particular crops must name this one. Those
+/// come before documents about a group of crops.
+fn matching_document<'a>(name: &str, crop: &str, documents: &'a [Document]) -> Option<&'a Document> {
+    let name_words = words(name);
+    let mut crop_words = words(crop);
+    if crop_words.iter().any(|w| w == "paddy") {
+        crop_words.push("rice".to_string());
+    }
+    let about_finding = documents.iter().filter(|doc| {
+        let subject = doc.title.split(" in ").next().unwrap_or(&doc.title).replace("Control", "");
+        let subject_words = words(&subject);
+        !subject_words.is_empty() && subject_words.iter().all(|w| name_words.contains(w))
+    });
+    about_finding
+        .clone()
+        .find(|doc| document_crops(doc).iter().any(|c| crop_words.iter().any(|w| w == c)))
+        .or_else(|| about_finding.clone().find(|doc| document_crops(doc).is_empty()))
+}
+
+/// The sentences after "Control:" in a pest document
+fn controls(doc: &Document) -> Vec<String> {
+    let Some((_, rest)) = doc.content.split_once("Control:") else {
+        return Vec::new();
+    };
+    rest.split(". ")
+        .map(|s| s.trim().trim_end_matches('.').to_string())
+        .filter(|s| !s.is_empty())
+        .map(|s| format!("{}.", s))
+        .collect()
+}
+
+fn default_questions(lang: &str) -> Vec<String> {
+    let questions: [&str; 3] = match lang {
+        "hi" => [
+            "क्या आप प्रभावित पत्तियों या फल की दिन की रोशनी में नज़दीक से फोटो भेज सकते हैं?",
+            "समस्या पहली बार कब दिखी, और क्या यह आसपास के पौधों में फैल रही है?",
+            "क्या पत्तियों की निचली सतह पर कोई कीड़े दिखते हैं?",
+        ],
+        "mr" => [
+            "बाधित पानांचा किंवा फळांचा दिवसाच्या प्रकाशात जवळून फोटो पाठवू शकता का?",
+            "समस्या पहिल्यांदा कधी दिसली, आणि ती आजूबाजूच्या झाडांवर पसरत आहे का?",
+            "पानांच्या खालच्या बाजूला काही कीटक दिसतात का?",
+        ],
+        _ => [
+            "Can you send a close-up photo of the affected leaves or fruit, taken in daylight?",
+            "When did you first notice the problem, and is it spreading to nearby plants?",
+            "Do you see any insects on the underside of the leaves?",
+        ],
+    };
+    questions.iter().map(|q| q.to_string()).collect()
+}
+
+/// Merge per-photo results, attach knowledge-base controls for `crop` and decide whether to
+/// ask questions
+pub fn combine(photos: Vec<PhotoDiagnosis>, crop: &str, lang: &str, min_confidence: f32, answered_by: String) -> Diagnosis {
+    let documents = pest_documents();
+    let images_analyzed = photos.len();
+    let all_healthy = photos.iter().all(|p| p.healthy && p.candidates.is_empty());
+
+    let mut findings: Vec<Finding> = Vec::new();
+    let mut questions: Vec<String> = Vec::new();
+    for photo in photos {
+        for q in photo.clarifying_questions {
+            if !q.trim().is_empty() && !questions.contains(&q) {
+                questions.push(q);
+            }
+        }
+        for candidate in photo.candidates {
+            let key = words(&candidate.name);
+            match findings.iter_mut().find(|f| words(&f.name) == key) {
+                Some(finding) => {
+                    finding.confidence = finding.confidence.max(candidate.confidence);
+                    for symptom in candidate.symptoms {
+                        if !finding.symptoms.contains(&symptom) {
+                            finding.symptoms.push(symptom);
+                        }
+                    }
+                }
+                None => {
+                    let doc = matching_document(&candidate.name, crop, &documents);
+                    findings.push(Finding {
+                        name: candidate.name,
+                        kind: candidate.kind,
+                        confidence: candidate.confidence.clamp(0.0, 1.0),
+                        symptoms: candidate.symptoms,
+                        controls: doc.map(controls).unwrap_or_default(),
+                        kb_title: doc.map(|d| d.title.clone()),
+                        source: doc.map(|d| d.source.clone()),
+                        url: doc.and_then(|d| source_url(&d.source)).map(str::to_string),
+                    });
+                }
+            }
+        }
+    }
+    findings.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let top = findings.first().map_or(0.0, |f| f.confidence);
+    let runner_up = findings.get(1).map_or(0.0, |f| f.confidence);
+    let status = if findings.is_empty() && all_healthy {
+        DiagnosisStatus::Healthy
+    } else if top < min_confidence || (findings.len() > 1 && top - runner_up < AMBIGUITY_MARGIN) {
+        DiagnosisStatus::NeedsClarification
+    } else {
+        DiagnosisStatus::Diagnosed
+    };
+    if status == DiagnosisStatus::NeedsClarification && questions.is_empty() {
+        questions = default_questions(lang);
+    }
+    if status != DiagnosisStatus::NeedsClarification {
+        questions.clear();
+    }
+
+    Diagnosis { status, findings, clarifying_questions: questions, answered_by, images_analyzed, rejected_photos: Vec::new(), photo_context: None }
+}
+
+/// What to send back when every photo failed the quality check: no prediction was made
+pub fn retake(rejected_photos: Vec<RejectedPhoto>, lang: &str) -> Diagnosis {
+    let mut issues: Vec<QualityIssue> = Vec::new();
+    for issue in rejected_photos.iter().flat_map(|r| &r.issues) {
+        if !issues.contains(issue) {
+            issues.push(*issue);
+        }
+    }
+    Diagnosis {
+        status: DiagnosisStatus::RetakePhoto,
+        findings: Vec::new(),
+        clarifying_questions: vec![image_quality::retake_message(&issues, lang)],
+        answered_by: image_quality::PHOTO_CHECK.to_string(),
+        images_analyzed: 0,
+        rejected_photos,
+        photo_context: None,
+    }
+}
+
+/// Diagnose `crop` from one or more photos (base64 or URLs); `context` says where and when
+/// they were taken, when the farmer shared it. Photos the model fails on are listed in
+/// `rejected_photos`, numbered by their place in `images`; it is an error only when every
+/// photo failed.
+pub async fn diagnose(
+    state: &AppState,
+    crop: &str,
+    images: &[String],
+    notes: Option<&str>,
+    context: Option<&PhotoContext>,
+    lang: &str,
+) -> Result<Diagnosis, LlmError> {
+    let passages: Vec<String> = pest_documents()
+        .iter()
+        .enumerate()
+        .map(|(i, d)| format!("[{}] {}: {}", i + 1, d.title, d.content))
+        .collect();
+    let query = format!(
+        "Diagnose the problem in this photo of a {} plant.{}{} List the diseases, pests or nutrient \
+         deficiencies you can see, each with your confidence from 0 to 1 and the symptoms visible in \
+         the photo. Use the names from the reference passages when they fit. Set healthy to true if \
+         the plant looks healthy. If the photo is unclear or could show several problems, lower the \
+         confidences and add the questions you would ask the farmer.",
+        crop,
+        context.map(|c| c.describe()).filter(|d| !d.is_empty()).map(|d| format!(" {}", d)).unwrap_or_default(),
+        notes.map(|n| format!(" The farmer says: \"{}\".", n)).unwrap_or_default(),
+    );
+    let overrides = GenerationOverrides { temperature: Some(0.2), ..GenerationOverrides::default() };
+    let max_attempts = structured::max_attempts_from_env();
+
+    // Photos are independent, so they go to the vision model together
+    let calls = images.iter().map(|image| {
+        let request = LlmRequest {
+            system_prompt: state.system_prompt.text().to_string(),
+            history: Vec::new(),
+            query: query.clone(),
+            passages: passages.clone(),
+            image: Some(image.clone()),
+            target_lang: lang.to_string(),
+            mode: ResponseMode::Normal,
+            params: state.generation.resolve(ResponseMode::Detailed, &overrides),
+            output_schema: None,
+            tracker: None,
+        };
+        async move { structured::generate::<PhotoDiagnosis>(&state.vision_chain, &state.http, &request, max_attempts).await }
+    });
+    let mut photos = Vec::new();
+    let mut failed = Vec::new();
+    let mut last_error = None;
+    let mut answered_by = String::new();
+    for (i, result) in join_all(calls).await.into_iter().enumerate() {
+        match result {
+            Ok(result) => {
+                info!("Photo {} of {}: {} candidates", i + 1, images.len(), result.value.candidates.len());
+                answered_by = result.answered_by;
+                photos.push(result.value);
+            }
+            Err(e) => {
+                warn!("Photo {} of {} could not be diagnosed: {}", i + 1, images.len(), e);
+                failed.push(RejectedPhoto { photo: i + 1, issues: Vec::new(), error: Some(e.reason().to_string()) });
+                last_error = Some(e);
+            }
+        }
+    }
+    if photos.is_empty() {
+        return Err(last_error.unwrap_or(LlmError::EmptyOutput));
+    }
+    let diagnosis = combine(photos, crop, lang, min_confidence_from_env(), answered_by);
+    Ok(Diagnosis { photo_context: context.cloned(), rejected_photos: failed, ..diagnosis })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str, confidence: f32, symptoms: &[&str]) -> Candidate {
+        Candidate {
+            name: name.to_string(),
+            kind: "pest".to_string(),
+            confidence,
+            symptoms: symptoms.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn photo(candidates: Vec<Candidate>) -> PhotoDiagnosis {
+        PhotoDiagnosis { healthy: false, candidates, clarifying_questions: Vec::new() }
+    }
+
+    #[test]
+    fn test_findings_merge_and_link_to_pest_documents() {
+        let photos = vec![
+            photo(vec![candidate("Aphids", 0.7, &["curled leaves"])]),
+            photo(vec![candidate("aphid", 0.85, &["honeydew", "curled leaves"]), candidate("Leaf miner", 0.2, &[])]),
+        ];
+        let diagnosis = combine(photos, "tomato", "en", 0.5, "mock:vision".to_string());
+
+        assert_eq!(diagnosis.status, DiagnosisStatus::Diagnosed);
+        assert_eq!(diagnosis.images_analyzed, 2);
+        let aphids = &diagnosis.findings[0];
+        assert_eq!(aphids.confidence, 0.85);
+        assert_eq!(aphids.symptoms, vec!["curled leaves", "honeydew"]);
+        assert_eq!(aphids.kb_title.as_deref(), Some("Aphid Control in Vegetables"));
+        assert_eq!(aphids.controls[0], "Spray neem oil (5ml/L), or use yellow sticky traps.");
+        assert_eq!(aphids.url.as_deref(), Some("https://icar.org.in"));
+        assert!(diagnosis.findings[1].controls.is_empty());
+        assert!(diagnosis.clarifying_questions.is_empty());
+    }
+
+    #[test]
+    fn test_subject_words_must_all_match() {
+        let documents = pest_documents();
+        assert_eq!(matching_document("Yellow stem borer", "rice", &documents).unwrap().title, "Stem Borer in Rice");
+        assert_eq!(matching_document("Rice blast", "Paddy", &documents).unwrap().title, "Blast in Rice");
+        assert!(matching_document("Late blight", "tomato", &documents).is_none());
+    }
+
+    #[test]
+    fn test_documents_for_other_crops_do_not_match() {
+        let documents = pest_documents();
+        assert!(matching_document("Stem borer", "maize", &documents).is_none());
+        assert!(matching_document("Early blight", "wheat", &documents).is_none());
+        assert_eq!(matching_document("Early blight", "potato", &documents).unwrap().title, "Early Blight in Tomato and Potato");
+        assert_eq!(matching_document("Aphids", "okra", &documents).unwrap().title, "Aphid Control in Vegetables");
+    }
+
+    #[test]
+    fn test_close_or_weak_findings_ask_questions() {
+        let split = combine(
+            vec![photo(vec![candidate("Early blight", 0.55, &[]), candidate("Late blight", 0.5, &[])])],
+            "tomato",
+            "hi",
+            0.5,
+            String::new(),
+        );
+        assert_eq!(split.status, DiagnosisStatus::NeedsClarification);
+        assert_eq!(split.clarifying_questions.len(), 3);
+        assert!(split.clarifying_questions[0].contains("फोटो"));
+
+        let mut unsure = photo(vec![candidate("Fruit fly", 0.3, &[])]);
+        unsure.clarifying_questions = vec!["Are the fruits rotting from inside?".to_string()];
+        let weak = combine(vec![unsure], "tomato", "en", 0.5, String::new());
+        assert_eq!(weak.status, DiagnosisStatus::NeedsClarification);
+        assert_eq!(weak.clarifying_questions, vec!["Are the fruits rotting from inside?"]);
+
+        let healthy = PhotoDiagnosis { healthy: true, candidates: Vec::new(), clarifying_questions: Vec::new() };
+        assert_eq!(combine(vec![healthy], "tomato", "en", 0.5, String::new()).status, DiagnosisStatus::Healthy);
+    }
+}
@@ -1,8 +1,74 @@
 /// Generator module - orchestrates the final response generation
-/// For now, this delegates to IBM Granite. Can be extended for prompt engineering.
+/// Runs the configured model chain (through the tool-calling agent for text queries) and
+/// falls back to the knowledge base when every link fails.
 
+use tracing::{info, warn};
+
+use super::agent::ToolCall;
+use crate::services::error::LlmError;
 use crate::services::ibm_granite;
+use crate::services::llm::LlmRequest;
+use crate::state::AppState;
+
+/// Label reported when the answer is the knowledge-base fallback
+pub const EXTRACTIVE: &str = "extractive";
+
+/// Result of a generation attempt
+pub struct Generation {
+    pub answer: String,
+    /// Which chain link produced `answer`, e.g. `replicate:ibm-granite/granite-3.3-8b-instruct`
+    pub answered_by: String,
+    /// Set when `answer` is the knowledge-base fallback rather than model output
+    pub fallback_reason: Option<&'static str>,
+    /// Tools the model called on the way to `answer`
+    pub tool_calls: Vec<ToolCall>,
+    /// Leading passages the model was shown; citation markers beyond them are invalid
+    pub passages_used: usize,
+}
+
+impl Generation {
+    pub fn is_degraded(&self) -> bool {
+        self.fallback_reason.is_some()
+    }
+}
+
+/// Generate an answer, falling back to the knowledge base on recoverable upstream errors.
+/// Configuration and auth errors are returned to the caller.
+pub async fn generate(state: &AppState, request: LlmRequest) -> Result<Generation, LlmError> {
+    let chain = if request.image.is_some() { &state.vision_chain } else { &state.text_chain };
+    info!("Generating with system prompt {}", state.system_prompt.label());
+
+    // Tool calls are reported even when the answer falls back to the knowledge base
+    let (result, tool_calls) = if request.image.is_none() && state.agent.is_enabled() {
+        let run = state.agent.run(chain, &state.http, &request).await;
+        (run.result, run.trace)
+    } else {
+        (chain.generate(&state.http, &request).await, Vec::new())
+    };
 
-pub async fn generate(query: &str, context: &str, image: Option<String>, target_lang: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    ibm_granite::generate_response(query, context, image, target_lang).await
+    match result {
+        Ok(answer) => Ok(Generation {
+            answer: answer.text,
+            answered_by: answer.answered_by,
+            fallback_reason: None,
+            tool_calls,
+            passages_used: answer.passages_used,
+        }),
+        Err(e) if e.allows_fallback() => {
+            warn!("Serving fallback response: {}", e);
+            Ok(Generation {
+                answer: ibm_granite::get_fallback_response(
+                    &request.query,
+                    &request.passages.join("\n\n"),
+                    &request.target_lang,
+                    &state.system_prompt,
+                ),
+                answered_by: EXTRACTIVE.to_string(),
+                fallback_reason: Some(e.reason()),
+                tool_calls,
+                passages_used: request.passages.len(),
+            })
+        }
+        Err(e) => Err(e),
+    }
 }
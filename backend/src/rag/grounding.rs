@@ -0,0 +1,302 @@
+//! Grounding check for numeric advice.
+//! Doses, prices and dates in a generated answer (5 ml/L, 25 kg/ha, ₹2,275/qtl, 21-25 days)
+//! must appear in the retrieved passages or the farmer's own question, and so must bare
+//! counts like "spray 3 times". Figures that don't are flagged with a warning or have their
+//! sentence removed, depending on `GROUNDING_MODE`.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::env;
+
+// A number or range: "5", "0.3", "2,275", "21-25", "4 to 5"
+const NUM: &str = r"(\d+(?:[.,]\d+)*)(?:\s*(?:-|–|to|से)\s*(\d+(?:[.,]\d+)*))?";
+
+lazy_static! {
+    /// (canonical unit, pattern), number first
+    static ref UNITS: Vec<(&'static str, Regex)> = [
+        ("ml/L", r"ml\s*(?:/|per|प्रति)\s*(?:l\b|lit(?:re|er)|लीटर|लिटर)|मिली\s*(?:/|प्रति)\s*(?:लीटर|लिटर)"),
+        ("g/L", r"(?:g|gm|grams?)\s*(?:/|per)\s*(?:l\b|lit(?:re|er))|ग्राम\s*(?:/|प्रति)\s*(?:लीटर|लिटर)"),
+        ("kg/ha", r"kg\s*(?:/|per)\s*(?:ha\b|hectare)|किलो(?:ग्राम)?\s*(?:/|प्रति)\s*हेक्टेयर"),
+        ("kg/acre", r"kg\s*(?:/|per)\s*acre|किलो(?:ग्राम)?\s*(?:/|प्रति)\s*एकड़"),
+        ("q/ha", r"(?:q|qtl|quintals?)\s*(?:/|per)\s*(?:ha\b|hectare)|क्विंटल\s*(?:/|प्रति)\s*हेक्टेयर"),
+        ("days", r"days?\b|दिन|दिवस"),
+        ("°C", r"°\s*c\b|degrees?\s*c(?:elsius)?\b"),
+        ("%", r"%|percent\b"),
+        ("cm", r"cm\b|सेमी"),
+    ]
+    .iter()
+    .map(|(unit, pattern)| (*unit, Regex::new(&format!(r"(?i){}\s*(?:{})", NUM, pattern)).unwrap()))
+    .collect();
+
+    /// Rupee amounts, currency first ("₹2,275/qtl", "Rs. 1800") or last ("1800 रुपये")
+    static ref RUPEES: Regex = Regex::new(&format!(
+        r"(?i)(?:₹|rs\.?|inr)\s*{num}|{num}\s*(?:rupees|रुपये|रुपए)",
+        num = NUM
+    )).unwrap();
+
+    static ref BARE: Regex = Regex::new(r"\d+(?:[.,]\d+)*").unwrap();
+
+    static ref SENTENCE_END: Regex = Regex::new(r"[.!?।]+(?:\s+|$)|\n+").unwrap();
+}
+
+/// Unit of a number written without one
+const BARE_UNIT: &str = "";
+
+/// Helplines the persona and the fallback answer give out (Kisan Call Center); they are
+/// contact details, not advice, and never appear in the passages
+pub const CONTACT_NUMBERS: &[&str] = &["1551"];
+
+/// A number (or range) with a unit found in text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Figure {
+    /// As written, e.g. "5ml/L"
+    pub text: String,
+    pub unit: &'static str,
+    pub low: f64,
+    pub high: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroundingMode {
+    /// Append a warning listing unsupported figures (default)
+    Flag,
+    /// Drop sentences that carry unsupported figures
+    Remove,
+    Off,
+}
+
+impl GroundingMode {
+    /// `GROUNDING_MODE`: flag, remove or off
+    pub fn from_env() -> Self {
+        match env::var("GROUNDING_MODE").as_deref() {
+            Ok("remove") => GroundingMode::Remove,
+            Ok("off") => GroundingMode::Off,
+            _ => GroundingMode::Flag,
+        }
+    }
+}
+
+pub struct Grounding {
+    /// Answer after flagging or removal
+    pub answer: String,
+    /// Figures found in the answer
+    pub checked: usize,
+    /// Figures not backed by the sources, as written in the answer
+    pub unsupported: Vec<String>,
+}
+
+impl Grounding {
+    /// Share of checked figures that were supported; 1.0 when there were none
+    pub fn supported_ratio(&self) -> f32 {
+        if self.checked == 0 {
+            1.0
+        } else {
+            (self.checked - self.unsupported.len()) as f32 / self.checked as f32
+        }
+    }
+}
+
+/// Devanagari digits to ASCII so "५ मिली/लीटर" is read as 5
+fn ascii_digits(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '०'..='९' => char::from_digit(c as u32 - '०' as u32, 10).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    s.replace(',', "").parse().ok()
+}
+
+fn figure(unit: &'static str, text: &str, low: Option<regex::Match>, high: Option<regex::Match>) -> Option<Figure> {
+    let low = parse_number(low?.as_str())?;
+    let high = high.and_then(|m| parse_number(m.as_str())).unwrap_or(low);
+    Some(Figure { text: text.trim().to_string(), unit, low, high })
+}
+
+/// Every figure in `text`: numbers with a known unit, then bare numbers outside them
+pub fn extract(text: &str) -> Vec<Figure> {
+    let text = ascii_digits(text);
+    let mut figures = Vec::new();
+    let mut spans = Vec::new();
+    for (unit, re) in UNITS.iter() {
+        for caps in re.captures_iter(&text) {
+            spans.push(caps.get(0).map_or(0..0, |m| m.range()));
+            figures.extend(figure(unit, &caps[0], caps.get(1), caps.get(2)));
+        }
+    }
+    for caps in RUPEES.captures_iter(&text) {
+        spans.push(caps.get(0).map_or(0..0, |m| m.range()));
+        let (low, high) = if caps.get(1).is_some() { (caps.get(1), caps.get(2)) } else { (caps.get(3), caps.get(4)) };
+        figures.extend(figure("₹", &caps[0], low, high));
+    }
+    for m in BARE.find_iter(&text) {
+        let overlaps = spans.iter().any(|span| m.start() < span.end && span.start < m.end());
+        if !overlaps && is_count(&text, m.range()) {
+            figures.extend(figure(BARE_UNIT, m.as_str(), Some(m), None));
+        }
+    }
+    figures
+}
+
+/// Whether the bare number at `range` reads as a quantity rather than a citation marker
+/// ("[1]"), list number ("2."), product code ("4G", "45SC"), year or helpline
+fn is_count(text: &str, range: std::ops::Range<usize>) -> bool {
+    if CONTACT_NUMBERS.contains(&&text[range.clone()]) {
+        return false;
+    }
+    let before = text[..range.start].chars().next_back();
+    let after = text[range.end..].chars().next();
+    if before.is_some_and(|c| c.is_alphanumeric() || c == '[') || after.is_some_and(|c| c.is_alphanumeric() || c == ']') {
+        return false;
+    }
+    let line_start = text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    if text[line_start..range.start].trim().is_empty() && matches!(after, Some('.' | ')')) {
+        return false;
+    }
+    let year = text[range.clone()].parse::<u32>().is_ok_and(|n| (1900..=2100).contains(&n));
+    !year
+}
+
+/// Answer figure lies within a source figure with the same unit; a bare number may be
+/// backed by a source figure with any unit
+fn is_supported(figure: &Figure, sources: &[Figure]) -> bool {
+    const EPSILON: f64 = 1e-6;
+    sources.iter().any(|s| {
+        (s.unit == figure.unit || figure.unit == BARE_UNIT)
+            && s.low - EPSILON <= figure.low
+            && figure.high <= s.high + EPSILON
+    })
+}
+
+fn warning(lang: &str, figures: &str) -> String {
+    match lang {
+        "hi" => format!("⚠️ ये आंकड़े हमारे स्रोतों में नहीं मिले, कृपया अपने नजदीकी कृषि विज्ञान केंद्र से पुष्टि करें: {}", figures),
+        "mr" => format!("⚠️ हे आकडे आमच्या स्रोतांमध्ये आढळले नाहीत, कृपया जवळच्या कृषी विज्ञान केंद्राकडून खात्री करा: {}", figures),
+        _ => format!("⚠️ We could not find these figures in our sources, please confirm them with your nearest Krishi Vigyan Kendra: {}", figures),
+    }
+}
+
+/// Sentences of `text`, each keeping its terminator
+fn sentences(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    for end in SENTENCE_END.find_iter(text) {
+        out.push(&text[start..end.end()]);
+        start = end.end();
+    }
+    if start < text.len() {
+        out.push(&text[start..]);
+    }
+    out
+}
+
+/// Check the figures in `answer` against `sources` (passages plus the question)
+pub fn verify(answer: &str, sources: &[&str], mode: GroundingMode, lang: &str) -> Grounding {
+    let found = extract(answer);
+    if mode == GroundingMode::Off || found.is_empty() {
+        return Grounding { answer: answer.to_string(), checked: found.len(), unsupported: Vec::new() };
+    }
+
+    let source_figures: Vec<Figure> = sources.iter().flat_map(|s| extract(s)).collect();
+    let mut unsupported: Vec<String> = Vec::new();
+    for f in found.iter().filter(|f| !is_supported(f, &source_figures)) {
+        if !unsupported.contains(&f.text) {
+            unsupported.push(f.text.clone());
+        }
+    }
+    if unsupported.is_empty() {
+        return Grounding { answer: answer.to_string(), checked: found.len(), unsupported };
+    }
+
+    let flagged = || format!("{}\n\n{}", answer.trim_end(), warning(lang, &unsupported.join(", ")));
+    let answer = match mode {
+        GroundingMode::Remove => {
+            let kept: String = sentences(answer)
+                .into_iter()
+                .filter(|s| extract(s).iter().all(|f| is_supported(f, &source_figures)))
+                .collect();
+            // Nothing useful left: keep the answer but warn instead
+            if kept.trim().is_empty() { flagged() } else { kept.trim_end().to_string() }
+        }
+        _ => flagged(),
+    };
+    Grounding { answer, checked: found.len(), unsupported }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSAGE: &str = "[1] Stem Borer: Apply Cartap hydrochloride 4G at 25kg/ha. Spray Spinosad 45SC at 0.3ml/L. \
+        [2] MSP: Wheat: ₹2,275/qtl. Transplanting age: 21-25 days.";
+
+    #[test]
+    fn test_extracts_units_ranges_and_devanagari_digits() {
+        let figures = extract("Use 25 kg per hectare, sell at Rs. 2,275 per quintal, transplant at 21 to 25 days, ५ मिली/लीटर");
+        let summary: Vec<(&str, f64, f64)> = figures.iter().map(|f| (f.unit, f.low, f.high)).collect();
+        assert!(summary.contains(&("kg/ha", 25.0, 25.0)));
+        assert!(summary.contains(&("₹", 2275.0, 2275.0)));
+        assert!(summary.contains(&("days", 21.0, 25.0)));
+        assert!(summary.contains(&("ml/L", 5.0, 5.0)));
+    }
+
+    #[test]
+    fn test_supported_figures_pass() {
+        let grounding = verify(
+            "Apply Cartap 4G at 25 kg/ha [1]. Transplant seedlings at 22-24 days.",
+            &[PASSAGE],
+            GroundingMode::Flag,
+            "en",
+        );
+        assert_eq!(grounding.checked, 2);
+        assert!(grounding.unsupported.is_empty());
+        assert_eq!(grounding.supported_ratio(), 1.0);
+    }
+
+    #[test]
+    fn test_unsupported_figures_are_flagged_or_removed() {
+        let answer = "Spray Spinosad at 3 ml/L. MSP for wheat is ₹2,275/qtl.";
+        let flagged = verify(answer, &[PASSAGE], GroundingMode::Flag, "en");
+        assert_eq!(flagged.unsupported, vec!["3 ml/L"]);
+        assert!(flagged.answer.starts_with(answer));
+        assert!(flagged.answer.contains("confirm them"));
+
+        let removed = verify(answer, &[PASSAGE], GroundingMode::Remove, "en");
+        assert_eq!(removed.answer, "MSP for wheat is ₹2,275/qtl.");
+        assert_eq!(removed.supported_ratio(), 0.5);
+    }
+
+    #[test]
+    fn test_removal_matches_whole_quantities() {
+        let answer = "Spray Spinosad at 0.3 ml/L. Never go up to 3 ml/L.";
+        let removed = verify(answer, &[PASSAGE], GroundingMode::Remove, "en");
+        assert_eq!(removed.unsupported, vec!["3 ml/L"]);
+        assert_eq!(removed.answer, "Spray Spinosad at 0.3 ml/L.");
+    }
+
+    #[test]
+    fn test_fallback_answer_passes_unchanged() {
+        use crate::rag::system_prompt::SystemPrompt;
+        use crate::services::ibm_granite::get_fallback_response;
+
+        let persona = SystemPrompt::builtin();
+        for lang in ["en", "hi", "mr"] {
+            let answer = get_fallback_response("wheat sowing time", PASSAGE, lang, &persona);
+            assert!(answer.contains("1551"));
+            let grounding = verify(&answer, &[PASSAGE], GroundingMode::Remove, lang);
+            assert_eq!(grounding.answer, answer);
+            assert!(grounding.unsupported.is_empty(), "{:?}", grounding.unsupported);
+        }
+    }
+
+    #[test]
+    fn test_bare_numbers_are_checked() {
+        let answer = "1. Apply Cartap 4G [1] in 2024.\n2. Repeat the spray 6 times, 21 days apart.";
+        let grounding = verify(answer, &[PASSAGE], GroundingMode::Flag, "en");
+        assert_eq!(grounding.checked, 2);
+        assert_eq!(grounding.unsupported, vec!["6"]);
+    }
+}
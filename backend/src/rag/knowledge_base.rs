@@ -1,7 +1,11 @@
 /// In-memory knowledge base for farming advice.
 /// Contains structured data about crops, weather, pest control, and market prices.
 
-#[derive(Clone, Debug)]
+use lazy_static::lazy_static;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+#[derive(Clone, Debug, Hash)]
 pub struct Document {
     pub title: String,
     pub content: String,
@@ -9,6 +13,37 @@ pub struct Document {
     pub source: String,
 }
 
+/// Public pages of the organisations behind each source, matched by prefix
+const SOURCE_URLS: &[(&str, &str)] = &[
+    ("ICAR", "https://icar.org.in"),
+    ("IMD", "https://mausam.imd.gov.in"),
+    ("TNAU", "https://agritech.tnau.ac.in"),
+    ("NHRDF", "https://nhrdf.org"),
+    ("DRR", "https://www.icar-iirr.org"),
+    ("IIHR", "https://www.iihr.res.in"),
+    ("Soil Health Card", "https://soilhealth.dac.gov.in"),
+    ("Ministry of Agriculture", "https://agriwelfare.gov.in"),
+    ("AgriMarket", "https://agmarknet.gov.in"),
+];
+
+/// Link for a document's `source`, when the publisher is known
+pub fn source_url(source: &str) -> Option<&'static str> {
+    SOURCE_URLS.iter().find(|(prefix, _)| source.starts_with(prefix)).map(|(_, url)| *url)
+}
+
+lazy_static! {
+    static ref REVISION: u64 = {
+        let mut hasher = DefaultHasher::new();
+        get_all_documents().hash(&mut hasher);
+        hasher.finish()
+    };
+}
+
+/// Hash of the current documents; answers cached under another revision are never served
+pub fn revision() -> u64 {
+    *REVISION
+}
+
 /// Get all documents from the knowledge base
 pub fn get_all_documents() -> Vec<Document> {
     vec![
@@ -97,6 +132,24 @@ pub fn get_all_documents() -> Vec<Document> {
             category: "pest_control".to_string(),
             source: "IIHR Bangalore".to_string(),
         },
+        Document {
+            title: "Early Blight in Tomato and Potato".to_string(),
+            content: "Early blight (Alternaria) shows as brown spots with concentric rings on older leaves, \
+                     often with a yellow halo; leaves dry and drop from the bottom up. Control: Remove and destroy \
+                     infected lower leaves. Spray Mancozeb 75WP at 2.5g/L, repeating after 10-15 days. \
+                     Avoid overhead irrigation. Rotate with non-solanaceous crops.".to_string(),
+            category: "pest_control".to_string(),
+            source: "ICAR Plant Protection".to_string(),
+        },
+        Document {
+            title: "Blast in Rice".to_string(),
+            content: "Rice blast shows as spindle-shaped spots with grey centres and brown margins on leaves; \
+                     neck blast turns the panicle neck black so grains stay empty. Control: Use resistant \
+                     varieties. Avoid excess nitrogen. Spray Tricyclazole 75WP at 0.6g/L at first symptoms. \
+                     Remove weed hosts from bunds.".to_string(),
+            category: "pest_control".to_string(),
+            source: "TNAU Agritech".to_string(),
+        },
 
         // Market Prices (Sample data - in real app, fetch from API)
         Document {
@@ -0,0 +1,225 @@
+//! Where and when a photo was taken, in farming terms.
+//! A GPS fix is mapped to the nearest district headquarters in a built-in table of major
+//! agricultural districts, and the capture month to the cropping season. Both go into the
+//! diagnosis prompt and nudge retrieval towards matching documents.
+
+use serde::{Deserialize, Serialize};
+
+use crate::services::photo_metadata::{GeoPoint, PhotoMetadata};
+
+/// Farther than this from every headquarters in the table, the district is left unknown
+const MAX_DISTRICT_KM: f64 = 80.0;
+
+/// Added to a retrieved document's score for each boost term it mentions
+pub const BOOST: f32 = 1.0;
+
+struct District {
+    name: &'static str,
+    state: &'static str,
+    latitude: f64,
+    longitude: f64,
+}
+
+const fn district(name: &'static str, state: &'static str, latitude: f64, longitude: f64) -> District {
+    District { name, state, latitude, longitude }
+}
+
+/// District headquarters, approximately
+const DISTRICTS: &[District] = &[
+    district("Pune", "Maharashtra", 18.52, 73.86),
+    district("Nashik", "Maharashtra", 19.99, 73.79),
+    district("Nagpur", "Maharashtra", 21.15, 79.09),
+    district("Chhatrapati Sambhajinagar", "Maharashtra", 19.88, 75.34),
+    district("Ahilyanagar", "Maharashtra", 19.09, 74.74),
+    district("Solapur", "Maharashtra", 17.66, 75.91),
+    district("Kolhapur", "Maharashtra", 16.70, 74.24),
+    district("Jalgaon", "Maharashtra", 21.00, 75.56),
+    district("Amravati", "Maharashtra", 20.93, 77.75),
+    district("Latur", "Maharashtra", 18.40, 76.56),
+    district("Satara", "Maharashtra", 17.68, 74.00),
+    district("Sangli", "Maharashtra", 16.85, 74.58),
+    district("Ludhiana", "Punjab", 30.90, 75.85),
+    district("Amritsar", "Punjab", 31.63, 74.87),
+    district("Bathinda", "Punjab", 30.21, 74.95),
+    district("Patiala", "Punjab", 30.34, 76.39),
+    district("Karnal", "Haryana", 29.69, 76.99),
+    district("Hisar", "Haryana", 29.15, 75.72),
+    district("Lucknow", "Uttar Pradesh", 26.85, 80.95),
+    district("Meerut", "Uttar Pradesh", 28.98, 77.71),
+    district("Varanasi", "Uttar Pradesh", 25.32, 82.97),
+    district("Agra", "Uttar Pradesh", 27.18, 78.01),
+    district("Gorakhpur", "Uttar Pradesh", 26.76, 83.37),
+    district("Indore", "Madhya Pradesh", 22.72, 75.86),
+    district("Bhopal", "Madhya Pradesh", 23.26, 77.41),
+    district("Jabalpur", "Madhya Pradesh", 23.18, 79.99),
+    district("Ahmedabad", "Gujarat", 23.02, 72.57),
+    district("Rajkot", "Gujarat", 22.30, 70.80),
+    district("Surat", "Gujarat", 21.17, 72.83),
+    district("Jaipur", "Rajasthan", 26.91, 75.79),
+    district("Kota", "Rajasthan", 25.21, 75.86),
+    district("Sri Ganganagar", "Rajasthan", 29.90, 73.88),
+    district("Belagavi", "Karnataka", 15.85, 74.50),
+    district("Dharwad", "Karnataka", 15.46, 75.01),
+    district("Mysuru", "Karnataka", 12.30, 76.64),
+    district("Raichur", "Karnataka", 16.21, 77.36),
+    district("Warangal", "Telangana", 17.97, 79.59),
+    district("Nizamabad", "Telangana", 18.67, 78.09),
+    district("Guntur", "Andhra Pradesh", 16.31, 80.44),
+    district("Krishna", "Andhra Pradesh", 16.19, 81.14),
+    district("Thanjavur", "Tamil Nadu", 10.79, 79.14),
+    district("Coimbatore", "Tamil Nadu", 11.02, 76.96),
+    district("Madurai", "Tamil Nadu", 9.93, 78.12),
+    district("Patna", "Bihar", 25.59, 85.14),
+    district("Muzaffarpur", "Bihar", 26.12, 85.39),
+    district("Purba Bardhaman", "West Bengal", 23.23, 87.86),
+    district("Cuttack", "Odisha", 20.46, 85.88),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Season {
+    /// June-October, monsoon sown
+    Kharif,
+    /// November-March, winter sown
+    Rabi,
+    /// April-May, summer
+    Zaid,
+}
+
+impl Season {
+    pub fn from_month(month: u32) -> Option<Self> {
+        match month {
+            6..=10 => Some(Season::Kharif),
+            11 | 12 | 1..=3 => Some(Season::Rabi),
+            4 | 5 => Some(Season::Zaid),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Season::Kharif => "kharif",
+            Season::Rabi => "rabi",
+            Season::Zaid => "zaid",
+        }
+    }
+
+    /// How the knowledge base refers to the season
+    fn terms(self) -> &'static [&'static str] {
+        match self {
+            Season::Kharif => &["kharif", "monsoon"],
+            Season::Rabi => &["rabi", "winter"],
+            Season::Zaid => &["zaid", "summer"],
+        }
+    }
+}
+
+/// Location and season inferred from a photo
+#[derive(Clone, Debug, Serialize)]
+pub struct PhotoContext {
+    /// Rounded to two decimals (about 1 km) so the farm itself isn't pinpointed
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub district: Option<String>,
+    pub state: Option<String>,
+    pub season: Option<Season>,
+    pub taken_on: Option<String>,
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn distance_km(a: GeoPoint, latitude: f64, longitude: f64) -> f64 {
+    let (lat1, lat2) = (a.latitude.to_radians(), latitude.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (longitude - a.longitude).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * 6371.0 * h.sqrt().asin()
+}
+
+/// Every district and state in the table
+pub fn place_names() -> impl Iterator<Item = &'static str> {
+    DISTRICTS.iter().flat_map(|d| [d.name, d.state])
+}
+
+fn nearest_district(point: GeoPoint) -> Option<&'static District> {
+    DISTRICTS
+        .iter()
+        .map(|d| (distance_km(point, d.latitude, d.longitude), d))
+        .filter(|(km, _)| *km <= MAX_DISTRICT_KM)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, d)| d)
+}
+
+impl PhotoContext {
+    /// `None` when the photo says nothing about where or when it was taken
+    pub fn from_metadata(metadata: &PhotoMetadata) -> Option<Self> {
+        if metadata.is_empty() {
+            return None;
+        }
+        let district = metadata.location.and_then(nearest_district);
+        Some(Self {
+            latitude: metadata.location.map(|p| round(p.latitude)),
+            longitude: metadata.location.map(|p| round(p.longitude)),
+            district: district.map(|d| d.name.to_string()),
+            state: district.map(|d| d.state.to_string()),
+            season: metadata.month.and_then(Season::from_month),
+            taken_on: metadata.taken_on.clone(),
+        })
+    }
+
+    /// One sentence for the prompt, e.g. "The photo was taken near Nashik, Maharashtra on
+    /// 2024-07-14, in the kharif season."; empty when nothing useful is known
+    pub fn describe(&self) -> String {
+        let place = match (&self.district, &self.state) {
+            (Some(district), Some(state)) => format!(" near {}, {}", district, state),
+            _ => String::new(),
+        };
+        let date = self.taken_on.as_ref().map(|d| format!(" on {}", d)).unwrap_or_default();
+        let season = self.season.map(|s| format!(", in the {} season", s.as_str())).unwrap_or_default();
+        if place.is_empty() && date.is_empty() {
+            return String::new();
+        }
+        format!("The photo was taken{}{}{}.", place, date, season)
+    }
+
+    /// Lowercase words that mark a document as relevant to this place and season
+    pub fn boost_terms(&self) -> Vec<String> {
+        let mut terms: Vec<String> = self.season.map(|s| s.terms().iter().map(|t| t.to_string()).collect()).unwrap_or_default();
+        if let Some(state) = &self.state {
+            terms.push(state.to_lowercase());
+        }
+        terms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_district_and_season() {
+        let metadata = PhotoMetadata {
+            location: Some(GeoPoint { latitude: 20.0123, longitude: 73.7456 }),
+            taken_on: Some("2024-07-14".to_string()),
+            month: Some(7),
+        };
+        let context = PhotoContext::from_metadata(&metadata).unwrap();
+        assert_eq!(context.district.as_deref(), Some("Nashik"));
+        assert_eq!(context.latitude, Some(20.01));
+        assert_eq!(context.season, Some(Season::Kharif));
+        assert_eq!(context.describe(), "The photo was taken near Nashik, Maharashtra on 2024-07-14, in the kharif season.");
+        assert_eq!(context.boost_terms(), vec!["kharif", "monsoon", "maharashtra"]);
+
+        // Mid-ocean: no district, but the date still counts
+        let far = PhotoMetadata {
+            location: Some(GeoPoint { latitude: 10.0, longitude: 65.0 }),
+            taken_on: Some("2024-01-05".to_string()),
+            month: Some(1),
+        };
+        let context = PhotoContext::from_metadata(&far).unwrap();
+        assert_eq!(context.district, None);
+        assert_eq!(context.describe(), "The photo was taken on 2024-01-05, in the rabi season.");
+    }
+}
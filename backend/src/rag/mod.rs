@@ -1,3 +1,15 @@
 pub mod retriever;
+pub mod rewriter;
+pub mod agent;
+pub mod budget;
+pub mod citations;
+pub mod confidence;
+pub mod diagnosis;
 pub mod generator;
+pub mod grounding;
+pub mod locality;
+pub mod structured;
 pub mod knowledge_base;
+pub mod system_prompt;
+pub mod templates;
+pub mod tokens;
@@ -1,14 +1,46 @@
 use tracing::debug;
 
 use super::knowledge_base::{get_all_documents, Document};
+use super::locality;
+
+/// Words that match almost every document and would inflate scores
+pub const STOPWORDS: &[&str] = &[
+    "a", "an", "the", "is", "are", "was", "be", "of", "in", "on", "at", "to", "for", "and", "or",
+    "it", "this", "that", "with", "i", "my", "me", "we", "you", "do", "does", "can", "should",
+    "what", "which", "when", "how", "about", "there", "please", "tell",
+];
+
+/// A retrieved document with its keyword score
+#[derive(Clone, Debug)]
+pub struct Passage {
+    pub doc: Document,
+    pub score: f32,
+}
+
+impl Passage {
+    /// Text given to the model, numbered so the answer can cite it as `[id]`
+    pub fn cited_text(&self, id: usize) -> String {
+        format!("[{}] {}: {}", id, self.doc.title, self.doc.content)
+    }
+}
 
 /// Simple TF-IDF-like retriever that finds relevant documents based on keyword matching.
 /// For production, this should be replaced with a proper vector DB like Qdrant.
+/// Returns the top 3 passages, best first.
+pub async fn retrieve(query: &str) -> Vec<Passage> {
+    retrieve_boosted(query, &[]).await
+}
 
-pub async fn retrieve(query: &str) -> (Vec<String>, Vec<String>) {
+/// `retrieve`, with documents that already match the query ranked up for each of `boost_terms`
+/// (lowercase season or state names from a photo) they mention
+pub async fn retrieve_boosted(query: &str, boost_terms: &[String]) -> Vec<Passage> {
     let documents = get_all_documents();
     let query_lower = query.to_lowercase();
-    let query_terms: Vec<&str> = query_lower.split_whitespace().collect();
+    let query_terms: Vec<&str> = query_lower
+        .split_whitespace()
+        .map(|t| t.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|t| t.chars().count() > 1 && !STOPWORDS.contains(t))
+        .collect();
     
     // Score each document based on term frequency
     let mut scored_docs: Vec<(f32, &Document)> = documents
@@ -28,6 +60,10 @@ pub async fn retrieve(query: &str) -> (Vec<String>, Vec<String>) {
                 
                 score += content_matches + title_matches + category_match;
             }
+            if score > 0.0 {
+                let text = format!("{} {}", title_lower, content_lower);
+                score += boost_terms.iter().filter(|t| text.contains(t.as_str())).count() as f32 * locality::BOOST;
+            }
             
             (score, doc)
         })
@@ -38,14 +74,19 @@ pub async fn retrieve(query: &str) -> (Vec<String>, Vec<String>) {
     scored_docs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
 
     // Take top 3 results
-    let top_docs: Vec<&Document> = scored_docs.iter().take(3).map(|(_, doc)| *doc).collect();
-
-    debug!("Retrieved {} documents for query: '{}'", top_docs.len(), query);
+    let top_docs: Vec<Passage> = scored_docs
+        .into_iter()
+        .take(3)
+        .map(|(score, doc)| Passage { doc: doc.clone(), score })
+        .collect();
 
-    let contents: Vec<String> = top_docs.iter().map(|d| format!("[{}] {}", d.title, d.content)).collect();
-    let sources: Vec<String> = top_docs.iter().map(|d| d.source.clone()).collect();
+    // The query may be a transcribed voice note, so only its length is logged
+    debug!(
+        "Retrieved {} documents for a {}-char query (scores {:?})",
+        top_docs.len(), query.chars().count(), top_docs.iter().map(|p| p.score).collect::<Vec<_>>()
+    );
 
-    (contents, sources)
+    top_docs
 }
 
 /// Alternative keyword-based retrieval for specific farming topics
@@ -56,3 +97,25 @@ pub fn retrieve_by_category(category: &str) -> Vec<Document> {
         .filter(|doc| doc.category.to_lowercase() == category.to_lowercase())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn titles(query: &str) -> Vec<String> {
+        retrieve(query).await.into_iter().map(|p| p.doc.title).collect()
+    }
+
+    #[tokio::test]
+    async fn test_crop_guides_stay_on_top() {
+        assert_eq!(titles("How do I grow tomato?").await[0], "Tomato Farming");
+        assert_eq!(titles("rice paddy cultivation and transplanting").await[0], "Rice Paddy Cultivation");
+        assert_eq!(titles("When should I sow wheat?").await[0], "Wheat Cultivation - Rabi Season");
+    }
+
+    #[tokio::test]
+    async fn test_disease_questions_find_pest_documents() {
+        assert_eq!(titles("early blight on tomato leaves").await[0], "Early Blight in Tomato and Potato");
+        assert_eq!(titles("rice blast spots").await[0], "Blast in Rice");
+    }
+}
@@ -0,0 +1,245 @@
+//! Follow-up rewriting: condenses a follow-up plus conversation history into a
+//! standalone query for retrieval, e.g. "and how much water?" after a wheat
+//! question becomes "how much water for wheat?".
+//! Uses the text model chain when `QUERY_REWRITE_MODE=llm` (default) and falls
+//! back to simple rules when the model is unavailable or `QUERY_REWRITE_MODE=rules`.
+
+use std::env;
+use std::time::Duration;
+use tracing::{debug, info};
+
+use crate::services::generation::{GenerationParams, ResponseMode};
+use crate::services::llm::LlmRequest;
+use crate::services::sessions::{Role, Turn};
+use crate::state::AppState;
+use crate::utils::logger::query_text;
+
+const REWRITE_INSTRUCTION: &str = "You rewrite follow-up questions from a farming chat into standalone questions. \
+Reply with ONLY the rewritten question in English, on one line, keeping every crop, place and quantity it refers to.";
+
+/// Crops and topics a follow-up can refer back to, with Devanagari spellings
+const SUBJECTS: &[(&str, &[&str])] = &[
+    ("wheat", &["गेहूं", "गहू"]),
+    ("rice", &["धान", "चावल", "भात"]),
+    ("paddy", &[]),
+    ("onion", &["प्याज", "कांदा"]),
+    ("tomato", &["टमाटर", "टोमॅटो"]),
+    ("potato", &["आलू", "बटाटा"]),
+    ("cotton", &["कपास", "कापूस"]),
+    ("soybean", &["सोयाबीन"]),
+    ("sugarcane", &["गन्ना", "ऊस"]),
+    ("gram", &["चना", "हरभरा"]),
+    ("mustard", &["सरसों", "मोहरी"]),
+    ("cucumber", &["खीरा", "काकडी"]),
+    ("okra", &["भिंडी", "भेंडी"]),
+    ("maize", &["मक्का", "मका"]),
+];
+
+const LEADING_CONNECTIVES: &[&str] = &["what about", "how about", "and", "also", "then", "so", "ok", "okay"];
+const PRONOUNS: &[&str] = &["it", "its", "this", "that", "them", "they", "these", "those"];
+
+pub struct Rewrite {
+    pub query: String,
+    /// "none", "llm" or "rules"
+    pub method: &'static str,
+}
+
+/// `transcribed` keeps a spoken follow-up and its rewrite out of the logs
+pub async fn standalone_query(state: &AppState, query: &str, history: &[Turn], transcribed: bool) -> Rewrite {
+    if history.is_empty() {
+        return Rewrite { query: query.to_string(), method: "none" };
+    }
+
+    let mode = env::var("QUERY_REWRITE_MODE").unwrap_or_else(|_| "llm".to_string());
+    // A complete question needs no rewrite, so don't make the farmer wait for one
+    if mode == "off" || !looks_like_follow_up(query) {
+        return Rewrite { query: query.to_string(), method: "none" };
+    }
+
+    if mode == "llm" {
+        if let Some(rewritten) = rewrite_with_llm(state, query, history, transcribed).await {
+            info!("Rewrote follow-up {} -> {} (llm)", query_text(query, transcribed), query_text(&rewritten, transcribed));
+            return Rewrite { query: rewritten, method: "llm" };
+        }
+    }
+
+    match rewrite_with_rules(query, history) {
+        Some(rewritten) => {
+            info!("Rewrote follow-up {} -> {} (rules)", query_text(query, transcribed), query_text(&rewritten, transcribed));
+            Rewrite { query: rewritten, method: "rules" }
+        }
+        None => Rewrite { query: query.to_string(), method: "none" },
+    }
+}
+
+async fn rewrite_with_llm(state: &AppState, query: &str, history: &[Turn], transcribed: bool) -> Option<String> {
+    let transcript = history.iter()
+        .map(|t| format!("{}: {}", if t.role == Role::User { "Farmer" } else { "Assistant" }, t.content))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let request = LlmRequest {
+        system_prompt: REWRITE_INSTRUCTION.to_string(),
+        history: Vec::new(),
+        query: format!("Conversation:\n{}\n\nFollow-up: {}\n\nStandalone question:", transcript, query),
+        passages: Vec::new(),
+        image: None,
+        target_lang: "en".to_string(),
+        mode: ResponseMode::Normal,
+        params: GenerationParams { max_tokens: 80, temperature: 0.2, ..GenerationParams::default() },
+        output_schema: None,
+        tracker: None,
+    };
+
+    let timeout = env::var("QUERY_REWRITE_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(8);
+    let answer = state.text_chain
+        .generate_within(&state.http, &request, Duration::from_secs(timeout))
+        .await
+        .ok()?;
+
+    // Models sometimes add a preamble; take the first non-empty line and reject anything essay-like
+    let line = answer.text.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let line = line.trim_start_matches("Standalone question:").trim().trim_matches('"');
+    if line.is_empty() || line.len() > 300 {
+        debug!("Discarding LLM rewrite: {}", query_text(&answer.text, transcribed));
+        return None;
+    }
+    Some(line.to_string())
+}
+
+/// English names of the crops a follow-up can refer back to
+pub fn crop_names() -> impl Iterator<Item = &'static str> {
+    SUBJECTS.iter().map(|(name, _)| *name)
+}
+
+/// English name of the first subject mentioned in `text`
+fn find_subject(text: &str) -> Option<&'static str> {
+    let lower = text.to_lowercase();
+    let words: Vec<&str> = lower.split(|c: char| !c.is_alphanumeric()).collect();
+    SUBJECTS.iter()
+        .find(|(name, local)| {
+            words.iter().any(|w| *w == *name || w.strip_suffix('s') == Some(name))
+                || local.iter().any(|l| text.contains(l))
+        })
+        .map(|(name, _)| *name)
+}
+
+fn strip_connectives(query: &str) -> String {
+    let mut rest = query.trim().trim_end_matches('?').trim().to_string();
+    loop {
+        let lower = rest.to_lowercase();
+        let Some(c) = LEADING_CONNECTIVES.iter().find(|c| lower.starts_with(&format!("{} ", c)) || lower == **c) else {
+            break;
+        };
+        rest = rest[c.len()..].trim_start_matches([' ', ',']).to_string();
+    }
+    rest
+}
+
+/// Whether `query` leans on the conversation: it opens with "and", "what about" and the
+/// like, refers back with a pronoun, or is too short to stand alone. A name of its own
+/// ("Kisan Credit Card", "PM-KISAN") makes it standalone unless it refers back.
+pub fn looks_like_follow_up(query: &str) -> bool {
+    let trimmed = query.trim().trim_end_matches('?').trim();
+    let stripped = strip_connectives(trimmed);
+    let words: Vec<&str> = stripped.split_whitespace().collect();
+    let refers_back = words.iter()
+        .any(|w| PRONOUNS.contains(&w.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase().as_str()));
+    let named = words.iter().skip(1).any(|w| w.starts_with(|c: char| c.is_uppercase()));
+    refers_back || (!named && (stripped.len() < trimmed.len() || words.len() <= 4))
+}
+
+/// Rule-based rewrite. Returns `None` when the query already looks standalone.
+pub fn rewrite_with_rules(query: &str, history: &[Turn]) -> Option<String> {
+    let previous_question = history.iter().rev().find(|t| t.role == Role::User)?;
+    let previous_subject = history.iter().rev().find_map(|t| find_subject(&t.content))?;
+    let stripped = strip_connectives(query);
+
+    if let Some(new_subject) = find_subject(query) {
+        if new_subject == previous_subject {
+            return None;
+        }
+        // "what about for onion?" -> previous question with the subject swapped
+        let is_bare_subject = stripped
+            .split_whitespace()
+            .filter(|w| !matches!(w.to_lowercase().as_str(), "for" | "in" | "the" | "of"))
+            .count() <= 2;
+        let re = regex::Regex::new(&format!(r"(?i)\b{}s?\b", previous_subject)).ok()?;
+        if is_bare_subject && re.is_match(&previous_question.content) {
+            return Some(re.replace_all(&previous_question.content, new_subject).to_string());
+        }
+        return None;
+    }
+
+    // No crop of its own: resolve pronouns, or attach the previous subject to a follow-up
+    if !looks_like_follow_up(query) {
+        return None;
+    }
+    let mut replaced = false;
+    let words: Vec<String> = stripped.split_whitespace()
+        .map(|w| {
+            if PRONOUNS.contains(&w.to_lowercase().as_str()) {
+                replaced = true;
+                previous_subject.to_string()
+            } else {
+                w.to_string()
+            }
+        })
+        .collect();
+    let mut rewritten = words.join(" ");
+    if rewritten.is_empty() {
+        return None;
+    }
+    if !replaced {
+        rewritten = format!("{} for {}", rewritten, previous_subject);
+    }
+    Some(format!("{}?", rewritten))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wheat_history() -> Vec<Turn> {
+        vec![
+            Turn::new(Role::User, "When should I sow wheat?"),
+            Turn::new(Role::Assistant, "Sow wheat from October to November."),
+        ]
+    }
+
+    #[test]
+    fn test_attaches_previous_subject() {
+        assert_eq!(
+            rewrite_with_rules("and how much water?", &wheat_history()).as_deref(),
+            Some("how much water for wheat?")
+        );
+        assert_eq!(
+            rewrite_with_rules("which pests attack it?", &wheat_history()).as_deref(),
+            Some("which pests attack wheat?")
+        );
+    }
+
+    #[test]
+    fn test_swaps_subject_for_bare_follow_up() {
+        assert_eq!(
+            rewrite_with_rules("what about for onion?", &wheat_history()).as_deref(),
+            Some("When should I sow onion?")
+        );
+    }
+
+    #[test]
+    fn test_standalone_question_is_left_alone() {
+        assert_eq!(rewrite_with_rules("how do I store onions in the monsoon?", &wheat_history()), None);
+        assert_eq!(rewrite_with_rules("and wheat?", &wheat_history()), None);
+        assert_eq!(rewrite_with_rules("anything", &[]), None);
+        assert_eq!(rewrite_with_rules("How do I get a Kisan Credit Card?", &wheat_history()), None);
+        assert_eq!(rewrite_with_rules("What is the current repo rate for farm loans in India?", &wheat_history()), None);
+        assert!(!looks_like_follow_up("How do I get a Kisan Credit Card?"));
+        assert!(looks_like_follow_up("and how much water?"));
+    }
+
+    #[test]
+    fn test_devanagari_subject_in_history() {
+        let history = vec![Turn::new(Role::User, "गेहूं की बुवाई कब करें?")];
+        assert_eq!(rewrite_with_rules("and fertilizer?", &history).as_deref(), Some("fertilizer for wheat?"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Structured (JSON) output.
+//! The model is asked for a JSON object matching a schema; the reply is parsed, validated
+//! against the schema and deserialized into a typed result. Invalid replies are sent back
+//! with the validation error for another try, up to `STRUCTURED_MAX_ATTEMPTS` in total.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use tracing::warn;
+
+use crate::services::error::LlmError;
+use crate::services::http::HttpClient;
+use crate::services::json_schema;
+use crate::services::llm::LlmRequest;
+use crate::services::llm_chain::LlmChain;
+
+/// A result type the model can be asked to produce
+pub trait OutputSchema: DeserializeOwned {
+    /// JSON schema given to the model and checked before deserializing
+    fn schema() -> Value;
+
+    /// What the result advises, one sentence per claim, for the grounding and citation checks
+    fn claims(&self) -> Vec<String>;
+}
+
+/// A validated result and how it was obtained
+pub struct Structured<T> {
+    pub value: T,
+    pub answered_by: String,
+    /// Model calls it took, 1 when the first reply was valid
+    pub attempts: u32,
+    /// Leading passages of the request that fit in the prompt
+    pub passages_used: usize,
+}
+
+/// `STRUCTURED_MAX_ATTEMPTS` (default 3)
+pub fn max_attempts_from_env() -> u32 {
+    env::var("STRUCTURED_MAX_ATTEMPTS").ok().and_then(|v| v.parse().ok()).unwrap_or(3).max(1)
+}
+
+/// Longest previous reply quoted back to the model on a retry
+const MAX_QUOTED_CHARS: usize = 1500;
+
+/// Ask `chain` for a `T`, retrying invalid replies
+pub async fn generate<T: OutputSchema>(
+    chain: &LlmChain,
+    http: &HttpClient,
+    request: &LlmRequest,
+    max_attempts: u32,
+) -> Result<Structured<T>, LlmError> {
+    let schema = T::schema();
+    let mut structured = request.clone();
+    structured.system_prompt = format!(
+        "{}\n\nReply with only a JSON object that matches this JSON schema, with no other text:\n{}\n\
+         Keys and enum values stay in English; write free-text values in the requested language.",
+        request.system_prompt, schema
+    );
+    structured.output_schema = Some(schema.clone());
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let answer = chain.generate(http, &structured).await?;
+        let error = match parse(&answer.text, &schema) {
+            Ok(value) => {
+                return Ok(Structured { value, answered_by: answer.answered_by, attempts: attempt, passages_used: answer.passages_used });
+            }
+            Err(e) => e,
+        };
+        warn!("Structured output attempt {}/{} invalid: {}", attempt, max_attempts, error);
+        if attempt >= max_attempts {
+            return Err(LlmError::InvalidOutput { attempts: attempt, message: error });
+        }
+        let quoted: String = answer.text.chars().take(MAX_QUOTED_CHARS).collect();
+        structured.query = format!(
+            "{}\n\nYour previous reply was:\n{}\nIt was rejected: {}. Reply again with only the corrected JSON object.",
+            request.query, quoted, error
+        );
+    }
+}
+
+/// The JSON object in a reply, allowing for a code fence or a sentence around it
+fn extract_json(text: &str) -> Option<&str> {
+    let start = text.find('{')?;
+    let end = text.rfind('}')?;
+    (start < end).then(|| &text[start..=end])
+}
+
+fn parse<T: DeserializeOwned>(text: &str, schema: &Value) -> Result<T, String> {
+    let json = extract_json(text).ok_or("no JSON object found")?;
+    let value: Value = serde_json::from_str(json).map_err(|e| format!("not valid JSON ({})", e))?;
+    json_schema::validate(schema, &value)?;
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+/// Season-by-stage plan for one crop
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CropPlan {
+    pub crop: String,
+    pub season: String,
+    pub stages: Vec<CropStage>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CropStage {
+    pub stage: String,
+    /// e.g. "October, weeks 2-3" or "21 days after sowing"
+    pub timing: String,
+    pub tasks: Vec<String>,
+}
+
+impl OutputSchema for CropPlan {
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "crop": { "type": "string", "minLength": 1 },
+                "season": { "type": "string", "minLength": 1 },
+                "stages": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "stage": { "type": "string", "minLength": 1 },
+                            "timing": { "type": "string" },
+                            "tasks": { "type": "array", "items": { "type": "string" } }
+                        },
+                        "required": ["stage", "timing", "tasks"]
+                    }
+                }
+            },
+            "required": ["crop", "season", "stages"]
+        })
+    }
+
+    fn claims(&self) -> Vec<String> {
+        self.stages
+            .iter()
+            .flat_map(|stage| {
+                std::iter::once(format!("{}: {}.", stage.stage, stage.timing))
+                    .chain(stage.tasks.iter().map(|task| format!("{}.", task.trim_end_matches('.'))))
+            })
+            .collect()
+    }
+}
+
+/// Split fertilizer doses over the season
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FertilizerSchedule {
+    pub crop: String,
+    pub area_unit: String,
+    pub applications: Vec<FertilizerApplication>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FertilizerApplication {
+    pub timing: String,
+    pub fertilizer: String,
+    /// Per `area_unit`
+    pub quantity_kg: f64,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+impl OutputSchema for FertilizerSchedule {
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "crop": { "type": "string", "minLength": 1 },
+                "area_unit": { "enum": ["acre", "hectare"] },
+                "applications": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "timing": { "type": "string", "minLength": 1 },
+                            "fertilizer": { "type": "string", "minLength": 1 },
+                            "quantity_kg": { "type": "number", "minimum": 0 },
+                            "notes": { "type": ["string", "null"] }
+                        },
+                        "required": ["timing", "fertilizer", "quantity_kg"]
+                    }
+                }
+            },
+            "required": ["crop", "area_unit", "applications"]
+        })
+    }
+
+    fn claims(&self) -> Vec<String> {
+        self.applications
+            .iter()
+            .map(|a| {
+                let notes = a.notes.as_deref().map(|n| format!(" {}", n)).unwrap_or_default();
+                format!("{}: {} kg/{} of {}.{}", a.timing, a.quantity_kg, self.area_unit, a.fertilizer, notes)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{http, llm_request, mock_chain, replies};
+
+    fn chain(script: &[&str]) -> LlmChain {
+        mock_chain("json", replies(script))
+    }
+
+    fn request() -> LlmRequest {
+        llm_request("Fertilizer schedule for wheat")
+    }
+
+    const SCHEDULE: &str = r#"Here is the schedule:
+```json
+{"crop": "wheat", "area_unit": "acre", "applications": [
+  {"timing": "At sowing", "fertilizer": "DAP", "quantity_kg": 50},
+  {"timing": "First irrigation (21 days)", "fertilizer": "Urea", "quantity_kg": 45, "notes": "Before irrigation"}
+]}
+```"#;
+
+    #[tokio::test]
+    async fn test_valid_reply_is_typed() {
+        let result = generate::<FertilizerSchedule>(&chain(&[SCHEDULE]), &http(), &request(), 3).await.unwrap();
+        assert_eq!(result.attempts, 1);
+        assert_eq!(result.answered_by, "mock:json");
+        assert_eq!(result.value.applications.len(), 2);
+        assert_eq!(result.value.applications[1].notes.as_deref(), Some("Before irrigation"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_reply_is_retried() {
+        let wrong_unit = r#"{"crop": "wheat", "area_unit": "bigha", "applications": []}"#;
+        let chain = chain(&["Apply DAP at sowing.", wrong_unit, SCHEDULE]);
+        let result = generate::<FertilizerSchedule>(&chain, &http(), &request(), 3).await.unwrap();
+        assert_eq!(result.attempts, 3);
+        assert_eq!(result.value.crop, "wheat");
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let plan = r#"{"crop": "wheat", "season": "rabi", "stages": [{"stage": "Sowing", "tasks": []}]}"#;
+        let err = generate::<CropPlan>(&chain(&[plan, plan]), &http(), &request(), 2).await.err().unwrap();
+        match err {
+            LlmError::InvalidOutput { attempts, message } => {
+                assert_eq!(attempts, 2);
+                assert_eq!(message, "$.stages[0].timing is required");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+}
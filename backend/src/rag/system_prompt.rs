@@ -0,0 +1,133 @@
+//! Versioned, file-based system prompts.
+//! Each deployment picks a persona profile with `PROMPT_PROFILE` (a TOML file under `prompts/`),
+//! so branding and scope rules change without a rebuild. The `id@version` label is logged per request.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::services::error::LlmError;
+
+const BUILTIN: &str = include_str!("../../prompts/kisanai.toml");
+
+#[derive(Debug, Deserialize)]
+pub struct SystemPrompt {
+    pub id: String,
+    pub version: String,
+    persona: String,
+    #[serde(default)]
+    scope_rules: Vec<String>,
+    /// Greeting per language code, used when a query is just "hello"
+    #[serde(default)]
+    greetings: HashMap<String, String>,
+    #[serde(skip)]
+    rendered: String,
+}
+
+impl SystemPrompt {
+    pub fn parse(src: &str) -> Result<Self, toml::de::Error> {
+        let mut prompt: SystemPrompt = toml::from_str(src)?;
+        prompt.rendered = if prompt.scope_rules.is_empty() {
+            prompt.persona.trim().to_string()
+        } else {
+            let rules = prompt.scope_rules.iter()
+                .map(|r| format!("- {}", r))
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("{}\n\nSCOPE RULES:\n{}", prompt.persona.trim(), rules)
+        };
+        Ok(prompt)
+    }
+
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN).expect("built-in system prompt is valid")
+    }
+
+    /// Load `PROMPT_PROFILE`. When it is unset, `prompts/kisanai.toml` is tried and the built-in
+    /// profile used if that is missing; a profile that was asked for by name must load.
+    pub fn from_env() -> Result<Self, LlmError> {
+        Self::load(env::var("PROMPT_PROFILE").ok().filter(|p| !p.is_empty()).as_deref())
+    }
+
+    fn load(profile: Option<&str>) -> Result<Self, LlmError> {
+        let path = profile.unwrap_or("prompts/kisanai.toml");
+        let loaded = fs::read_to_string(Path::new(path))
+            .map_err(|e| e.to_string())
+            .and_then(|src| Self::parse(&src).map_err(|e| e.to_string()));
+
+        match loaded {
+            Ok(prompt) => {
+                info!("Loaded system prompt {} from {}", prompt.label(), path);
+                Ok(prompt)
+            }
+            Err(e) if profile.is_some() => {
+                Err(LlmError::Config(format!("PROMPT_PROFILE {} could not be loaded: {}", path, e)))
+            }
+            Err(e) => {
+                warn!("Could not load system prompt from {} ({}), using built-in", path, e);
+                Ok(Self::builtin())
+            }
+        }
+    }
+
+    /// Persona plus scope rules, ready for the template's `{system}` slot
+    pub fn text(&self) -> &str {
+        &self.rendered
+    }
+
+    /// `id@version`, logged with every request
+    pub fn label(&self) -> String {
+        format!("{}@{}", self.id, self.version)
+    }
+
+    pub fn greeting(&self, lang: &str) -> Option<&str> {
+        self.greetings.get(lang).or_else(|| self.greetings.get("en")).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_rules_are_appended() {
+        let prompt = SystemPrompt::parse(r#"
+            id = "agri"
+            version = "3"
+            persona = "You are Krishi Sahayak."
+            scope_rules = ["Only answer farming questions."]
+            [greetings]
+            en = "Hello from the FPO"
+        "#).unwrap();
+
+        assert_eq!(prompt.label(), "agri@3");
+        assert_eq!(prompt.text(), "You are Krishi Sahayak.\n\nSCOPE RULES:\n- Only answer farming questions.");
+        assert_eq!(prompt.greeting("mr"), Some("Hello from the FPO"));
+    }
+
+    #[test]
+    fn test_shipped_profiles_parse() {
+        let prompt = SystemPrompt::builtin();
+        assert_eq!(prompt.id, "kisanai");
+        assert!(prompt.text().starts_with("You are KisanAI"));
+
+        let agri = SystemPrompt::parse(include_str!("../../prompts/agri-only.toml")).unwrap();
+        assert!(agri.text().contains("SCOPE RULES"));
+    }
+
+    #[test]
+    fn test_named_profile_must_load() {
+        let err = SystemPrompt::load(Some("/nonexistent/agri-only.toml")).unwrap_err();
+        assert!(matches!(err, LlmError::Config(_)));
+
+        let dir = env::temp_dir().join(format!("kisan-prompt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let broken = dir.join("agri.toml");
+        fs::write(&broken, "id = \"agri\"\npersona = unquoted").unwrap();
+        assert!(SystemPrompt::load(broken.to_str()).is_err());
+        fs::remove_dir_all(dir).ok();
+    }
+}
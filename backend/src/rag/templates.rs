@@ -0,0 +1,327 @@
+//! Prompt template registry keyed by model family.
+//! Templates live in `templates/*.toml` (override the directory with `PROMPT_TEMPLATE_DIR`)
+//! so role tokens and stop sequences can be edited without touching code.
+//! Built-in copies are compiled in and used when a file is missing; a file that cannot be
+//! read or parsed, or that uses a slot its field does not fill, stops startup.
+
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use super::tokens::Tokenizer;
+use crate::services::error::LlmError;
+use crate::services::sessions::{Role, Turn};
+
+const BUILTIN: &[&str] = &[
+    include_str!("../../templates/granite.toml"),
+    include_str!("../../templates/llava.toml"),
+    include_str!("../../templates/llama3.toml"),
+    include_str!("../../templates/default.toml"),
+];
+
+/// Unknown keys are rejected so a misspelt or unsupported slot fails loudly instead of
+/// being silently ignored
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PromptTemplate {
+    pub family: String,
+    /// Model-id substrings that select this template
+    #[serde(default, rename = "match")]
+    pub matches: Vec<String>,
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    /// Total tokens (prompt + answer) the model accepts
+    #[serde(default = "default_context_window")]
+    pub context_window: usize,
+    #[serde(default = "default_latin_ratio")]
+    latin_chars_per_token: f32,
+    #[serde(default = "default_other_ratio")]
+    other_chars_per_token: f32,
+    #[serde(default)]
+    pub prefix: String,
+    pub system: String,
+    pub user: String,
+    /// Earlier assistant turns in the conversation history
+    pub assistant: String,
+    pub generation_prompt: String,
+    pub question: String,
+    pub context: String,
+}
+
+fn default_context_window() -> usize {
+    4096
+}
+
+fn default_latin_ratio() -> f32 {
+    Tokenizer::default().latin_chars_per_token
+}
+
+fn default_other_ratio() -> f32 {
+    Tokenizer::default().other_chars_per_token
+}
+
+/// Values for a template's slots
+pub struct PromptParts<'a> {
+    pub system: &'a str,
+    /// Earlier conversation turns, oldest first
+    pub history: &'a [Turn],
+    pub context: &'a str,
+    pub question: &'a str,
+    pub language_instruction: &'a str,
+}
+
+impl PromptTemplate {
+    /// Each field with the slots `render` fills in it
+    fn fields(&self) -> [(&'static str, &str, &'static [&'static str]); 7] {
+        [
+            ("prefix", &self.prefix, &[]),
+            ("system", &self.system, &["system"]),
+            ("user", &self.user, &["content"]),
+            ("assistant", &self.assistant, &["content"]),
+            ("generation_prompt", &self.generation_prompt, &[]),
+            ("question", &self.question, &["context", "question", "language_instruction"]),
+            ("context", &self.context, &["context"]),
+        ]
+    }
+
+    /// Rejects a `{slot}` that its field never fills, so it cannot reach the model verbatim
+    fn check_slots(&self) -> Result<(), String> {
+        for (field, template, allowed) in self.fields() {
+            if let Some(slot) = placeholders(template).find(|slot| !allowed.contains(slot)) {
+                return Err(format!("unknown slot {{{}}} in `{}` (allowed: {:?})", slot, field, allowed));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tokenizer(&self) -> Tokenizer {
+        Tokenizer {
+            latin_chars_per_token: self.latin_chars_per_token,
+            other_chars_per_token: self.other_chars_per_token,
+        }
+    }
+
+    pub fn render(&self, parts: &PromptParts) -> String {
+        let context_block = if parts.context.is_empty() {
+            String::new()
+        } else {
+            fill(&self.context, &[("context", parts.context)])
+        };
+        let question = fill(&self.question, &[
+            ("context", &context_block),
+            ("question", parts.question),
+            ("language_instruction", parts.language_instruction),
+        ]);
+
+        let mut prompt = self.prefix.clone();
+        prompt.push_str(&fill(&self.system, &[("system", parts.system)]));
+        for turn in parts.history {
+            let turn_template = match turn.role {
+                Role::User => &self.user,
+                Role::Assistant => &self.assistant,
+            };
+            prompt.push_str(&fill(turn_template, &[("content", &turn.content)]));
+        }
+        prompt.push_str(&fill(&self.user, &[("content", &question)]));
+        prompt.push_str(&self.generation_prompt);
+        prompt
+    }
+}
+
+/// Single-pass `{slot}` substitution, so slot values containing braces are left alone
+fn fill(template: &str, slots: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after
+            .find('}')
+            .and_then(|end| slots.iter().find(|(name, _)| *name == &after[..end]).map(|(_, v)| (end, *v)));
+        match value {
+            Some((end, v)) => {
+                out.push_str(v);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Names of the `{slot}` placeholders in `template`; braces around anything but a plain
+/// identifier are literal text
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template.split('{').skip(1).filter_map(|after| {
+        let name = &after[..after.find('}')?];
+        let is_slot = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        is_slot.then_some(name)
+    })
+}
+
+pub struct TemplateRegistry {
+    templates: Vec<PromptTemplate>,
+}
+
+impl TemplateRegistry {
+    /// Built-in templates, overridden family-by-family by files in `dir`. Fails on a file
+    /// that cannot be read, does not parse or uses an unknown slot.
+    pub fn load(dir: &Path) -> Result<Self, LlmError> {
+        let mut templates: Vec<PromptTemplate> = BUILTIN
+            .iter()
+            .map(|src| toml::from_str(src).expect("built-in prompt template is valid"))
+            .collect();
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => {
+                info!("No prompt template directory at {}, using built-in templates", dir.display());
+                return Ok(Self { templates });
+            }
+        };
+
+        for path in entries.filter_map(Result::ok).map(|e| e.path()) {
+            if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                continue;
+            }
+            let template = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|src| toml::from_str::<PromptTemplate>(&src).map_err(|e| e.to_string()))
+                .and_then(|template| template.check_slots().map(|_| template))
+                .map_err(|e| LlmError::Config(format!("prompt template {} is invalid: {}", path.display(), e)))?;
+            templates.retain(|t| t.family != template.family);
+            templates.push(template);
+        }
+
+        Ok(Self { templates })
+    }
+
+    pub fn from_env() -> Result<Self, LlmError> {
+        let dir = env::var("PROMPT_TEMPLATE_DIR").unwrap_or_else(|_| "templates".to_string());
+        Self::load(Path::new(&dir))
+    }
+
+    /// Template whose `match` list hits `model_id`, or the `default` family
+    pub fn for_model(&self, model_id: &str) -> PromptTemplate {
+        let model = model_id.to_lowercase();
+        self.templates
+            .iter()
+            .find(|t| t.matches.iter().any(|m| model.contains(&m.to_lowercase())))
+            .or_else(|| self.templates.iter().find(|t| t.family == "default"))
+            .cloned()
+            .expect("default prompt template is always registered")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts<'a>(context: &'a str) -> PromptParts<'a> {
+        PromptParts {
+            system: "You are KisanAI.",
+            history: &[],
+            context,
+            question: "When to sow wheat {x}?",
+            language_instruction: "Respond in English.",
+        }
+    }
+
+    #[test]
+    fn test_granite_format() {
+        let registry = TemplateRegistry::load(Path::new("/nonexistent")).unwrap();
+        let template = registry.for_model("ibm-granite/granite-3.3-8b-instruct");
+        assert_eq!(template.family, "granite");
+
+        let prompt = template.render(&parts("[Wheat] Sow in November."));
+        assert!(prompt.starts_with("<|start_of_role|>system<|end_of_role|>You are KisanAI.<|end_of_text|>"));
+        assert!(prompt.contains("[Wheat] Sow in November.\n\nWhen to sow wheat {x}?"));
+        assert!(prompt.ends_with("<|start_of_role|>assistant<|end_of_role|>"));
+    }
+
+    #[test]
+    fn test_unknown_model_uses_default_and_skips_empty_context() {
+        let registry = TemplateRegistry::load(Path::new("/nonexistent")).unwrap();
+        let template = registry.for_model("acme/some-model");
+        assert_eq!(template.family, "default");
+        assert!(!template.render(&parts("")).contains("CONTEXT FROM KNOWLEDGE BASE"));
+    }
+
+    #[test]
+    fn test_builtin_templates_use_known_slots() {
+        let registry = TemplateRegistry::load(Path::new("/nonexistent")).unwrap();
+        for template in &registry.templates {
+            assert_eq!(template.check_slots(), Ok(()), "{}", template.family);
+        }
+    }
+
+    #[test]
+    fn test_file_overrides_builtin() {
+        let dir = env::temp_dir().join(format!("kisan-templates-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("granite.toml"), r#"
+            family = "granite"
+            match = ["granite"]
+            system = "S:{system}|"
+            user = "U:{content}|"
+            assistant = "A:{content}|"
+            generation_prompt = "A:"
+            question = "{question}"
+            context = "{context}"
+        "#).unwrap();
+
+        let registry = TemplateRegistry::load(&dir).unwrap();
+        let prompt = registry.for_model("granite-3.3").render(&parts(""));
+        assert_eq!(prompt, "S:You are KisanAI.|U:When to sow wheat {x}?|A:");
+        assert!(registry.for_model("llama3.2").render(&parts("")).starts_with("<|begin_of_text|>"));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_invalid_file_is_a_config_error() {
+        let broken = [
+            ("misspelt-key", r#"asistant = "A:{content}|""#),
+            ("unknown-slot", r#"assistant = "A:{contnet}|""#),
+            ("slot-in-wrong-field", r#"assistant = "A:{question}|""#),
+            ("not-toml", "assistant = "),
+        ];
+        for (name, assistant) in broken {
+            let dir = env::temp_dir().join(format!("kisan-templates-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("llama3.toml"), format!(r#"
+                family = "llama3"
+                match = ["llama"]
+                system = "S:{{system}}|"
+                user = "U:{{content}}|"
+                {}
+                generation_prompt = "A:"
+                question = "{{question}}"
+                context = "{{context}}"
+            "#, assistant)).unwrap();
+
+            let err = TemplateRegistry::load(&dir).err();
+            assert!(matches!(err, Some(LlmError::Config(_))), "{}: {:?}", name, err);
+            fs::remove_dir_all(dir).ok();
+        }
+    }
+
+    #[test]
+    fn test_history_is_rendered_in_role_format() {
+        let registry = TemplateRegistry::load(Path::new("/nonexistent")).unwrap();
+        let history = [
+            Turn::new(Role::User, "When to sow wheat?"),
+            Turn::new(Role::Assistant, "October to November."),
+        ];
+        let prompt = registry.for_model("granite").render(&PromptParts { history: &history, ..parts("") });
+        assert!(prompt.contains(
+            "<|start_of_role|>user<|end_of_role|>When to sow wheat?<|end_of_text|>\n\
+             <|start_of_role|>assistant<|end_of_role|>October to November.<|end_of_text|>"
+        ));
+    }
+}
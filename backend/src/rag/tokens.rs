@@ -0,0 +1,42 @@
+//! Rough token counting for prompt budgeting.
+//! BPE tokenizers split Latin text into ~4 characters per token, while Devanagari
+//! is far less compressed (often one token per 1-2 characters), so count by script.
+//! Each prompt template can tune the ratios for its model family's tokenizer.
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tokenizer {
+    pub latin_chars_per_token: f32,
+    pub other_chars_per_token: f32,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self { latin_chars_per_token: 4.0, other_chars_per_token: 2.0 }
+    }
+}
+
+impl Tokenizer {
+    pub fn count(&self, text: &str) -> usize {
+        let (latin, other) = text.chars().fold((0usize, 0usize), |(latin, other), c| {
+            if c.is_ascii() { (latin + 1, other) } else { (latin, other + 1) }
+        });
+        (latin as f32 / self.latin_chars_per_token).ceil() as usize
+            + (other as f32 / self.other_chars_per_token).ceil() as usize
+    }
+}
+
+pub fn estimate_tokens(text: &str) -> usize {
+    Tokenizer::default().count(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_devanagari_costs_more() {
+        assert_eq!(estimate_tokens("wheat"), 2);
+        assert!(estimate_tokens("गेहूं का भाव") > estimate_tokens("wheat price"));
+        assert_eq!(estimate_tokens(""), 0);
+    }
+}
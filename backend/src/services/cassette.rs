@@ -0,0 +1,250 @@
+//! Record/replay of upstream HTTP exchanges ("cassettes").
+//! With `HTTP_CASSETTE_MODE=record` every request the backend sends upstream is saved with its
+//! response to the JSON file `HTTP_CASSETTE`; with `replay` the saved responses are served back
+//! in order without touching the network, so real Replicate behaviour can be reproduced offline
+//! and in tests. Authorization headers are never written.
+
+use reqwest::{RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use super::error::LlmError;
+
+/// One request and the response it got
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    /// Request body, for reading the cassette; not used for matching
+    #[serde(default)]
+    pub request: Value,
+    pub status: u16,
+    /// `Retry-After`, when the upstream sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<String>,
+    /// JSON bodies are stored as JSON, anything else as a string
+    pub response: Value,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+pub struct Cassette {
+    mode: CassetteMode,
+    /// Where recordings are written
+    path: Option<PathBuf>,
+    interactions: Mutex<Vec<Interaction>>,
+    /// Replay position per interaction
+    used: Mutex<Vec<bool>>,
+    /// Held while the recording is written, so an older snapshot never overwrites a newer one
+    writing: tokio::sync::Mutex<()>,
+}
+
+impl Cassette {
+    /// Start an empty recording that is rewritten to `path` after every exchange
+    pub fn record(path: &Path) -> Self {
+        Self {
+            mode: CassetteMode::Record,
+            path: Some(path.to_path_buf()),
+            interactions: Mutex::new(Vec::new()),
+            used: Mutex::new(Vec::new()),
+            writing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn replay(interactions: Vec<Interaction>) -> Self {
+        let used = vec![false; interactions.len()];
+        Self {
+            mode: CassetteMode::Replay,
+            path: None,
+            interactions: Mutex::new(interactions),
+            used: Mutex::new(used),
+            writing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file: CassetteFile = serde_json::from_str(&src).map_err(|e| e.to_string())?;
+        Ok(Self::replay(file.interactions))
+    }
+
+    /// `HTTP_CASSETTE` plus `HTTP_CASSETTE_MODE` (`record` or `replay`); `None` when unset.
+    /// A replay cassette that can't be loaded is an error rather than a silent switch to the
+    /// real upstream.
+    pub fn from_env() -> Result<Option<Self>, LlmError> {
+        let Some(path) = env::var("HTTP_CASSETTE").ok().filter(|p| !p.is_empty()).map(PathBuf::from) else {
+            return Ok(None);
+        };
+        match env::var("HTTP_CASSETTE_MODE").as_deref() {
+            Ok("record") => {
+                info!("Recording upstream HTTP exchanges to {}", path.display());
+                Ok(Some(Self::record(&path)))
+            }
+            Ok("replay") => {
+                let cassette = Self::load(&path)
+                    .map_err(|e| LlmError::Config(format!("could not load cassette {}: {}", path.display(), e)))?;
+                info!("Replaying upstream HTTP exchanges from {}", path.display());
+                Ok(Some(cassette))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// Next unplayed interaction with the request's method and URL
+    pub fn play(&self, request: &RequestBuilder) -> Result<Response, LlmError> {
+        let (method, url, _) = describe(request);
+        let interactions = self.interactions.lock().unwrap();
+        let mut used = self.used.lock().unwrap();
+        let index = interactions
+            .iter()
+            .zip(used.iter())
+            .position(|(it, played)| !played && it.method == method && it.url == url)
+            .ok_or_else(|| LlmError::UpstreamHttp {
+                status: None,
+                message: format!("no cassette entry left for {} {}", method, url),
+            })?;
+        used[index] = true;
+        to_response(&interactions[index])
+    }
+
+    /// Store the exchange and hand back an equivalent response (the body has been read)
+    pub async fn capture(&self, method: String, url: String, request: Value, response: Response) -> Result<Response, LlmError> {
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let body = response.text().await?;
+        let interaction = Interaction {
+            method,
+            url,
+            request,
+            status,
+            retry_after,
+            response: serde_json::from_str(&body).unwrap_or(Value::String(body)),
+        };
+        let replayed = to_response(&interaction)?;
+
+        let _writing = self.writing.lock().await;
+        let snapshot = {
+            let mut interactions = self.interactions.lock().unwrap();
+            interactions.push(interaction);
+            CassetteFile { interactions: interactions.clone() }
+        };
+        if let Some(path) = &self.path {
+            let written = match serde_json::to_string_pretty(&snapshot) {
+                Ok(json) => tokio::fs::write(path, json).await.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = written {
+                warn!("Could not write cassette {}: {}", path.display(), e);
+            }
+        }
+        Ok(replayed)
+    }
+}
+
+/// Method, URL and JSON body of a request
+pub fn describe(request: &RequestBuilder) -> (String, String, Value) {
+    let Some(built) = request.try_clone().and_then(|r| r.build().ok()) else {
+        return (String::new(), String::new(), Value::Null);
+    };
+    let body = built
+        .body()
+        .and_then(|b| b.as_bytes())
+        .map(|bytes| serde_json::from_slice(bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).into_owned())))
+        .unwrap_or(Value::Null);
+    (built.method().to_string(), built.url().to_string(), body)
+}
+
+fn to_response(interaction: &Interaction) -> Result<Response, LlmError> {
+    let body = match &interaction.response {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    let mut builder = http::Response::builder().status(interaction.status);
+    if interaction.response.is_object() || interaction.response.is_array() {
+        builder = builder.header("content-type", "application/json");
+    }
+    if let Some(retry_after) = &interaction.retry_after {
+        builder = builder.header("retry-after", retry_after);
+    }
+    builder.body(body).map(Response::from).map_err(|e| LlmError::UpstreamHttp {
+        status: None,
+        message: format!("cassette entry for {} {} is invalid: {}", interaction.method, interaction.url, e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::http::HttpClient;
+    use crate::test_support::http;
+    use axum::{routing::post, Json, Router};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn client(cassette: Cassette) -> HttpClient {
+        http().with_cassette(Arc::new(cassette))
+    }
+
+    #[tokio::test]
+    async fn test_recorded_exchange_replays_without_server() {
+        let app = Router::new().route("/v1/predictions", post(|Json(body): Json<Value>| async move {
+            Json(serde_json::json!({ "status": "succeeded", "output": ["echo: ", body["input"]["prompt"]] }))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/predictions", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let path = env::temp_dir().join(format!("kisan-cassette-{}.json", std::process::id()));
+        let recorder = client(Cassette::record(&path));
+        let request = recorder.post(&url).bearer_auth("secret-token").json(&serde_json::json!({ "input": { "prompt": "wheat" } }));
+        let live: Value = recorder.send(request, Duration::from_secs(5)).await.unwrap().json().await.unwrap();
+        assert_eq!(live["output"][1], "wheat");
+        server.abort();
+
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("secret-token"));
+
+        let replayer = client(Cassette::load(&path).unwrap());
+        let replayed: Value = replayer.send(replayer.post(&url), Duration::from_secs(5)).await.unwrap().json().await.unwrap();
+        assert_eq!(replayed, live);
+        assert!(replayer.send(replayer.post(&url), Duration::from_secs(5)).await.is_err());
+        fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_recorded_status_is_an_error() {
+        let broken = Interaction {
+            method: "POST".to_string(),
+            url: "http://replicate.test/v1/predictions".to_string(),
+            request: Value::Null,
+            status: 42,
+            retry_after: None,
+            response: Value::Null,
+        };
+        let replayer = client(Cassette::replay(vec![broken]));
+        let err = replayer.send(replayer.post("http://replicate.test/v1/predictions"), Duration::from_secs(5)).await.unwrap_err();
+        assert!(matches!(err, LlmError::UpstreamHttp { status: None, .. }));
+    }
+}
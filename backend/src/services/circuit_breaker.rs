@@ -0,0 +1,160 @@
+//! Per-provider circuit breaker.
+//! Opens after `failure_threshold` consecutive failures so requests skip a
+//! degraded provider instantly, then lets a single half-open probe through
+//! once `cooldown` has passed. Calls hold a `Permit`; a probe whose call is dropped before
+//! it reports back (client gone, job cancelled, timeout) hands its slot back on drop.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probing: bool },
+}
+
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// A permit when a call may go through now. In half-open state only one probe is admitted.
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        let probe = match *state {
+            BreakerState::Closed { .. } => false,
+            BreakerState::Open { until } if Instant::now() >= until => true,
+            BreakerState::Open { .. } | BreakerState::HalfOpen { probing: true } => return None,
+            BreakerState::HalfOpen { probing: false } => true,
+        };
+        if probe {
+            *state = BreakerState::HalfOpen { probing: true };
+        }
+        Some(Permit { breaker: self, probe, settled: false })
+    }
+
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        *state = match *state {
+            BreakerState::Closed { failures } if failures + 1 < self.failure_threshold => {
+                BreakerState::Closed { failures: failures + 1 }
+            }
+            _ => BreakerState::Open { until: Instant::now() + self.cooldown },
+        };
+    }
+
+    /// Give back a half-open probe slot without judging the provider
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let BreakerState::HalfOpen { probing: true } = *state {
+            *state = BreakerState::HalfOpen { probing: false };
+        }
+    }
+
+    pub fn state_label(&self) -> &'static str {
+        match *self.state.lock().unwrap() {
+            BreakerState::Closed { .. } => "closed",
+            BreakerState::Open { .. } => "open",
+            BreakerState::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+/// Leave to make one call. Report the outcome with `success` or `failure`; dropping it
+/// unreported (e.g. on a configuration error, or when the call's future is dropped) judges
+/// nothing and, for a probe, lets the next call probe instead.
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+    settled: bool,
+}
+
+impl Permit<'_> {
+    pub fn success(mut self) {
+        self.settled = true;
+        self.breaker.record_success();
+    }
+
+    pub fn failure(mut self) {
+        self.settled = true;
+        self.breaker.record_failure();
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.settled {
+            self.breaker.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_threshold() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.record_failure();
+        assert!(breaker.try_acquire().is_some());
+        breaker.record_failure();
+        assert_eq!(breaker.state_label(), "open");
+        assert!(breaker.try_acquire().is_none());
+    }
+
+    #[test]
+    fn test_half_open_admits_single_probe() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(0));
+        breaker.record_failure();
+        let probe = breaker.try_acquire().unwrap();
+        assert_eq!(breaker.state_label(), "half_open");
+        assert!(breaker.try_acquire().is_none());
+
+        probe.success();
+        assert_eq!(breaker.state_label(), "closed");
+        assert!(breaker.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_unreported_probe_frees_its_slot() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(0));
+        breaker.record_failure();
+        let probe = breaker.try_acquire().unwrap();
+        drop(probe);
+        assert_eq!(breaker.state_label(), "half_open");
+        breaker.try_acquire().unwrap().success();
+
+        // A permit from before the breaker opened must not free someone else's probe when dropped
+        let late = breaker.try_acquire().unwrap();
+        breaker.try_acquire().unwrap().failure();
+        let probe = breaker.try_acquire().unwrap();
+        drop(late);
+        assert!(breaker.try_acquire().is_none());
+        probe.failure();
+        assert_eq!(breaker.state_label(), "open");
+    }
+
+    #[test]
+    fn test_failed_probe_reopens() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        *breaker.state.lock().unwrap() = BreakerState::HalfOpen { probing: true };
+        breaker.record_failure();
+        assert!(breaker.try_acquire().is_none());
+    }
+}
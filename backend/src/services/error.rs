@@ -0,0 +1,109 @@
+//! Error types for LLM calls.
+//! Lets handlers tell a real model answer apart from a canned fallback.
+
+use axum::http::StatusCode;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LlmError {
+    /// Missing or invalid server configuration (e.g. no API token)
+    #[error("configuration error: {0}")]
+    Config(String),
+
+    /// Upstream rejected our credentials (401/403)
+    #[error("upstream rejected credentials (HTTP {status})")]
+    Auth { status: u16 },
+
+    /// Upstream returned a non-success status, or the request never completed
+    #[error("upstream HTTP error{}: {message}", status.map(|s| format!(" {}", s)).unwrap_or_default())]
+    UpstreamHttp { status: Option<u16>, message: String },
+
+    /// Prediction did not finish in time
+    #[error("prediction timed out after {secs}s")]
+    Timeout { secs: u64 },
+
+    /// Prediction ended in `failed` or `canceled`
+    #[error("prediction {status}: {message}")]
+    PredictionFailed { status: String, message: String },
+
+    /// Prediction succeeded but produced no usable text
+    #[error("prediction returned no output")]
+    EmptyOutput,
+
+    /// Structured output still broke its schema after every retry
+    #[error("output did not match the schema after {attempts} attempts: {message}")]
+    InvalidOutput { attempts: u32, message: String },
+
+    /// Provider skipped because its circuit breaker is open
+    #[error("circuit open for {0}")]
+    CircuitOpen(String),
+}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(e: reqwest::Error) -> Self {
+        LlmError::UpstreamHttp {
+            status: e.status().map(|s| s.as_u16()),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl LlmError {
+    /// Short machine-readable reason, surfaced as `fallback_reason` to clients
+    pub fn reason(&self) -> &'static str {
+        match self {
+            LlmError::Config(_) => "config",
+            LlmError::Auth { .. } => "auth",
+            LlmError::UpstreamHttp { .. } => "upstream_http",
+            LlmError::Timeout { .. } => "timeout",
+            LlmError::PredictionFailed { .. } => "prediction_failed",
+            LlmError::EmptyOutput => "empty_output",
+            LlmError::InvalidOutput { .. } => "invalid_output",
+            LlmError::CircuitOpen(_) => "circuit_open",
+        }
+    }
+
+    /// Whether serving the knowledge-base fallback is acceptable.
+    /// Config and auth problems are our fault and should be visible, not papered over.
+    pub fn allows_fallback(&self) -> bool {
+        !matches!(self, LlmError::Config(_) | LlmError::Auth { .. })
+    }
+
+    /// HTTP status to return when this error reaches a client
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            LlmError::Config(_) => StatusCode::SERVICE_UNAVAILABLE,
+            LlmError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            LlmError::UpstreamHttp { status: Some(429), .. } | LlmError::CircuitOpen(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            LlmError::Auth { .. }
+            | LlmError::UpstreamHttp { .. }
+            | LlmError::PredictionFailed { .. }
+            | LlmError::EmptyOutput
+            | LlmError::InvalidOutput { .. } => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_mapping() {
+        assert_eq!(LlmError::Config("x".into()).status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(LlmError::Timeout { secs: 90 }.status_code(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            LlmError::UpstreamHttp { status: Some(429), message: String::new() }.status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(LlmError::EmptyOutput.status_code(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn test_fallback_policy() {
+        assert!(!LlmError::Auth { status: 401 }.allows_fallback());
+        assert!(LlmError::Timeout { secs: 90 }.allows_fallback());
+    }
+}
@@ -0,0 +1,180 @@
+//! Response-length modes and generation parameters.
+//! A chat request picks `brief` (SMS-sized), `normal` or `detailed` and may ask for its own
+//! sampling values; everything is clamped to limits the operator sets per mode.
+
+use serde::Deserialize;
+use std::env;
+
+use crate::services::error::LlmError;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseMode {
+    Brief,
+    #[default]
+    Normal,
+    Detailed,
+}
+
+impl ResponseMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResponseMode::Brief => "brief",
+            ResponseMode::Normal => "normal",
+            ResponseMode::Detailed => "detailed",
+        }
+    }
+
+    /// Formatting instruction added to the prompt; empty for `normal`
+    pub fn instruction(self) -> &'static str {
+        match self {
+            ResponseMode::Brief => "Answer in at most two short sentences of plain text (no lists or markdown), short enough for an SMS.",
+            ResponseMode::Normal => "",
+            ResponseMode::Detailed => "Give a thorough answer with numbered steps, covering quantities, timing and precautions where relevant.",
+        }
+    }
+}
+
+/// Sampling values sent upstream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GenerationParams {
+    pub max_tokens: usize,
+    pub temperature: f32,
+    pub top_p: f32,
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        Self { max_tokens: 500, temperature: 0.7, top_p: 0.9 }
+    }
+}
+
+/// Values a client asked for; missing ones take the mode's defaults
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct GenerationOverrides {
+    pub max_tokens: Option<usize>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+}
+
+impl GenerationOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.max_tokens.is_none() && self.temperature.is_none() && self.top_p.is_none()
+    }
+}
+
+/// Server-side caps per response mode
+pub struct GenerationLimits {
+    brief: GenerationParams,
+    normal: GenerationParams,
+    detailed: GenerationParams,
+    max_temperature: f32,
+}
+
+impl Default for GenerationLimits {
+    fn default() -> Self {
+        Self {
+            brief: GenerationParams { max_tokens: 120, temperature: 0.3, top_p: 0.9 },
+            normal: GenerationParams::default(),
+            detailed: GenerationParams { max_tokens: 1000, ..GenerationParams::default() },
+            max_temperature: 1.0,
+        }
+    }
+}
+
+impl GenerationLimits {
+    /// `RESPONSE_MAX_TOKENS_BRIEF` (120), `RESPONSE_MAX_TOKENS_NORMAL` (500),
+    /// `RESPONSE_MAX_TOKENS_DETAILED` (1000) and `RESPONSE_MAX_TEMPERATURE` (1.0)
+    pub fn from_env() -> Result<Self, LlmError> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Limits from `var`; a zero token cap or a negative or non-finite temperature cap is a
+    /// config error, since clamping to it would fail on every request
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, LlmError> {
+        let defaults = Self::default();
+        let tokens = |name: &str, default: GenerationParams| {
+            let max_tokens = var(name).and_then(|v| v.parse().ok()).unwrap_or(default.max_tokens);
+            if max_tokens == 0 {
+                return Err(LlmError::Config(format!("{} must be at least 1", name)));
+            }
+            Ok(GenerationParams { max_tokens, ..default })
+        };
+        let max_temperature: f32 = var("RESPONSE_MAX_TEMPERATURE").and_then(|v| v.parse().ok()).unwrap_or(defaults.max_temperature);
+        if !max_temperature.is_finite() || max_temperature < 0.0 {
+            return Err(LlmError::Config(format!("RESPONSE_MAX_TEMPERATURE must be 0 or more, not {}", max_temperature)));
+        }
+        Ok(Self {
+            brief: tokens("RESPONSE_MAX_TOKENS_BRIEF", defaults.brief)?,
+            normal: tokens("RESPONSE_MAX_TOKENS_NORMAL", defaults.normal)?,
+            detailed: tokens("RESPONSE_MAX_TOKENS_DETAILED", defaults.detailed)?,
+            max_temperature,
+        })
+    }
+
+    /// Mode defaults with the client's overrides applied, clamped to the mode's caps
+    pub fn resolve(&self, mode: ResponseMode, overrides: &GenerationOverrides) -> GenerationParams {
+        let base = match mode {
+            ResponseMode::Brief => self.brief,
+            ResponseMode::Normal => self.normal,
+            ResponseMode::Detailed => self.detailed,
+        };
+        GenerationParams {
+            max_tokens: overrides.max_tokens.unwrap_or(base.max_tokens).clamp(1, base.max_tokens),
+            temperature: overrides.temperature.unwrap_or(base.temperature).clamp(0.0, self.max_temperature),
+            top_p: overrides.top_p.unwrap_or(base.top_p).clamp(0.01, 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> GenerationLimits {
+        GenerationLimits {
+            brief: GenerationParams { max_tokens: 120, temperature: 0.3, top_p: 0.9 },
+            normal: GenerationParams::default(),
+            detailed: GenerationParams { max_tokens: 1000, ..GenerationParams::default() },
+            max_temperature: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_mode_defaults() {
+        let params = limits().resolve(ResponseMode::Brief, &GenerationOverrides::default());
+        assert_eq!(params, GenerationParams { max_tokens: 120, temperature: 0.3, top_p: 0.9 });
+        assert_eq!(limits().resolve(ResponseMode::Normal, &GenerationOverrides::default()), GenerationParams::default());
+    }
+
+    #[test]
+    fn test_overrides_are_clamped_to_mode_caps() {
+        let overrides = GenerationOverrides { max_tokens: Some(4096), temperature: Some(2.5), top_p: Some(0.0) };
+        let params = limits().resolve(ResponseMode::Detailed, &overrides);
+        assert_eq!(params, GenerationParams { max_tokens: 1000, temperature: 1.0, top_p: 0.01 });
+
+        let smaller = GenerationOverrides { max_tokens: Some(50), ..GenerationOverrides::default() };
+        assert_eq!(limits().resolve(ResponseMode::Brief, &smaller).max_tokens, 50);
+    }
+
+    fn from(vars: &[(&str, &str)]) -> Result<GenerationLimits, LlmError> {
+        GenerationLimits::from_vars(|name| vars.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string()))
+    }
+
+    #[test]
+    fn test_zero_token_cap_is_a_config_error() {
+        let err = from(&[("RESPONSE_MAX_TOKENS_BRIEF", "0")]).err().unwrap();
+        assert!(matches!(err, LlmError::Config(ref m) if m == "RESPONSE_MAX_TOKENS_BRIEF must be at least 1"));
+        assert_eq!(from(&[("RESPONSE_MAX_TOKENS_BRIEF", "60")]).unwrap().brief.max_tokens, 60);
+    }
+
+    #[test]
+    fn test_negative_or_nan_temperature_cap_is_a_config_error() {
+        for value in ["-0.5", "NaN", "inf"] {
+            assert!(matches!(from(&[("RESPONSE_MAX_TEMPERATURE", value)]), Err(LlmError::Config(_))), "{}", value);
+        }
+        let limits = from(&[("RESPONSE_MAX_TEMPERATURE", "0")]).unwrap();
+        let overrides = GenerationOverrides { temperature: Some(0.8), ..GenerationOverrides::default() };
+        assert_eq!(limits.resolve(ResponseMode::Normal, &overrides).temperature, 0.0);
+    }
+}
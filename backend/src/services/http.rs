@@ -0,0 +1,322 @@
+//! Shared HTTP client for upstream calls (Replicate, IBM Cloud).
+//! One pooled `reqwest::Client` with connect/request timeouts, plus retry
+//! with jittered exponential backoff on 429/503 (and 502/504 for requests that are safe to
+//! repeat) that honours `Retry-After`.
+//! An optional cassette records or replays every exchange (see `cassette`).
+
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, Client, RequestBuilder, Response, StatusCode};
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use super::cassette::{self, Cassette, CassetteMode};
+use super::error::LlmError;
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Equal-jitter backoff: somewhere in [d/2, d] where d = base * 2^attempt, capped
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self.base_delay.saturating_mul(2u32.saturating_pow(attempt));
+        let capped = exp.min(self.max_delay);
+        let half = capped / 2;
+        let jitter_ms = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        half + Duration::from_millis(jitter_ms)
+    }
+}
+
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    retry: RetryPolicy,
+    cassette: Option<Arc<Cassette>>,
+}
+
+impl HttpClient {
+    /// Fails only when the TLS backend can't be initialised
+    pub fn new(connect_timeout: Duration, retry: RetryPolicy) -> Result<Self, LlmError> {
+        let client = Client::builder()
+            .connect_timeout(connect_timeout)
+            .pool_idle_timeout(Duration::from_secs(90))
+            .build()
+            .map_err(|e| LlmError::Config(format!("failed to build HTTP client: {}", e)))?;
+        Ok(Self { client, retry, cassette: None })
+    }
+
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Build from `HTTP_CONNECT_TIMEOUT_SECS`, `HTTP_MAX_RETRIES` and the cassette settings
+    pub fn from_env() -> Result<Self, LlmError> {
+        let connect_timeout = env_u64("HTTP_CONNECT_TIMEOUT_SECS", 5);
+        let retry = RetryPolicy {
+            max_retries: env_u64("HTTP_MAX_RETRIES", 3) as u32,
+            ..RetryPolicy::default()
+        };
+        let client = Self::new(Duration::from_secs(connect_timeout), retry)?;
+        Ok(match Cassette::from_env()? {
+            Some(cassette) => client.with_cassette(Arc::new(cassette)),
+            None => client,
+        })
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Send a request, retrying transient failures until `deadline` is spent.
+    /// A non-success response is returned as-is once retries run out so callers
+    /// can inspect the status and body themselves.
+    pub async fn send(&self, request: RequestBuilder, deadline: Duration) -> Result<Response, LlmError> {
+        match &self.cassette {
+            Some(c) if c.mode() == CassetteMode::Replay => c.play(&request),
+            Some(c) => {
+                let (method, url, body) = cassette::describe(&request);
+                let res = self.send_live(request, deadline).await?;
+                c.capture(method, url, body, res).await
+            }
+            None => self.send_live(request, deadline).await,
+        }
+    }
+
+    async fn send_live(&self, request: RequestBuilder, deadline: Duration) -> Result<Response, LlmError> {
+        // Bodies that can't be cloned (streams) only get a single attempt
+        if request.try_clone().is_none() {
+            return request.timeout(deadline).send().await.map_err(|e| send_error(e, deadline));
+        }
+
+        let idempotent = is_idempotent(&request);
+        let started = Instant::now();
+        let mut attempt = 0;
+
+        loop {
+            let remaining = deadline.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                return Err(LlmError::Timeout { secs: deadline.as_secs() });
+            }
+
+            let this_try = request.try_clone().expect("cloneability checked above");
+            let can_retry = attempt < self.retry.max_retries;
+
+            let wait = match this_try.timeout(remaining).send().await {
+                Ok(res) if can_retry && is_retryable_status(res.status(), idempotent) => {
+                    let wait = retry_after(&res).unwrap_or_else(|| self.retry.backoff(attempt));
+                    warn!("Upstream returned {}, retrying in {:?} (attempt {})", res.status(), wait, attempt + 1);
+                    if started.elapsed() + wait >= deadline {
+                        return Ok(res);
+                    }
+                    wait
+                }
+                Ok(res) => return Ok(res),
+                Err(e) if can_retry && e.is_connect() => {
+                    let wait = self.retry.backoff(attempt);
+                    warn!("Upstream connection failed ({}), retrying in {:?}", e, wait);
+                    if started.elapsed() + wait >= deadline {
+                        return Err(send_error(e, deadline));
+                    }
+                    wait
+                }
+                Err(e) => return Err(send_error(e, deadline)),
+            };
+
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn send_error(e: reqwest::Error, deadline: Duration) -> LlmError {
+    if e.is_timeout() {
+        LlmError::Timeout { secs: deadline.as_secs() }
+    } else {
+        e.into()
+    }
+}
+
+/// 429 and 503 mean the request was turned away, so any request may be repeated. After a
+/// 502/504 (or a plain 500) the upstream may still have acted on it, and Replicate creates a
+/// paid prediction on every POST, so those are only retried when repeating is harmless.
+fn is_retryable_status(status: StatusCode, idempotent: bool) -> bool {
+    match status.as_u16() {
+        429 | 503 => true,
+        502 | 504 => idempotent,
+        _ => false,
+    }
+}
+
+/// Idempotent methods (GET, PUT, DELETE, ...) and requests carrying an `Idempotency-Key`
+fn is_idempotent(request: &RequestBuilder) -> bool {
+    request
+        .try_clone()
+        .and_then(|r| r.build().ok())
+        .is_some_and(|r| r.method().is_idempotent() || r.headers().contains_key("idempotency-key"))
+}
+
+/// `Retry-After` in delay-seconds form (the HTTP-date form is not used by our upstreams)
+fn retry_after(res: &Response) -> Option<Duration> {
+    res.headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+fn env_u64(key: &str, default: u64) -> u64 {
+    env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::HeaderMap, routing::any, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Serve `/` on a random local port. The handler sees the 0-based hit count.
+    async fn mock_server<F>(respond: F) -> (String, Arc<AtomicUsize>)
+    where
+        F: Fn(usize) -> (axum::http::StatusCode, HeaderMap) + Clone + Send + Sync + 'static,
+    {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = Router::new().route("/", any(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            let respond = respond.clone();
+            async move { respond(n) }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/", addr), hits)
+    }
+
+    fn fast_client(max_retries: u32) -> HttpClient {
+        HttpClient::new(Duration::from_secs(1), RetryPolicy {
+            max_retries,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+        }).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success() {
+        let (url, hits) = mock_server(|n| {
+            let status = if n < 2 { 503 } else { 200 };
+            (axum::http::StatusCode::from_u16(status).unwrap(), HeaderMap::new())
+        }).await;
+
+        let http = fast_client(3);
+        let res = http.send(http.get(&url), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let (url, hits) = mock_server(|_| (axum::http::StatusCode::TOO_MANY_REQUESTS, HeaderMap::new())).await;
+
+        let http = fast_client(2);
+        let res = http.send(http.get(&url), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let (url, hits) = mock_server(|_| (axum::http::StatusCode::UNAUTHORIZED, HeaderMap::new())).await;
+
+        let http = fast_client(3);
+        let res = http.send(http.get(&url), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_honours_retry_after() {
+        let (url, hits) = mock_server(|n| {
+            let mut headers = HeaderMap::new();
+            if n == 0 {
+                headers.insert("retry-after", "1".parse().unwrap());
+                (axum::http::StatusCode::TOO_MANY_REQUESTS, headers)
+            } else {
+                (axum::http::StatusCode::OK, headers)
+            }
+        }).await;
+
+        let http = fast_client(3);
+        let started = Instant::now();
+        let res = http.send(http.get(&url), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_beyond_deadline_returns_response() {
+        let (url, hits) = mock_server(|_| {
+            let mut headers = HeaderMap::new();
+            headers.insert("retry-after", "30".parse().unwrap());
+            (axum::http::StatusCode::SERVICE_UNAVAILABLE, headers)
+        }).await;
+
+        let http = fast_client(3);
+        let res = http.send(http.get(&url), Duration::from_secs(2)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_bad_gateway_is_retried_only_when_repeating_is_safe() {
+        let (url, hits) = mock_server(|_| (axum::http::StatusCode::BAD_GATEWAY, HeaderMap::new())).await;
+        let http = fast_client(2);
+
+        let res = http.send(http.post(&url), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(hits.swap(0, Ordering::SeqCst), 1);
+
+        http.send(http.get(&url), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(hits.swap(0, Ordering::SeqCst), 3);
+
+        http.send(http.post(&url).header("Idempotency-Key", "p1"), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(hits.swap(0, Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_post_is_retried_when_throttled() {
+        let (url, hits) = mock_server(|n| {
+            let status = if n == 0 { 429 } else { 200 };
+            (axum::http::StatusCode::from_u16(status).unwrap(), HeaderMap::new())
+        }).await;
+
+        let http = fast_client(2);
+        let res = http.send(http.post(&url), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,9 +1,11 @@
-use reqwest::Client;
 use serde_json::Value;
+use std::time::Duration;
 use tracing::{info, error};
 use std::sync::OnceLock;
 use tokio::sync::RwLock;
 
+use super::http::HttpClient;
+
 /// Token cache to avoid repeated IAM calls
 static TOKEN_CACHE: OnceLock<RwLock<Option<CachedToken>>> = OnceLock::new();
 
@@ -19,7 +21,8 @@ fn get_cache() -> &'static RwLock<Option<CachedToken>> {
 
 /// Get IAM token for IBM Cloud authentication
 /// Caches the token for efficiency
-pub async fn get_iam_token(api_key: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+#[allow(dead_code)]
+pub async fn get_iam_token(http: &HttpClient, api_key: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let cache = get_cache();
     
     // Check cache first
@@ -34,16 +37,14 @@ pub async fn get_iam_token(api_key: &str) -> Result<String, Box<dyn std::error::
 
     info!("Fetching new IAM token from IBM Cloud");
 
-    let client = Client::new();
-    let res = client.post("https://iam.cloud.ibm.com/identity/token")
+    let request = http.post("https://iam.cloud.ibm.com/identity/token")
         .header("Content-Type", "application/x-www-form-urlencoded")
         .header("Accept", "application/json")
         .form(&[
             ("grant_type", "urn:ibm:params:oauth:grant-type:apikey"),
             ("apikey", api_key)
-        ])
-        .send()
-        .await?;
+        ]);
+    let res = http.send(request, Duration::from_secs(15)).await?;
 
     if res.status().is_success() {
         let json: Value = res.json().await?;
@@ -1,222 +1,432 @@
+use reqwest::RequestBuilder;
 use serde_json::json;
-use reqwest::Client;
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::{info, error, warn};
 
+use async_trait::async_trait;
+
+use super::error::LlmError;
+use super::http::HttpClient;
+use super::llm::{LlmProvider, LlmRequest, PredictionTracker, UpstreamPrediction};
+use super::replicate_webhook::ReplicateWebhooks;
+use crate::rag::budget::{fit_prompt, Fitted};
+use crate::rag::citations::CITATION_INSTRUCTION;
+use crate::rag::system_prompt::SystemPrompt;
+use crate::rag::templates::{PromptTemplate, TemplateRegistry};
+
 /// IBM Granite LLM integration via Replicate API
 /// Uses Replicate's hosted IBM Granite models for text generation
 
-const SYSTEM_PROMPT: &str = r#"You are KisanAI, a helpful and knowledgeable AI assistant.
+// using model-specific endpoint to always get latest version
+// URL format: https://api.replicate.com/v1/models/{owner}/{model}/predictions
 
-Your primary goal is to help users with accurate and practical information. While you have a special focus on agriculture and helping farmers, you can answer questions on a wide range of topics including general knowledge, science, history, and daily life.
+pub const DEFAULT_TEXT_MODEL: &str = "ibm-granite/granite-3.3-8b-instruct";
+pub const DEFAULT_VISION_MODEL: &str = "yorickvp/llava-13b";
 
-GUIDELINES:
-1. **Be Helpful & Accurate**: Provide clear, correct, and useful answers.
-2. **Context Matters**: Use the provided context from the knowledge base to answer properly. If the context is relevant, prioritize it.
-3. **General Knowledge**: If the query is not about farming, answer it using your general knowledge.
-4. **Farming Persona**: When answering agricultural questions, use simple, farmer-friendly language and consider the Indian context (seasons, mandis, crops).
-5. **Safety**: Do not generate harmful, illegal, or biased content.
+/// Where and how to reach the Replicate API
+#[derive(Clone, Debug)]
+pub struct ReplicateApi {
+    /// e.g. `https://api.replicate.com/v1`, without a trailing slash
+    pub base_url: String,
+    pub token: Option<String>,
+    /// Time between status checks while a prediction runs
+    pub poll_interval: Duration,
+}
 
-FORMAT:
-- Use bullet points for lists.
-- Be concise and direct.
-- For farming advice, include actionable steps."#;
+impl ReplicateApi {
+    /// `REPLICATE_API_BASE` (point it at a stand-in server for testing) and `REPLICATE_API_TOKEN`
+    pub fn from_env() -> Self {
+        let base_url = env::var("REPLICATE_API_BASE").unwrap_or_else(|_| "https://api.replicate.com/v1".to_string());
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: env::var("REPLICATE_API_TOKEN").ok().filter(|t| !t.is_empty()),
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
 
-// using model-specific endpoint to always get latest version
-// URL format: https://api.replicate.com/v1/models/{owner}/{model}/predictions
+/// A single Replicate-hosted model, used as one link in the model chain
+pub struct ReplicateProvider {
+    model_id: String,
+    is_vision: bool,
+    label: String,
+    template: PromptTemplate,
+    /// When set, completion arrives by webhook instead of polling
+    webhooks: Option<Arc<ReplicateWebhooks>>,
+    api: ReplicateApi,
+}
 
-pub async fn generate_response(query: &str, context: &str, image: Option<String>, target_lang: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let api_token = env::var("REPLICATE_API_TOKEN").map_err(|_| "REPLICATE_API_TOKEN not set")?;
+impl ReplicateProvider {
+    pub fn new(
+        model_id: &str,
+        is_vision: bool,
+        templates: &TemplateRegistry,
+        webhooks: Option<Arc<ReplicateWebhooks>>,
+        api: ReplicateApi,
+    ) -> Self {
+        let model_id = if is_vision { model_id.to_string() } else { resolve_text_model(model_id) };
+        Self {
+            label: format!("replicate:{}", model_id),
+            template: templates.for_model(&model_id),
+            model_id,
+            is_vision,
+            webhooks,
+            api,
+        }
+    }
+}
 
-    // Determine model (Owner/Name format)
-    let (model_id, is_vision) = if image.is_some() {
-        (
-            env::var("REPLICATE_VISION_MODEL").unwrap_or_else(|_| "yorickvp/llava-13b".to_string()),
-            true
-        )
-    } else {
-        (
-            {
-                // User requested specific active model: ibm-granite/granite-3.3-8b-instruct
-                let env_model = env::var("REPLICATE_MODEL_VERSION").unwrap_or_else(|_| "ibm-granite/granite-3.3-8b-instruct".to_string());
-                
-                // Override disabled/older versions to the new 3.3 version
-                if env_model.contains("granite-3.0-8b-instruct") || env_model.contains("granite-34b-code-instruct") {
-                    warn!("Switching to active Granite 3.3 8B Instruct model.");
-                    "ibm-granite/granite-3.3-8b-instruct".to_string()
-                } else {
-                    env_model
-                }
-            },
-            false
-        )
-    };
+#[async_trait]
+impl LlmProvider for ReplicateProvider {
+    fn name(&self) -> &str {
+        &self.label
+    }
 
-    let client = Client::new();
+    async fn generate(&self, http: &HttpClient, request: &LlmRequest) -> Result<String, LlmError> {
+        generate_response(http, self, request).await
+    }
 
-    // Prepare prompt
-    let user_suffix = if is_vision { "" } else { "User: " };
-    let assistant_prefix = if is_vision { "" } else { "Assistant:" };
-    
-    // RAG Context Integration
-    let context_block = if context.is_empty() {
-        String::new()
+    fn passages_kept(&self, request: &LlmRequest) -> usize {
+        fit_request(&self.template, request).map_or(0, |fitted| fitted.passages_used)
+    }
+}
+
+/// Override disabled/older Granite versions to the active 3.3 version
+fn resolve_text_model(model: &str) -> String {
+    if model.contains("granite-3.0-8b-instruct") || model.contains("granite-34b-code-instruct") {
+        warn!("Switching to active Granite 3.3 8B Instruct model.");
+        DEFAULT_TEXT_MODEL.to_string()
     } else {
-        format!("CONTEXT FROM KNOWLEDGE BASE:\n{}\n\n---\n\n", context)
-    };
+        model.to_string()
+    }
+}
 
-    let lang_instruction = match target_lang {
+/// `request` rendered with its family's chat template and trimmed to the model's
+/// context window, with room left for `max_tokens` of answer
+pub fn fit_request(template: &PromptTemplate, request: &LlmRequest) -> Result<Fitted, LlmError> {
+    let citation = if request.passages.is_empty() { "" } else { CITATION_INSTRUCTION };
+    let instruction = [request.mode.instruction(), citation, language_instruction(&request.target_lang)]
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    fit_prompt(
+        template,
+        &request.system_prompt,
+        &request.history,
+        &request.passages,
+        &request.query,
+        &instruction,
+        request.params.max_tokens,
+    )
+}
+
+/// Build the prompt sent to the model from its family's chat template,
+/// trimmed to the model's context window with room left for `max_tokens` of answer
+pub fn build_prompt(template: &PromptTemplate, request: &LlmRequest) -> Result<String, LlmError> {
+    let fitted = fit_request(template, request)?;
+    if fitted.passages_used < request.passages.len() || fitted.history_used < request.history.len() || fitted.truncated || fitted.question_truncated {
+        info!(
+            "Trimmed prompt for {} ({} tokens): kept {}/{} passages, {}/{} turns{}{}",
+            template.family, template.context_window,
+            fitted.passages_used, request.passages.len(),
+            fitted.history_used, request.history.len(),
+            if fitted.truncated { ", top passage truncated" } else { "" },
+            if fitted.question_truncated { ", question truncated" } else { "" }
+        );
+    }
+    Ok(fitted.prompt)
+}
+
+fn language_instruction(target_lang: &str) -> &'static str {
+    match target_lang {
         "hi" => "IMPORTANT: Respond in Hindi (Devanagari script).",
         "mr" => "IMPORTANT: Respond in Marathi (Devanagari script).",
         _ => "IMPORTANT: Respond in English."
-    };
+    }
+}
 
-    // Prompt construction
-    let final_prompt = if is_vision {
-        // LLaVA format
-        format!("{}\n\n{}\n{}\n{}", SYSTEM_PROMPT, lang_instruction, context_block, query)
-    } else {
-        // Granite Instruct format
-        let user_msg = format!("{}\n{}\n{}", context_block, query, lang_instruction);
-        format!("{}\n\n{}{}\n{}", SYSTEM_PROMPT, user_suffix, user_msg, assistant_prefix)
-    };
+async fn generate_response(http: &HttpClient, provider: &ReplicateProvider, request: &LlmRequest) -> Result<String, LlmError> {
+    let model_id = provider.model_id.as_str();
+    let is_vision = provider.is_vision;
+    let webhooks = provider.webhooks.as_deref();
+    let api_token = provider.api.token.clone()
+        .ok_or_else(|| LlmError::Config("REPLICATE_API_TOKEN not set".to_string()))?;
+
+    let final_prompt = build_prompt(&provider.template, request)?;
 
-    info!("Calling Replicate API with model: {} (Vision: {})", model_id, is_vision);
+    info!(
+        "Calling Replicate API with model: {} (Vision: {}, template: {}, mode: {})",
+        model_id, is_vision, provider.template.family, request.mode.as_str()
+    );
 
     // Build Payload (No version field needed for model endpoint)
     let mut input_obj = serde_json::Map::new();
     input_obj.insert("prompt".to_string(), json!(final_prompt));
-    input_obj.insert("max_tokens".to_string(), json!(500)); // Updated back to max_tokens per user snippet
-    input_obj.insert("temperature".to_string(), json!(0.7));
-    input_obj.insert("top_p".to_string(), json!(0.9));
+    input_obj.insert("max_tokens".to_string(), json!(request.params.max_tokens));
+    input_obj.insert("temperature".to_string(), json!(request.params.temperature));
+    input_obj.insert("top_p".to_string(), json!(request.params.top_p));
     
-    if let Some(img_data) = image {
+    if let (true, Some(img_data)) = (is_vision, &request.image) {
         input_obj.insert("image".to_string(), json!(img_data));
     }
 
-    if !is_vision {
-         input_obj.insert("stop_sequences".to_string(), json!("User:,\n\nUser"));
+    // Replicate's text models take stop sequences as one comma-separated string
+    if !is_vision && !provider.template.stop_sequences.is_empty() {
+        input_obj.insert("stop_sequences".to_string(), json!(provider.template.stop_sequences.join(",")));
     }
 
-    let body = json!({
+    let mut body = json!({
         "input": input_obj
     });
+    if let Some(hooks) = webhooks {
+        body["webhook"] = json!(hooks.callback_url);
+        body["webhook_events_filter"] = json!(["completed"]);
+    }
 
-    let url = format!("https://api.replicate.com/v1/models/{}/predictions", model_id);
+    let url = format!("{}/models/{}/predictions", provider.api.base_url, model_id);
 
-    let res = client.post(&url)
+    // `Prefer: wait` holds the connection for up to 60s, so allow a little more
+    let deadline = Duration::from_secs(
+        env::var("REPLICATE_REQUEST_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(70)
+    );
+    let mut create = http.post(&url)
         .header("Authorization", format!("Bearer {}", api_token))
         .header("Content-Type", "application/json")
-        .header("Prefer", "wait")  // Wait for result synchronously
-        .json(&body)
-        .send()
-        .await?;
-
-    if res.status().is_success() || res.status().as_u16() == 201 {
-        let json_resp: serde_json::Value = res.json().await?;
-        
-        // Check if prediction is completed
-        let status = json_resp["status"].as_str().unwrap_or("unknown");
-        
-        if status == "succeeded" {
-            // Get the output
-            let output = if let Some(output_array) = json_resp["output"].as_array() {
-                output_array.iter()
-                    .filter_map(|v| v.as_str())
-                    .collect::<Vec<&str>>()
-                    .join("")
-            } else if let Some(output_str) = json_resp["output"].as_str() {
-                output_str.to_string()
-            } else {
-                return Ok(get_fallback_response(query, context, target_lang));
-            };
+        .json(&body);
+    // A background job needs the prediction ID to cancel it, so it doesn't hold the
+    // connection open waiting for the result
+    if webhooks.is_none() && request.tracker.is_none() {
+        create = create.header("Prefer", "wait");  // Wait for result synchronously
+    }
 
+    let json_resp = match &request.tracker {
+        // Jobs are cancelled by aborting their task, which may land mid-request. The create runs
+        // detached so the new prediction is still recorded (or cancelled straight away) if it does.
+        Some(tracker) => {
+            let (http, tracker, token, model) = (http.clone(), tracker.clone(), api_token.clone(), model_id.to_string());
+            tokio::spawn(async move {
+                let created = create_prediction(&http, create, deadline, &model).await?;
+                track(&http, &tracker, &created, &token).await;
+                Ok::<_, LlmError>(created)
+            })
+            .await
+            .map_err(|e| LlmError::UpstreamHttp { status: None, message: format!("prediction request task failed: {}", e) })??
+        }
+        None => create_prediction(http, create, deadline, model_id).await?,
+    };
+
+    // Check if prediction is completed
+    match json_resp["status"].as_str().unwrap_or("unknown") {
+        "succeeded" => {
+            let output = extract_output(&json_resp)?;
             info!("Successfully generated response ({} chars)", output.len());
-            Ok(output.trim().to_string())
-        } else if status == "processing" || status == "starting" {
-            // Need to poll for result
-            if let Some(get_url) = json_resp["urls"]["get"].as_str() {
-                poll_for_result(query, &client, get_url, &api_token, target_lang).await
-            } else {
-                warn!("No polling URL available, using fallback");
-                Ok(get_fallback_response(query, context, target_lang))
+            Ok(output)
+        }
+        "processing" | "starting" => {
+            let get_url = json_resp["urls"]["get"].as_str();
+            let interval = provider.api.poll_interval;
+            if let (Some(hooks), Some(id)) = (webhooks, json_resp["id"].as_str()) {
+                return wait_for_webhook(http, hooks, id, get_url, &api_token, interval).await;
             }
-        } else {
-            warn!("Prediction failed with status: {}", status);
-            Ok(get_fallback_response(query, context, target_lang))
+
+            // Need to poll for result
+            let get_url = get_url.ok_or_else(|| LlmError::UpstreamHttp {
+                status: None,
+                message: "No polling URL in prediction response".to_string(),
+            })?;
+            poll_for_result(http, get_url, &api_token, interval, Duration::from_secs(MAX_POLL_SECS)).await
         }
-    } else {
-        let status = res.status();
+        other => {
+            warn!("Prediction failed with status: {}", other);
+            Err(prediction_failed(other, &json_resp))
+        }
+    }
+}
+
+async fn create_prediction(http: &HttpClient, create: RequestBuilder, deadline: Duration, model_id: &str) -> Result<serde_json::Value, LlmError> {
+    let res = http.send(create, deadline).await?;
+
+    let status = res.status();
+    if !status.is_success() {
         let error_text = res.text().await.unwrap_or_default();
         error!("Replicate API Error ({}) for model {}: {}", status, model_id, error_text);
-        
-        // Return fallback response instead of error
-        Ok(get_fallback_response(query, context, target_lang))
+        return Err(http_error(status, error_text));
+    }
+    Ok(res.json().await?)
+}
+
+/// Record a still-running prediction for its job, or cancel it if the job already was
+async fn track(http: &HttpClient, tracker: &PredictionTracker, prediction: &serde_json::Value, api_token: &str) {
+    if !matches!(prediction["status"].as_str(), Some("starting" | "processing")) {
+        return;
+    }
+    let (Some(id), Some(cancel_url)) = (prediction["id"].as_str(), prediction["urls"]["cancel"].as_str()) else {
+        return;
+    };
+    let prediction = UpstreamPrediction {
+        id: id.to_string(),
+        cancel_url: cancel_url.to_string(),
+        api_token: api_token.to_string(),
+    };
+    if tracker.record(prediction.clone()) {
+        return;
+    }
+    info!("Job was canceled while prediction {} was being created; canceling it", id);
+    if let Err(e) = cancel_prediction(http, &prediction).await {
+        warn!("Failed to cancel prediction {}: {}", id, e);
+    }
+}
+
+/// Max time to wait for a started prediction (90 seconds covers cold boots)
+const MAX_POLL_SECS: u64 = 90;
+
+/// Wait for `/api/webhooks/replicate` to deliver the prediction's final state. A delivery
+/// can be lost, so once `hooks.poll_after` passes without one `get_url` is polled as well.
+async fn wait_for_webhook(
+    http: &HttpClient,
+    hooks: &ReplicateWebhooks,
+    prediction_id: &str,
+    get_url: Option<&str>,
+    api_token: &str,
+    interval: Duration,
+) -> Result<String, LlmError> {
+    info!("Waiting for webhook for prediction {}", prediction_id);
+    let max_wait = Duration::from_secs(MAX_POLL_SECS);
+    let delivered = async {
+        let prediction = hooks
+            .wait_for(prediction_id, max_wait)
+            .await
+            .ok_or(LlmError::Timeout { secs: MAX_POLL_SECS })?;
+        match prediction["status"].as_str().unwrap_or("unknown") {
+            "succeeded" => extract_output(&prediction),
+            other => Err(prediction_failed(other, &prediction)),
+        }
+    };
+    let Some(get_url) = get_url.filter(|_| hooks.poll_after < max_wait) else {
+        return delivered.await;
+    };
+    let polled = async {
+        tokio::time::sleep(hooks.poll_after).await;
+        warn!("No webhook for prediction {} after {:?}; polling it", prediction_id, hooks.poll_after);
+        poll_for_result(http, get_url, api_token, interval, max_wait - hooks.poll_after).await
+    };
+
+    tokio::select! {
+        result = delivered => result,
+        result = polled => result,
     }
 }
 
-/// Poll Replicate API for prediction result
-async fn poll_for_result(query: &str, client: &Client, url: &str, api_token: &str, target_lang: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+/// Poll Replicate API for prediction result, for at most `budget`
+async fn poll_for_result(http: &HttpClient, url: &str, api_token: &str, interval: Duration, budget: Duration) -> Result<String, LlmError> {
+    const POLL_REQUEST_DEADLINE: Duration = Duration::from_secs(10);
+
     info!("Polling for result at: {}", url);
-    for attempt in 0..90 {  // Max 90 attempts (90 seconds) for cold boots
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-        
-        let res = client.get(url)
-            .header("Authorization", format!("Bearer {}", api_token))
-            .send()
-            .await?;
-
-        if res.status().is_success() {
-            let json_resp: serde_json::Value = res.json().await?;
-            let status = json_resp["status"].as_str().unwrap_or("unknown");
-            
-            if attempt % 5 == 0 {
-                info!("Poll attempt {}: Status = {}", attempt, status);
+    let started = std::time::Instant::now();
+    let mut attempt = 0;
+    while started.elapsed() < budget {
+        tokio::time::sleep(interval).await;
+        attempt += 1;
+
+        let request = http.get(url)
+            .header("Authorization", format!("Bearer {}", api_token));
+        // The prediction keeps running upstream, so a dropped poll is worth another try
+        let res = match http.send(request, POLL_REQUEST_DEADLINE).await {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("Poll attempt {} failed: {}", attempt, e);
+                continue;
             }
+        };
 
-            match status {
-                "succeeded" => {
-                    let output = if let Some(output_array) = json_resp["output"].as_array() {
-                        output_array.iter()
-                            .filter_map(|v| v.as_str())
-                            .collect::<Vec<&str>>()
-                            .join("")
-                    } else if let Some(output_str) = json_resp["output"].as_str() {
-                        output_str.to_string()
-                    } else {
-                         // Greeting check if LLM returns empty
-                        if is_greeting(query) {
-                             return Ok(get_greeting(target_lang));
-                        }
-                        match target_lang {
-                           "hi" => "क्षमा करें, मैं प्रतिक्रिया उत्पन्न नहीं कर सका।".to_string(),
-                           "mr" => "क्षमस्व, मी प्रतिसाद देऊ शकलो नाही.".to_string(),
-                           _ => "I apologize, but I couldn't generate a response.".to_string()
-                        }
-                    };
-                    return Ok(output.trim().to_string());
-                }
-                "failed" | "canceled" => {
-                    let error_msg = json_resp["error"].as_str().unwrap_or("Unknown error");
-                    error!("Prediction API failed: {}", error_msg);
-                    warn!("Prediction {} after {} attempts. Error: {}", status, attempt, error_msg);
-                    break;
-                }
-                _ => continue,  // Still processing
+        let status = res.status();
+        if !status.is_success() {
+            error!("Poll request failed: {}", status);
+            if matches!(status.as_u16(), 401 | 403) {
+                return Err(LlmError::Auth { status: status.as_u16() });
             }
-        } else {
-             error!("Poll request failed: {}", res.status());
+            continue;
+        }
+
+        let json_resp: serde_json::Value = match res.json().await {
+            Ok(json_resp) => json_resp,
+            Err(e) => {
+                warn!("Poll attempt {} returned an unreadable body: {}", attempt, e);
+                continue;
+            }
+        };
+        let prediction_status = json_resp["status"].as_str().unwrap_or("unknown");
+
+        if attempt % 5 == 0 {
+            info!("Poll attempt {}: Status = {}", attempt, prediction_status);
+        }
+
+        match prediction_status {
+            "succeeded" => return extract_output(&json_resp),
+            "failed" | "canceled" => {
+                let err = prediction_failed(prediction_status, &json_resp);
+                warn!("Prediction {} after {} attempts: {}", prediction_status, attempt, err);
+                return Err(err);
+            }
+            _ => continue,  // Still processing
         }
     }
 
-    warn!("Polling timed out after 90 seconds");
-    // On timeout, if it's a greeting, return greeting!
-    // We don't have query here cleanly, but we can assume fallback handles it
-    Ok(match target_lang {
-        "hi" => "क्षमा करें, प्रतिक्रिया में बहुत समय लग रहा है। कृपया पुनः प्रयास करें।".to_string(),
-        "mr" => "क्षमस्व, प्रतिसादास खूप वेळ लागत आहे. कृपया पुन्हा प्रयत्न करा.".to_string(),
-        _ => "I apologize, but the response is taking too long. Please try again.".to_string()
-    })
+    warn!("Polling timed out after {} seconds", MAX_POLL_SECS);
+    Err(LlmError::Timeout { secs: MAX_POLL_SECS })
+}
+
+/// Cancel a running prediction via its `urls.cancel` endpoint, with the token it was created with
+pub async fn cancel_prediction(http: &HttpClient, prediction: &UpstreamPrediction) -> Result<(), LlmError> {
+    let cancel_url = prediction.cancel_url.as_str();
+    let request = http.post(cancel_url)
+        .header("Authorization", format!("Bearer {}", prediction.api_token));
+    let res = http.send(request, Duration::from_secs(10)).await?;
+
+    let status = res.status();
+    if status.is_success() {
+        info!("Canceled prediction at {}", cancel_url);
+        Ok(())
+    } else {
+        Err(http_error(status, res.text().await.unwrap_or_default()))
+    }
+}
+
+/// Replicate returns either a string or an array of streamed string chunks
+fn extract_output(prediction: &serde_json::Value) -> Result<String, LlmError> {
+    let output = if let Some(output_array) = prediction["output"].as_array() {
+        output_array.iter()
+            .filter_map(|v| v.as_str())
+            .collect::<Vec<&str>>()
+            .join("")
+    } else if let Some(output_str) = prediction["output"].as_str() {
+        output_str.to_string()
+    } else {
+        return Err(LlmError::EmptyOutput);
+    };
+
+    let output = output.trim();
+    if output.is_empty() {
+        return Err(LlmError::EmptyOutput);
+    }
+    Ok(output.to_string())
+}
+
+fn prediction_failed(status: &str, prediction: &serde_json::Value) -> LlmError {
+    LlmError::PredictionFailed {
+        status: status.to_string(),
+        message: prediction["error"].as_str().unwrap_or("Unknown error").to_string(),
+    }
+}
+
+fn http_error(status: reqwest::StatusCode, body: String) -> LlmError {
+    match status.as_u16() {
+        401 | 403 => LlmError::Auth { status: status.as_u16() },
+        code => LlmError::UpstreamHttp { status: Some(code), message: body },
+    }
 }
 
 fn is_greeting(text: &str) -> bool {
@@ -224,7 +434,10 @@ fn is_greeting(text: &str) -> bool {
     matches!(lower.as_str(), "hello" | "hi" | "hey" | "namaste" | "namaskar" | "ram ram" | "sat sri akal" | "greetings")
 }
 
-fn get_greeting(lang: &str) -> String {
+fn get_greeting(lang: &str, persona: &SystemPrompt) -> String {
+    if let Some(greeting) = persona.greeting(lang) {
+        return greeting.to_string();
+    }
     match lang {
         "hi" => "नमस्ते! मैं किसानAI हूँ। मैं आपकी खेती में कैसे सहायता कर सकता हूँ?".to_string(),
         "mr" => "नमस्कार! मी किसानAI आहे. मी तुम्हाला शेतीत कशी मदत करू शकतो?".to_string(),
@@ -233,10 +446,10 @@ fn get_greeting(lang: &str) -> String {
 }
 
 /// Fallback response when API is not available
-pub fn get_fallback_response(query: &str, context: &str, target_lang: &str) -> String {
+pub fn get_fallback_response(query: &str, context: &str, target_lang: &str, persona: &SystemPrompt) -> String {
     // Check for greetings first
     if is_greeting(query) {
-        return get_greeting(target_lang);
+        return get_greeting(target_lang, persona);
     }
 
     let (intro, contact, error_msg) = match target_lang {
@@ -270,3 +483,117 @@ pub fn get_fallback_response(query: &str, context: &str, target_lang: &str) -> S
         format!("{}\n\n{}\n\n{}", intro, context, contact)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::cassette::{Cassette, Interaction};
+    use crate::test_support::{http, llm_request};
+    use std::path::Path;
+
+    fn provider() -> ReplicateProvider {
+        let api = ReplicateApi {
+            base_url: "https://api.replicate.com/v1".to_string(),
+            token: Some("test-token".to_string()),
+            poll_interval: Duration::from_millis(1),
+        };
+        ReplicateProvider::new(DEFAULT_TEXT_MODEL, false, &TemplateRegistry::load(Path::new("/nonexistent")).unwrap(), None, api)
+    }
+
+    fn replaying(cassette: Cassette) -> HttpClient {
+        http().with_cassette(Arc::new(cassette))
+    }
+
+    fn request() -> LlmRequest {
+        LlmRequest {
+            passages: vec!["[1] Wheat: Best sowing time is October to November.".to_string()],
+            ..llm_request("When should I sow wheat?")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replayed_prediction_is_polled_to_completion() {
+        let cassette = Cassette::load(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/cassettes/replicate_polling.json"))).unwrap();
+        let answer = provider().generate(&replaying(cassette), &request()).await.unwrap();
+        assert_eq!(answer, "Sow wheat between October and November [1].");
+    }
+
+    #[tokio::test]
+    async fn test_bad_poll_response_keeps_polling() {
+        let get_url = "https://api.replicate.com/v1/predictions/p1";
+        let interaction = |method: &str, url: &str, response: serde_json::Value| Interaction {
+            method: method.to_string(),
+            url: url.to_string(),
+            request: serde_json::Value::Null,
+            status: if method == "POST" { 201 } else { 200 },
+            retry_after: None,
+            response,
+        };
+        let cassette = Cassette::replay(vec![
+            interaction(
+                "POST",
+                &format!("https://api.replicate.com/v1/models/{}/predictions", DEFAULT_TEXT_MODEL),
+                json!({ "id": "p1", "status": "starting", "urls": { "get": get_url } }),
+            ),
+            interaction("GET", get_url, json!("<html>upstream hiccup</html>")),
+            interaction("GET", get_url, json!({ "id": "p1", "status": "succeeded", "output": ["Sow in November."] })),
+        ]);
+        let answer = provider().generate(&replaying(cassette), &request()).await.unwrap();
+        assert_eq!(answer, "Sow in November.");
+    }
+
+    #[tokio::test]
+    async fn test_failed_prediction_reports_upstream_error() {
+        let cassette = Cassette::replay(vec![Interaction {
+            method: "POST".to_string(),
+            url: format!("https://api.replicate.com/v1/models/{}/predictions", DEFAULT_TEXT_MODEL),
+            request: serde_json::Value::Null,
+            status: 201,
+            retry_after: None,
+            response: json!({ "id": "p1", "status": "failed", "error": "CUDA out of memory" }),
+        }]);
+        let err = provider().generate(&replaying(cassette), &request()).await.unwrap_err();
+        assert_eq!(err.reason(), "prediction_failed");
+        assert!(err.to_string().contains("CUDA out of memory"));
+    }
+
+    #[tokio::test]
+    async fn test_lost_webhook_falls_back_to_polling() {
+        use crate::services::replicate_webhook::ReplicateWebhooks;
+
+        let get_url = "https://api.replicate.com/v1/predictions/p1";
+        let cassette = Cassette::replay(vec![
+            Interaction {
+                method: "POST".to_string(),
+                url: format!("https://api.replicate.com/v1/models/{}/predictions", DEFAULT_TEXT_MODEL),
+                request: serde_json::Value::Null,
+                status: 201,
+                retry_after: None,
+                response: json!({ "id": "p1", "status": "starting", "urls": { "get": get_url } }),
+            },
+            Interaction {
+                method: "GET".to_string(),
+                url: get_url.to_string(),
+                request: serde_json::Value::Null,
+                status: 200,
+                retry_after: None,
+                response: json!({ "id": "p1", "status": "succeeded", "output": ["Sow in November."] }),
+            },
+        ]);
+        let mut hooks = ReplicateWebhooks::new("http://localhost/hook", "whsec_c2VjcmV0").unwrap();
+        hooks.poll_after = Duration::from_millis(20);
+        let mut provider = provider();
+        provider.webhooks = Some(Arc::new(hooks));
+
+        let answer = provider.generate(&replaying(cassette), &request()).await.unwrap();
+        assert_eq!(answer, "Sow in November.");
+    }
+
+    #[tokio::test]
+    async fn test_missing_token_is_a_config_error() {
+        let mut provider = provider();
+        provider.api.token = None;
+        let err = provider.generate(&replaying(Cassette::replay(Vec::new())), &request()).await.unwrap_err();
+        assert_eq!(err.reason(), "config");
+    }
+}
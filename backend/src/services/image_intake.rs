@@ -0,0 +1,386 @@
+//! Photo intake.
+//! Phone photos arrive as multi-megabyte base64 data URIs. Before anything goes upstream they
+//! are size-checked, sniffed (JPEG, PNG or WebP only), decoded with dimension, pixel and
+//! allocation limits, turned upright, downscaled and re-encoded as a JPEG under
+//! `IMAGE_MAX_BYTES`. Re-encoding drops all metadata, EXIF included; GPS and capture time are
+//! read from the original first when the farmer has agreed to share them. Links to photos are
+//! refused, since they could not go through any of this.
+
+use axum::http::StatusCode;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use serde::Deserialize;
+use std::env;
+use std::io::Cursor;
+use thiserror::Error;
+use tracing::info;
+
+use crate::services::image_quality::{self, PhotoStats};
+use crate::services::photo_metadata::{self, PhotoMetadata};
+
+#[derive(Debug, Error)]
+pub enum ImageError {
+    #[error("photo is {bytes} bytes, the limit is {limit}")]
+    TooLarge { bytes: usize, limit: usize },
+
+    #[error("photo is {width}x{height}, the limit is {limit} pixels per side")]
+    TooManyPixels { width: u32, height: u32, limit: u32 },
+
+    #[error("photo is {width}x{height}, the limit is {limit} pixels in total")]
+    TooManyPixelsTotal { width: u32, height: u32, limit: u64 },
+
+    #[error("photo links are not accepted; send the photo itself")]
+    Link,
+
+    #[error("unsupported image type {0}; send a JPEG, PNG or WebP photo")]
+    Unsupported(String),
+
+    #[error("photo could not be read: {0}")]
+    Invalid(String),
+
+    #[error("photo could not be compressed below {limit} bytes")]
+    CannotFit { limit: usize },
+}
+
+impl ImageError {
+    /// 413 for anything too big, 415 for the wrong format, 400 for a broken upload or a link
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ImageError::TooLarge { .. }
+            | ImageError::TooManyPixels { .. }
+            | ImageError::TooManyPixelsTotal { .. }
+            | ImageError::CannotFit { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ImageError::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ImageError::Invalid(_) | ImageError::Link => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageLimits {
+    /// Largest accepted upload, after base64 decoding
+    pub max_upload_bytes: usize,
+    /// Largest accepted width or height before downscaling
+    pub max_input_dimension: u32,
+    /// Largest accepted width times height before downscaling
+    pub max_input_pixels: u64,
+    /// Longest side of the image sent upstream
+    pub max_dimension: u32,
+    /// Largest JPEG sent upstream
+    pub max_bytes: usize,
+}
+
+impl ImageLimits {
+    /// `IMAGE_MAX_UPLOAD_BYTES` (10 MB), `IMAGE_MAX_INPUT_DIMENSION` (10000),
+    /// `IMAGE_MAX_INPUT_PIXELS` (50 million), `IMAGE_MAX_DIMENSION` (1024) and
+    /// `IMAGE_MAX_BYTES` (300 KB)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str, default: usize| env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+        Self {
+            max_upload_bytes: var("IMAGE_MAX_UPLOAD_BYTES", defaults.max_upload_bytes),
+            max_input_dimension: var("IMAGE_MAX_INPUT_DIMENSION", defaults.max_input_dimension as usize) as u32,
+            max_input_pixels: var("IMAGE_MAX_INPUT_PIXELS", defaults.max_input_pixels as usize) as u64,
+            max_dimension: var("IMAGE_MAX_DIMENSION", defaults.max_dimension as usize) as u32,
+            max_bytes: var("IMAGE_MAX_BYTES", defaults.max_bytes),
+        }
+    }
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            max_upload_bytes: 10 * 1024 * 1024,
+            max_input_dimension: 10_000,
+            max_input_pixels: 50_000_000,
+            max_dimension: 1024,
+            max_bytes: 300 * 1024,
+        }
+    }
+}
+
+/// A photo as it arrived: base64 text in a JSON body, or the bytes of a form part
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Photo {
+    Encoded(String),
+    #[serde(skip)]
+    Raw(Vec<u8>),
+}
+
+impl Photo {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Photo::Encoded(text) => text.trim().is_empty(),
+            Photo::Raw(bytes) => bytes.is_empty(),
+        }
+    }
+
+    /// The data URI of a photo that has been through the intake
+    pub fn into_encoded(self) -> Option<String> {
+        match self {
+            Photo::Encoded(text) => Some(text),
+            Photo::Raw(_) => None,
+        }
+    }
+}
+
+/// A photo ready to send upstream
+#[derive(Clone, Debug)]
+pub struct PreparedImage {
+    /// `data:image/jpeg;base64,...`
+    pub data_url: String,
+    pub width: u32,
+    pub height: u32,
+    /// Size of the JPEG
+    pub bytes: usize,
+    /// Measured on the decoded upload
+    pub stats: Option<PhotoStats>,
+    /// Read from the upload's EXIF when asked for; never sent upstream
+    pub metadata: Option<PhotoMetadata>,
+}
+
+/// Lowest JPEG quality tried before the image is made smaller instead
+const MIN_QUALITY: u8 = 40;
+/// Smallest side length worth sending to a vision model
+const MIN_DIMENSION: u32 = 256;
+
+/// Prepare a base64 photo (data URI or bare); `read_metadata` is the farmer's consent to use
+/// the photo's location and date
+pub fn prepare(input: &str, limits: &ImageLimits, read_metadata: bool) -> Result<PreparedImage, ImageError> {
+    let input = input.trim();
+    if input.starts_with("https://") || input.starts_with("http://") {
+        return Err(ImageError::Link);
+    }
+    let encoded = match input.strip_prefix("data:") {
+        Some(rest) => rest.split_once(',').map(|(_, data)| data).ok_or_else(|| ImageError::Invalid("malformed data URI".to_string()))?,
+        None => input,
+    };
+    // Cheap check on the encoded length before allocating for the decoded bytes
+    let approx_bytes = encoded.len() / 4 * 3;
+    if approx_bytes > limits.max_upload_bytes + 3 {
+        return Err(ImageError::TooLarge { bytes: approx_bytes, limit: limits.max_upload_bytes });
+    }
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| ImageError::Invalid(format!("not valid base64 ({})", e)))?;
+    prepare_bytes(&bytes, limits, read_metadata)
+}
+
+/// Prepare raw image bytes
+pub fn prepare_bytes(bytes: &[u8], limits: &ImageLimits, read_metadata: bool) -> Result<PreparedImage, ImageError> {
+    if bytes.len() > limits.max_upload_bytes {
+        return Err(ImageError::TooLarge { bytes: bytes.len(), limit: limits.max_upload_bytes });
+    }
+    let image = decode(bytes, limits)?;
+    let stats = image_quality::measure(&image);
+    let prepared = encode(image, limits)?;
+    let metadata = read_metadata.then(|| photo_metadata::read(bytes));
+    Ok(PreparedImage { stats: Some(stats), metadata, ..prepared })
+}
+
+/// What the magic bytes say, e.g. "image/heic"; used in the 415 message
+fn sniff(bytes: &[u8]) -> String {
+    match image::guess_format(bytes) {
+        Ok(format) => format.to_mime_type().to_string(),
+        Err(_) if bytes.len() > 12 && &bytes[4..8] == b"ftyp" => "image/heic".to_string(),
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+fn decode(bytes: &[u8], limits: &ImageLimits) -> Result<DynamicImage, ImageError> {
+    match image::guess_format(bytes) {
+        Ok(ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP) => {}
+        _ => return Err(ImageError::Unsupported(sniff(bytes))),
+    }
+    let reader = || {
+        ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .map_err(|e| ImageError::Invalid(e.to_string()))
+    };
+    // The header alone gives the dimensions, so oversized photos are refused before decoding
+    let (width, height) = reader()?.into_dimensions().map_err(|e| ImageError::Invalid(e.to_string()))?;
+    if width > limits.max_input_dimension || height > limits.max_input_dimension {
+        return Err(ImageError::TooManyPixels { width, height, limit: limits.max_input_dimension });
+    }
+    if u64::from(width) * u64::from(height) > limits.max_input_pixels {
+        return Err(ImageError::TooManyPixelsTotal { width, height, limit: limits.max_input_pixels });
+    }
+
+    let mut reader = reader()?;
+    let mut decoder_limits = Limits::default();
+    decoder_limits.max_image_width = Some(limits.max_input_dimension);
+    decoder_limits.max_image_height = Some(limits.max_input_dimension);
+    // Room for the allowed pixels at 16-bit RGBA, the widest layout the decoders produce
+    decoder_limits.max_alloc = Some(limits.max_input_pixels.saturating_mul(8));
+    reader.limits(decoder_limits);
+    let mut decoder = reader.into_decoder().map_err(|e| ImageError::Invalid(e.to_string()))?;
+    // Phones store portrait shots sideways with an EXIF rotation; apply it before EXIF is dropped
+    let orientation = decoder.orientation().map_err(|e| ImageError::Invalid(e.to_string()))?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| match e {
+        image::ImageError::Limits(_) => ImageError::TooManyPixels { width, height, limit: limits.max_input_dimension },
+        other => ImageError::Invalid(other.to_string()),
+    })?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Downscale to `max_dimension`, then lower the quality and finally the size until under `max_bytes`
+fn encode(image: DynamicImage, limits: &ImageLimits) -> Result<PreparedImage, ImageError> {
+    let mut image = if image.width().max(image.height()) > limits.max_dimension {
+        image.resize(limits.max_dimension, limits.max_dimension, FilterType::Triangle)
+    } else {
+        image
+    };
+    loop {
+        let rgb = image.to_rgb8();
+        let mut quality = 85;
+        loop {
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, quality)
+                .encode_image(&rgb)
+                .map_err(|e| ImageError::Invalid(e.to_string()))?;
+            if jpeg.len() <= limits.max_bytes {
+                return Ok(PreparedImage {
+                    data_url: format!("data:image/jpeg;base64,{}", base64::engine::general_purpose::STANDARD.encode(&jpeg)),
+                    width: rgb.width(),
+                    height: rgb.height(),
+                    bytes: jpeg.len(),
+                    stats: None,
+                    metadata: None,
+                });
+            }
+            if quality <= MIN_QUALITY {
+                break;
+            }
+            quality -= 15;
+        }
+        let side = image.width().max(image.height()) * 3 / 4;
+        if side < MIN_DIMENSION {
+            return Err(ImageError::CannotFit { limit: limits.max_bytes });
+        }
+        image = image.resize(side, side, FilterType::Triangle);
+    }
+}
+
+/// Decoding and resizing are CPU-bound, so run them off the async workers
+pub async fn prepare_blocking(photo: Photo, limits: ImageLimits, read_metadata: bool) -> Result<PreparedImage, ImageError> {
+    let prepared = tokio::task::spawn_blocking(move || match photo {
+        Photo::Encoded(input) => prepare(&input, &limits, read_metadata),
+        Photo::Raw(bytes) => prepare_bytes(&bytes, &limits, read_metadata),
+    })
+        .await
+        .unwrap_or_else(|e| Err(ImageError::Invalid(e.to_string())))?;
+    if let Some(stats) = &prepared.stats {
+        info!(
+            "Photo re-encoded to {}x{} JPEG, {} bytes (sharpness {:.0}, brightness {:.0})",
+            prepared.width, prepared.height, prepared.bytes, stats.sharpness, stats.brightness
+        );
+    }
+    Ok(prepared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    fn limits() -> ImageLimits {
+        ImageLimits {
+            max_upload_bytes: 16 * 1024 * 1024,
+            max_input_dimension: 5000,
+            max_input_pixels: 20_000_000,
+            max_dimension: 512,
+            max_bytes: 60 * 1024,
+        }
+    }
+
+    /// Noisy gradient, so JPEG can't compress it to nothing
+    fn photo(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let img = ImageBuffer::from_fn(width, height, |x, y| {
+            let noise = ((x * 7919 + y * 104_729) % 61) as u8;
+            Rgb([(x % 256) as u8 ^ noise, (y % 256) as u8, noise.wrapping_mul(4)])
+        });
+        let mut out = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(img).write_to(&mut out, format).unwrap();
+        out.into_inner()
+    }
+
+    fn data_uri(bytes: &[u8], mime: &str) -> String {
+        format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+
+    /// A JPEG carrying an EXIF APP1 segment with only the Orientation tag
+    fn with_orientation(jpeg: &[u8], orientation: u8) -> Vec<u8> {
+        let mut exif = b"Exif\0\0II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+        exif.extend([orientation, 0, 0, 0, 0, 0, 0, 0]);
+        let len = (exif.len() + 2) as u16;
+        let mut out = jpeg[..2].to_vec();
+        out.extend([0xFF, 0xE1]);
+        out.extend(len.to_be_bytes());
+        out.extend(exif);
+        out.extend(&jpeg[2..]);
+        out
+    }
+
+    #[test]
+    fn test_large_png_is_downscaled_to_jpeg_under_cap() {
+        let prepared = prepare(&data_uri(&photo(2000, 1500, ImageFormat::Png), "image/png"), &limits(), false).unwrap();
+        assert!(prepared.data_url.starts_with("data:image/jpeg;base64,"));
+        assert_eq!((prepared.width, prepared.height), (512, 384));
+        assert!(prepared.bytes <= 60 * 1024);
+    }
+
+    #[test]
+    fn test_exif_is_applied_then_stripped() {
+        let jpeg = with_orientation(&photo(300, 200, ImageFormat::Jpeg), 6);
+        assert!(jpeg.windows(4).any(|w| w == b"Exif"));
+
+        let prepared = prepare(&data_uri(&jpeg, "image/jpeg"), &limits(), false).unwrap();
+        assert_eq!((prepared.width, prepared.height), (200, 300));
+        let encoded = prepared.data_url.split_once(',').unwrap().1;
+        let out = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+        assert!(!out.windows(4).any(|w| w == b"Exif"));
+    }
+
+    #[test]
+    fn test_location_is_read_only_with_consent_and_never_forwarded() {
+        use crate::services::photo_metadata::tests::with_gps;
+        let jpeg = with_gps(&photo(300, 200, ImageFormat::Jpeg), 19.99, 73.79, "2024:07:14 09:30:00");
+
+        assert!(prepare_bytes(&jpeg, &limits(), false).unwrap().metadata.is_none());
+        let prepared = prepare_bytes(&jpeg, &limits(), true).unwrap();
+        assert_eq!(prepared.metadata.unwrap().month, Some(7));
+        let encoded = prepared.data_url.split_once(',').unwrap().1;
+        let out = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+        assert!(photo_metadata::read(&out).is_empty());
+    }
+
+    #[test]
+    fn test_rejections() {
+        let gif = b"GIF89a\x01\x00\x01\x00\x00\x00\x00;";
+        let err = prepare(&data_uri(gif, "image/gif"), &limits(), false).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(err.to_string(), "unsupported image type image/gif; send a JPEG, PNG or WebP photo");
+
+        let tiny = ImageLimits { max_upload_bytes: 1000, ..limits() };
+        let err = prepare(&data_uri(&photo(300, 200, ImageFormat::Png), "image/png"), &tiny, false).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let narrow = ImageLimits { max_input_dimension: 100, ..limits() };
+        let err = prepare_bytes(&photo(300, 50, ImageFormat::Png), &narrow, false).unwrap_err();
+        assert!(matches!(err, ImageError::TooManyPixels { width: 300, height: 50, .. }));
+
+        let few = ImageLimits { max_input_pixels: 10_000, ..limits() };
+        let err = prepare_bytes(&photo(200, 100, ImageFormat::Png), &few, false).unwrap_err();
+        assert!(matches!(err, ImageError::TooManyPixelsTotal { width: 200, height: 100, limit: 10_000 }));
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        assert_eq!(prepare("data:image/jpeg;base64,@@@", &limits(), false).unwrap_err().status_code(), StatusCode::BAD_REQUEST);
+        let err = prepare("https://example.org/leaf.jpg", &limits(), false).unwrap_err();
+        assert!(matches!(err, ImageError::Link));
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,196 @@
+//! Photo quality checks.
+//! Blurry, dark or tiny leaf photos get confident nonsense back from vision models, so each
+//! decoded photo is measured locally (Laplacian-variance sharpness, mean brightness and size)
+//! and failing ones are sent back to the farmer with a retake request instead of upstream.
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde::Serialize;
+use std::env;
+
+/// `answered_by` for replies that asked for a retake instead of calling a model
+pub const PHOTO_CHECK: &str = "photo_check";
+
+/// Side length sharpness is measured at, so the threshold doesn't depend on camera resolution
+const MEASURE_DIMENSION: u32 = 512;
+
+/// What was measured on a decoded photo, before it was downscaled
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PhotoStats {
+    pub width: u32,
+    pub height: u32,
+    /// Variance of the Laplacian of the grayscale image; low means blurred
+    pub sharpness: f32,
+    /// Mean luma, 0-255
+    pub brightness: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityIssue {
+    Blurry,
+    TooDark,
+    TooBright,
+    TooSmall,
+}
+
+/// A photo left out of a request, numbered from 1 in upload order
+#[derive(Clone, Debug, Serialize)]
+pub struct RejectedPhoto {
+    pub photo: usize,
+    pub issues: Vec<QualityIssue>,
+    /// Why the vision model could not analyse a photo that passed the quality check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QualityLimits {
+    pub min_sharpness: f32,
+    pub min_brightness: f32,
+    pub max_brightness: f32,
+    /// Shortest side, in pixels
+    pub min_dimension: u32,
+}
+
+impl Default for QualityLimits {
+    fn default() -> Self {
+        Self { min_sharpness: 40.0, min_brightness: 45.0, max_brightness: 225.0, min_dimension: 320 }
+    }
+}
+
+impl QualityLimits {
+    /// `PHOTO_MIN_SHARPNESS` (40), `PHOTO_MIN_BRIGHTNESS` (45), `PHOTO_MAX_BRIGHTNESS` (225) and
+    /// `PHOTO_MIN_DIMENSION` (320); set a minimum to 0 (or the maximum to 255) to skip that check
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str, default: f32| env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+        Self {
+            min_sharpness: var("PHOTO_MIN_SHARPNESS", defaults.min_sharpness),
+            min_brightness: var("PHOTO_MIN_BRIGHTNESS", defaults.min_brightness),
+            max_brightness: var("PHOTO_MAX_BRIGHTNESS", defaults.max_brightness),
+            min_dimension: var("PHOTO_MIN_DIMENSION", defaults.min_dimension as f32) as u32,
+        }
+    }
+
+    /// Everything wrong with a photo; empty when it is good enough to send
+    pub fn issues(&self, stats: &PhotoStats) -> Vec<QualityIssue> {
+        let mut issues = Vec::new();
+        if stats.width.min(stats.height) < self.min_dimension {
+            issues.push(QualityIssue::TooSmall);
+        }
+        if stats.brightness < self.min_brightness {
+            issues.push(QualityIssue::TooDark);
+        } else if stats.brightness > self.max_brightness {
+            issues.push(QualityIssue::TooBright);
+        }
+        // A dark or washed-out frame has no edges either, so only call it blurry when exposure is fine
+        if issues.iter().all(|i| *i == QualityIssue::TooSmall) && stats.sharpness < self.min_sharpness {
+            issues.push(QualityIssue::Blurry);
+        }
+        issues
+    }
+}
+
+pub fn measure(image: &DynamicImage) -> PhotoStats {
+    let (width, height) = (image.width(), image.height());
+    let small = if width.max(height) > MEASURE_DIMENSION {
+        image.resize(MEASURE_DIMENSION, MEASURE_DIMENSION, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+    let gray = small.to_luma8();
+    let (w, h) = gray.dimensions();
+    let pixels = gray.as_raw();
+    let brightness = pixels.iter().map(|&p| p as f64).sum::<f64>() / pixels.len().max(1) as f64;
+
+    // 4-neighbour Laplacian over the interior
+    let at = |x: u32, y: u32| pixels[(y * w + x) as usize] as f64;
+    let (mut sum, mut sum_sq, mut n) = (0.0, 0.0, 0.0);
+    for y in 1..h.saturating_sub(1) {
+        for x in 1..w.saturating_sub(1) {
+            let lap = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+            sum += lap;
+            sum_sq += lap * lap;
+            n += 1.0;
+        }
+    }
+    let sharpness = if n > 0.0 { sum_sq / n - (sum / n).powi(2) } else { 0.0 };
+
+    PhotoStats { width, height, sharpness: sharpness as f32, brightness: brightness as f32 }
+}
+
+/// Ask the farmer, in their language, to take the photo again and say how
+pub fn retake_message(issues: &[QualityIssue], lang: &str) -> String {
+    let (intro, blurry, dark, bright, small) = match lang {
+        "hi" => (
+            "कृपया फोटो दोबारा लें।",
+            "फोटो धुंधली है: फोन को स्थिर रखें और पत्ती पर टैप करके फोकस करें।",
+            "फोटो बहुत अंधेरी है: दिन की रोशनी में फोटो लें।",
+            "फोटो में बहुत ज़्यादा रोशनी है: सीधी धूप से हटकर छाया में फोटो लें।",
+            "फोटो बहुत छोटी या दूर से ली गई है: पास जाएं ताकि प्रभावित पत्ती पूरी फ्रेम में आए।",
+        ),
+        "mr" => (
+            "कृपया फोटो पुन्हा काढा.",
+            "फोटो धूसर आहे: फोन स्थिर धरा आणि पानावर टॅप करून फोकस करा.",
+            "फोटो खूप अंधारा आहे: दिवसाच्या प्रकाशात फोटो काढा.",
+            "फोटोत खूप जास्त प्रकाश आहे: थेट उन्हाऐवजी सावलीत फोटो काढा.",
+            "फोटो खूप लहान किंवा लांबून काढलेला आहे: जवळ जा म्हणजे बाधित पान पूर्ण फ्रेममध्ये येईल.",
+        ),
+        _ => (
+            "Please take the photo again.",
+            "It is blurry: hold the phone steady and tap the leaf to focus.",
+            "It is too dark: take it in daylight.",
+            "It is too bright: take it in shade, out of direct sun.",
+            "It is too small or taken from too far away: move closer so the affected leaf fills the frame.",
+        ),
+    };
+    let mut parts = vec![intro];
+    for issue in issues {
+        parts.push(match issue {
+            QualityIssue::Blurry => blurry,
+            QualityIssue::TooDark => dark,
+            QualityIssue::TooBright => bright,
+            QualityIssue::TooSmall => small,
+        });
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn limits() -> QualityLimits {
+        QualityLimits { min_sharpness: 40.0, min_brightness: 45.0, max_brightness: 225.0, min_dimension: 320 }
+    }
+
+    /// Leaf-like texture: veins every few pixels over a mid green
+    fn leaf(width: u32, height: u32, scale: f32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let vein = if (x + y) % 6 < 2 || (x * 3 + y) % 23 == 0 { 70.0 } else { 0.0 };
+            let g = (110.0 + vein) * scale;
+            Rgb([(g * 0.5) as u8, g.min(255.0) as u8, (g * 0.4) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_sharp_photo_passes_and_blurred_copy_fails() {
+        let sharp = leaf(800, 600, 1.0);
+        assert!(limits().issues(&measure(&sharp)).is_empty(), "{:?}", measure(&sharp));
+
+        let blurred = sharp.blur(6.0);
+        assert_eq!(limits().issues(&measure(&blurred)), vec![QualityIssue::Blurry]);
+    }
+
+    #[test]
+    fn test_exposure_and_size() {
+        assert_eq!(limits().issues(&measure(&leaf(800, 600, 0.2))), vec![QualityIssue::TooDark]);
+        let small = measure(&leaf(200, 150, 1.0));
+        assert_eq!(limits().issues(&small), vec![QualityIssue::TooSmall]);
+
+        let message = retake_message(&[QualityIssue::TooDark], "hi");
+        assert!(message.contains("दिन की रोशनी"));
+    }
+}
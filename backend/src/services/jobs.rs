@@ -0,0 +1,153 @@
+//! In-memory store for asynchronous prediction jobs.
+//! Lets clients on flaky connections submit a query, disconnect, and collect
+//! the answer later via `GET /api/jobs/{id}`.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tokio::task::AbortHandle;
+
+use super::llm::PredictionTracker;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Succeeded,
+    Failed,
+    Canceled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        self != JobStatus::Pending
+    }
+}
+
+/// Client-facing view of a job
+#[derive(Clone, Serialize)]
+pub struct JobView {
+    pub id: String,
+    pub status: JobStatus,
+    pub created_at: u64, // unix seconds
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+struct Job {
+    view: JobView,
+    created: Instant,
+    task: Option<AbortHandle>,
+    tracker: Arc<PredictionTracker>,
+}
+
+pub struct JobStore {
+    jobs: RwLock<HashMap<String, Job>>,
+    ttl: Duration,
+}
+
+impl JobStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self { jobs: RwLock::new(HashMap::new()), ttl }
+    }
+
+    /// TTL from `JOB_TTL_SECS` (default one hour)
+    pub fn from_env() -> Self {
+        let ttl = env::var("JOB_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(3600);
+        Self::new(Duration::from_secs(ttl))
+    }
+
+    /// Register a new pending job and return its id plus the tracker its
+    /// upstream predictions should be recorded in
+    pub async fn create(&self) -> (String, Arc<PredictionTracker>) {
+        let id = uuid::Uuid::new_v4().to_string();
+        let tracker = Arc::new(PredictionTracker::default());
+        let created_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        let mut jobs = self.jobs.write().await;
+        let ttl = self.ttl;
+        jobs.retain(|_, job| job.created.elapsed() < ttl);
+        jobs.insert(id.clone(), Job {
+            view: JobView { id: id.clone(), status: JobStatus::Pending, created_at, result: None, error: None },
+            created: Instant::now(),
+            task: None,
+            tracker: tracker.clone(),
+        });
+        (id, tracker)
+    }
+
+    /// Attach the task running the job so it can be aborted on cancel
+    pub async fn attach_task(&self, id: &str, task: AbortHandle) {
+        if let Some(job) = self.jobs.write().await.get_mut(id) {
+            job.task = Some(task);
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Option<JobView> {
+        self.jobs.read().await.get(id).map(|job| job.view.clone())
+    }
+
+    /// Record the outcome. Ignored if the job was already canceled.
+    pub async fn finish(&self, id: &str, outcome: Result<serde_json::Value, String>) {
+        if let Some(job) = self.jobs.write().await.get_mut(id) {
+            if job.view.status.is_finished() {
+                return;
+            }
+            match outcome {
+                Ok(result) => {
+                    job.view.status = JobStatus::Succeeded;
+                    job.view.result = Some(result);
+                }
+                Err(error) => {
+                    job.view.status = JobStatus::Failed;
+                    job.view.error = Some(error);
+                }
+            }
+            job.task = None;
+        }
+    }
+
+    /// Mark a pending job canceled and abort its task.
+    /// Returns the job view and the tracker holding any upstream predictions to cancel.
+    pub async fn cancel(&self, id: &str) -> Option<(JobView, Arc<PredictionTracker>)> {
+        let mut jobs = self.jobs.write().await;
+        let job = jobs.get_mut(id)?;
+        if !job.view.status.is_finished() {
+            job.view.status = JobStatus::Canceled;
+            if let Some(task) = job.task.take() {
+                task.abort();
+            }
+        }
+        Some((job.view.clone(), job.tracker.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_wins_over_late_result() {
+        let store = JobStore::new(Duration::from_secs(60));
+        let (id, _) = store.create().await;
+
+        let (view, _) = store.cancel(&id).await.unwrap();
+        assert_eq!(view.status, JobStatus::Canceled);
+
+        store.finish(&id, Ok(serde_json::json!({"answer": "late"}))).await;
+        let view = store.get(&id).await.unwrap();
+        assert_eq!(view.status, JobStatus::Canceled);
+        assert!(view.result.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_jobs_are_purged() {
+        let store = JobStore::new(Duration::from_millis(0));
+        let (old, _) = store.create().await;
+        let (_new, _) = store.create().await;
+        assert!(store.get(&old).await.is_none());
+    }
+}
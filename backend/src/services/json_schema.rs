@@ -0,0 +1,136 @@
+//! Minimal JSON Schema validation.
+//! Covers the subset our tool and output schemas use: `type`, `enum`, `properties`,
+//! `required`, `additionalProperties: false`, `items`, `minimum`/`maximum`,
+//! `minLength`/`maxLength` and `minItems`/`maxItems`. Unknown keywords are ignored.
+
+use serde_json::Value;
+
+/// Check `value` against `schema`; the error names the first offending path, e.g. `$.days`
+pub fn validate(schema: &Value, value: &Value) -> Result<(), String> {
+    check(schema, value, "$")
+}
+
+fn type_matches(name: &str, value: &Value) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn check(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    match &schema["type"] {
+        Value::String(name) if !type_matches(name, value) => {
+            return Err(format!("{} should be {}", path, name));
+        }
+        Value::Array(names) if !names.iter().filter_map(Value::as_str).any(|n| type_matches(n, value)) => {
+            return Err(format!("{} should be one of {}", path, schema["type"]));
+        }
+        _ => {}
+    }
+
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            return Err(format!("{} should be one of {}", path, schema["enum"]));
+        }
+    }
+
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = schema["minimum"].as_f64().filter(|min| n < *min) {
+            return Err(format!("{} should be at least {}", path, min));
+        }
+        if let Some(max) = schema["maximum"].as_f64().filter(|max| n > *max) {
+            return Err(format!("{} should be at most {}", path, max));
+        }
+    }
+
+    if let Some(s) = value.as_str() {
+        let len = s.chars().count() as u64;
+        if schema["minLength"].as_u64().is_some_and(|min| len < min) {
+            return Err(format!("{} is too short", path));
+        }
+        if schema["maxLength"].as_u64().is_some_and(|max| len > max) {
+            return Err(format!("{} is too long", path));
+        }
+    }
+
+    if let Some(items) = value.as_array() {
+        if schema["minItems"].as_u64().is_some_and(|min| (items.len() as u64) < min) {
+            return Err(format!("{} needs at least {} items", path, schema["minItems"]));
+        }
+        if schema["maxItems"].as_u64().is_some_and(|max| items.len() as u64 > max) {
+            return Err(format!("{} allows at most {} items", path, schema["maxItems"]));
+        }
+        if schema["items"].is_object() {
+            for (i, item) in items.iter().enumerate() {
+                check(&schema["items"], item, &format!("{}[{}]", path, i))?;
+            }
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        for name in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+            if object.get(name).is_none_or(Value::is_null) {
+                return Err(format!("{}.{} is required", path, name));
+            }
+        }
+        let properties = schema["properties"].as_object();
+        for (name, field) in object {
+            match properties.and_then(|p| p.get(name)) {
+                Some(field_schema) => check(field_schema, field, &format!("{}.{}", path, name))?,
+                None if schema["additionalProperties"] == Value::Bool(false) => {
+                    return Err(format!("{}.{} is not allowed", path, name));
+                }
+                None => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "commodity": { "type": "string", "minLength": 2 },
+                "days": { "type": "integer", "minimum": 1, "maximum": 7 },
+                "unit": { "enum": ["acre", "hectare"] },
+                "tags": { "type": "array", "items": { "type": "string" }, "maxItems": 2 }
+            },
+            "required": ["commodity"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn test_valid_value_passes() {
+        let value = json!({ "commodity": "onion", "days": 3, "unit": "acre", "tags": ["rabi"] });
+        assert_eq!(validate(&schema(), &value), Ok(()));
+    }
+
+    #[test]
+    fn test_errors_name_the_offending_path() {
+        let cases = [
+            (json!({ "days": 3 }), "$.commodity is required"),
+            (json!({ "commodity": "onion", "days": 2.5 }), "$.days should be integer"),
+            (json!({ "commodity": "onion", "days": 9 }), "$.days should be at most 7"),
+            (json!({ "commodity": "onion", "unit": "bigha" }), "$.unit should be one of [\"acre\",\"hectare\"]"),
+            (json!({ "commodity": "onion", "tags": ["a", 1] }), "$.tags[1] should be string"),
+            (json!({ "commodity": "onion", "price": 10 }), "$.price is not allowed"),
+            (json!("onion"), "$ should be object"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(validate(&schema(), &value), Err(expected.to_string()));
+        }
+    }
+}
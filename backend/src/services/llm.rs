@@ -0,0 +1,97 @@
+//! Common interface for text-generation backends.
+//! Each link in the model chain (Replicate models, a local model) implements `LlmProvider`.
+
+use async_trait::async_trait;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+use super::error::LlmError;
+use super::generation::{GenerationParams, ResponseMode};
+use super::http::HttpClient;
+use super::sessions::Turn;
+
+/// Everything a provider needs to produce an answer
+#[derive(Clone, Debug)]
+pub struct LlmRequest {
+    /// Persona and scope rules from the deployment's prompt profile
+    pub system_prompt: String,
+    /// Prior conversation turns that fit the history budget
+    pub history: Vec<Turn>,
+    pub query: String,
+    /// Retrieved passages, best first; trimmed to fit each model's context window
+    pub passages: Vec<String>,
+    pub image: Option<String>,
+    pub target_lang: String,
+    pub mode: ResponseMode,
+    /// Already clamped to the server's limits for `mode`
+    pub params: GenerationParams,
+    /// JSON schema the answer must follow (structured output); providers that can constrain
+    /// decoding to it do, the others rely on the prompt
+    pub output_schema: Option<Value>,
+    /// Set for background jobs so upstream predictions can be cancelled
+    pub tracker: Option<Arc<PredictionTracker>>,
+}
+
+/// An upstream prediction started on behalf of a request
+#[derive(Clone)]
+pub struct UpstreamPrediction {
+    pub id: String,
+    pub cancel_url: String,
+    /// Credential the prediction was created with, needed to cancel it
+    pub api_token: String,
+}
+
+impl std::fmt::Debug for UpstreamPrediction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UpstreamPrediction")
+            .field("id", &self.id)
+            .field("cancel_url", &self.cancel_url)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Records the upstream predictions a job has started, and whether the job was cancelled
+#[derive(Default, Debug)]
+pub struct PredictionTracker {
+    state: Mutex<Tracked>,
+}
+
+#[derive(Default, Debug)]
+struct Tracked {
+    predictions: Vec<UpstreamPrediction>,
+    canceled: bool,
+}
+
+impl PredictionTracker {
+    /// Remember a started prediction. Returns false when the job has already been cancelled;
+    /// the caller then cancels the prediction itself.
+    pub fn record(&self, prediction: UpstreamPrediction) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.canceled {
+            return false;
+        }
+        state.predictions.push(prediction);
+        true
+    }
+
+    /// Mark the job cancelled and hand back the predictions started so far
+    pub fn cancel(&self) -> Vec<UpstreamPrediction> {
+        let mut state = self.state.lock().unwrap();
+        state.canceled = true;
+        std::mem::take(&mut state.predictions)
+    }
+}
+
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Stable label reported to clients, e.g. `replicate:ibm-granite/granite-3.3-8b-instruct`
+    fn name(&self) -> &str;
+
+    async fn generate(&self, http: &HttpClient, request: &LlmRequest) -> Result<String, LlmError>;
+
+    /// How many of `request.passages` fit in this provider's prompt, so citation markers
+    /// can be checked against the passages the model actually saw
+    fn passages_kept(&self, request: &LlmRequest) -> usize {
+        request.passages.len()
+    }
+}
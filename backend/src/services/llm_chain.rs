@@ -0,0 +1,293 @@
+//! Ordered fallback chain across LLM providers.
+//! Links are tried in order, each behind its own circuit breaker and deadline,
+//! so a degraded provider is skipped instantly instead of burning the request.
+//! The extractive knowledge-base answer is the implicit last link (see `rag::generator`).
+
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use super::circuit_breaker::CircuitBreaker;
+use super::error::LlmError;
+use super::http::HttpClient;
+use super::ibm_granite::{ReplicateApi, ReplicateProvider, DEFAULT_TEXT_MODEL, DEFAULT_VISION_MODEL};
+use super::llm::{LlmProvider, LlmRequest};
+use super::local_llm::LocalProvider;
+use super::mock_llm::MockProvider;
+use super::replicate_webhook::ReplicateWebhooks;
+use crate::rag::templates::TemplateRegistry;
+
+const DEFAULT_LINK_DEADLINE_SECS: u64 = 90;
+
+pub struct ChainLink {
+    provider: Arc<dyn LlmProvider>,
+    breaker: CircuitBreaker,
+    deadline: Duration,
+}
+
+impl ChainLink {
+    pub fn new(provider: Arc<dyn LlmProvider>, breaker: CircuitBreaker, deadline: Duration) -> Self {
+        Self { provider, breaker, deadline }
+    }
+}
+
+/// Text produced by one link of the chain
+pub struct ChainAnswer {
+    pub text: String,
+    pub answered_by: String,
+    /// Leading passages of the request that made it into the prompt
+    pub passages_used: usize,
+}
+
+pub struct LlmChain {
+    links: Vec<ChainLink>,
+}
+
+impl LlmChain {
+    pub fn new(links: Vec<ChainLink>) -> Self {
+        Self { links }
+    }
+
+    /// Text chain from `LLM_CHAIN`, defaulting to `REPLICATE_MODEL_VERSION`
+    pub fn text_from_env(templates: &TemplateRegistry, webhooks: Option<Arc<ReplicateWebhooks>>) -> Self {
+        let default = format!(
+            "replicate:{}",
+            env::var("REPLICATE_MODEL_VERSION").unwrap_or_else(|_| DEFAULT_TEXT_MODEL.to_string())
+        );
+        Self::parse(&env::var("LLM_CHAIN").unwrap_or(default), false, templates, webhooks)
+    }
+
+    /// Vision chain from `LLM_VISION_CHAIN`, defaulting to `REPLICATE_VISION_MODEL`
+    pub fn vision_from_env(templates: &TemplateRegistry, webhooks: Option<Arc<ReplicateWebhooks>>) -> Self {
+        let default = format!(
+            "replicate:{}",
+            env::var("REPLICATE_VISION_MODEL").unwrap_or_else(|_| DEFAULT_VISION_MODEL.to_string())
+        );
+        Self::parse(&env::var("LLM_VISION_CHAIN").unwrap_or(default), true, templates, webhooks)
+    }
+
+    /// Parse `kind:model[@deadline_secs]` entries separated by commas, e.g.
+    /// `replicate:ibm-granite/granite-3.3-8b-instruct@40,local:llama3.2:1b@20,extractive`.
+    /// `mock:<name>` is a deterministic offline model (see `mock_llm`).
+    /// Breakers use `CIRCUIT_FAILURE_THRESHOLD` and `CIRCUIT_COOLDOWN_SECS`.
+    pub fn parse(spec: &str, is_vision: bool, templates: &TemplateRegistry, webhooks: Option<Arc<ReplicateWebhooks>>) -> Self {
+        let threshold = env::var("CIRCUIT_FAILURE_THRESHOLD").ok().and_then(|v| v.parse().ok()).unwrap_or(3);
+        let cooldown = env::var("CIRCUIT_COOLDOWN_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30);
+        let replicate_api = ReplicateApi::from_env();
+
+        let links = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty() && *entry != "extractive")
+            .filter_map(|entry| {
+                let (kind, rest) = entry.split_once(':')?;
+                let (model, deadline) = match rest.rsplit_once('@') {
+                    Some((model, secs)) => (model, secs.parse().unwrap_or(DEFAULT_LINK_DEADLINE_SECS)),
+                    None => (rest, DEFAULT_LINK_DEADLINE_SECS),
+                };
+                let provider: Arc<dyn LlmProvider> = match kind {
+                    "replicate" => Arc::new(ReplicateProvider::new(model, is_vision, templates, webhooks.clone(), replicate_api.clone())),
+                    "local" => Arc::new(LocalProvider::new(model, templates)),
+                    "mock" => Arc::new(MockProvider::new(model)),
+                    other => {
+                        warn!("Ignoring unknown LLM chain provider '{}'", other);
+                        return None;
+                    }
+                };
+                Some(ChainLink::new(
+                    provider,
+                    CircuitBreaker::new(threshold, Duration::from_secs(cooldown)),
+                    Duration::from_secs(deadline),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        info!(
+            "LLM chain ({}): {}",
+            if is_vision { "vision" } else { "text" },
+            links.iter().map(|l| l.provider.name()).collect::<Vec<_>>().join(" -> ")
+        );
+        Self::new(links)
+    }
+
+    /// Try each link in order and return the first answer.
+    /// On total failure, prefers an error that permits the extractive fallback so that
+    /// a misconfigured secondary link doesn't hide an outage of the primary.
+    pub async fn generate(&self, http: &HttpClient, request: &LlmRequest) -> Result<ChainAnswer, LlmError> {
+        self.run(http, request, None).await
+    }
+
+    /// Like `generate`, but gives up once `budget` has passed. A link cut short by the
+    /// budget rather than its own deadline isn't counted against its breaker.
+    pub async fn generate_within(&self, http: &HttpClient, request: &LlmRequest, budget: Duration) -> Result<ChainAnswer, LlmError> {
+        self.run(http, request, Some(Instant::now() + budget)).await
+    }
+
+    async fn run(&self, http: &HttpClient, request: &LlmRequest, give_up_at: Option<Instant>) -> Result<ChainAnswer, LlmError> {
+        let mut errors: Vec<LlmError> = Vec::new();
+
+        for link in &self.links {
+            let name = link.provider.name();
+            let remaining = give_up_at.map(|at| at.saturating_duration_since(Instant::now()));
+            if remaining == Some(Duration::ZERO) {
+                break;
+            }
+            let Some(permit) = link.breaker.try_acquire() else {
+                info!("Skipping {} (circuit open)", name);
+                errors.push(LlmError::CircuitOpen(name.to_string()));
+                continue;
+            };
+
+            let deadline = remaining.map_or(link.deadline, |r| r.min(link.deadline));
+            let result = tokio::time::timeout(deadline, link.provider.generate(http, request))
+                .await
+                .unwrap_or(Err(LlmError::Timeout { secs: deadline.as_secs() }));
+
+            match result {
+                Ok(text) => {
+                    permit.success();
+                    let passages_used = link.provider.passages_kept(request);
+                    return Ok(ChainAnswer { text, answered_by: name.to_string(), passages_used });
+                }
+                Err(e) => {
+                    // A configuration error or the caller's own budget says nothing about the
+                    // provider's health
+                    let cut_short = deadline < link.deadline && matches!(e, LlmError::Timeout { .. });
+                    if !matches!(e, LlmError::Config(_)) && !cut_short {
+                        permit.failure();
+                    }
+                    warn!("LLM link {} failed: {} (breaker {})", name, e, link.breaker.state_label());
+                    errors.push(e);
+                }
+            }
+        }
+
+        let fallback_ok = errors.iter().position(LlmError::allows_fallback);
+        match fallback_ok {
+            Some(i) => Err(errors.swap_remove(i)),
+            None if !errors.is_empty() => Err(errors.swap_remove(0)),
+            None => Err(LlmError::Config("LLM chain has no providers".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{http, llm_request};
+    use async_trait::async_trait;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Scripted {
+        name: String,
+        fail: bool,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl LlmProvider for Scripted {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn generate(&self, _http: &HttpClient, _request: &LlmRequest) -> Result<String, LlmError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fail {
+                Err(LlmError::UpstreamHttp { status: Some(503), message: String::new() })
+            } else {
+                Ok(format!("answer from {}", self.name))
+            }
+        }
+    }
+
+    fn scripted(name: &str, fail: bool) -> Arc<Scripted> {
+        Arc::new(Scripted { name: name.to_string(), fail, calls: AtomicUsize::new(0) })
+    }
+
+    fn link(provider: Arc<Scripted>) -> ChainLink {
+        ChainLink::new(provider, CircuitBreaker::new(1, Duration::from_secs(60)), Duration::from_secs(5))
+    }
+
+    fn request() -> LlmRequest {
+        llm_request("wheat")
+    }
+
+    #[tokio::test]
+    async fn test_falls_through_and_skips_open_breaker() {
+        let primary = scripted("primary", true);
+        let secondary = scripted("secondary", false);
+        let chain = LlmChain::new(vec![link(primary.clone()), link(secondary.clone())]);
+        let http = http();
+
+        let answer = chain.generate(&http, &request()).await.unwrap();
+        assert_eq!(answer.answered_by, "secondary");
+
+        // Breaker opened after one failure, so the primary isn't called again
+        let answer = chain.generate(&http, &request()).await.unwrap();
+        assert_eq!(answer.answered_by, "secondary");
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_total_failure_allows_fallback() {
+        let chain = LlmChain::new(vec![link(scripted("only", true))]);
+        let err = chain.generate(&http(), &request()).await.err().unwrap();
+        assert!(err.allows_fallback());
+    }
+
+    /// Fails, then hangs, then answers
+    struct Flaky {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl LlmProvider for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn generate(&self, _http: &HttpClient, _request: &LlmRequest) -> Result<String, LlmError> {
+            match self.calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(LlmError::UpstreamHttp { status: Some(503), message: String::new() }),
+                1 => std::future::pending().await,
+                _ => Ok("recovered".to_string()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dropped_probe_does_not_wedge_the_breaker() {
+        let provider = Arc::new(Flaky { calls: AtomicUsize::new(0) });
+        let chain = LlmChain::new(vec![ChainLink::new(provider, CircuitBreaker::new(1, Duration::ZERO), Duration::from_secs(5))]);
+        let http = http();
+        assert!(chain.generate(&http, &request()).await.is_err());
+
+        // The half-open probe hangs and its future is dropped, as on client disconnect or cancel
+        let probe = tokio::time::timeout(Duration::from_millis(20), chain.generate(&http, &request())).await;
+        assert!(probe.is_err());
+
+        let answer = chain.generate(&http, &request()).await.unwrap();
+        assert_eq!(answer.text, "recovered");
+    }
+
+    #[tokio::test]
+    async fn test_budget_timeout_does_not_trip_the_breaker() {
+        let provider = Arc::new(Flaky { calls: AtomicUsize::new(1) });
+        let chain = LlmChain::new(vec![ChainLink::new(provider, CircuitBreaker::new(1, Duration::from_secs(60)), Duration::from_secs(5))]);
+        let http = http();
+
+        let err = chain.generate_within(&http, &request(), Duration::from_millis(20)).await.err().unwrap();
+        assert!(matches!(err, LlmError::Timeout { .. }));
+        assert_eq!(chain.generate(&http, &request()).await.unwrap().text, "recovered");
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let chain = LlmChain::parse("replicate:ibm-granite/granite-3.3-8b-instruct@40, local:llama3.2:1b, extractive", false, &TemplateRegistry::load(Path::new("/nonexistent")).unwrap(), None);
+        let names: Vec<_> = chain.links.iter().map(|l| l.provider.name().to_string()).collect();
+        assert_eq!(names, ["replicate:ibm-granite/granite-3.3-8b-instruct", "local:llama3.2:1b"]);
+        assert_eq!(chain.links[0].deadline, Duration::from_secs(40));
+    }
+}
@@ -0,0 +1,84 @@
+//! Local model served over an Ollama-compatible `/api/generate` endpoint.
+//! Cheap last resort before the extractive fallback when hosted models are down.
+
+use async_trait::async_trait;
+use serde_json::json;
+use std::env;
+use std::time::Duration;
+use tracing::{error, info};
+
+use super::error::LlmError;
+use super::http::HttpClient;
+use super::ibm_granite::{build_prompt, fit_request};
+use super::llm::{LlmProvider, LlmRequest};
+use crate::rag::templates::{PromptTemplate, TemplateRegistry};
+
+pub struct LocalProvider {
+    base_url: String,
+    model: String,
+    label: String,
+    template: PromptTemplate,
+}
+
+impl LocalProvider {
+    /// `LOCAL_LLM_URL` defaults to a local Ollama instance
+    pub fn new(model: &str, templates: &TemplateRegistry) -> Self {
+        let base_url = env::var("LOCAL_LLM_URL").unwrap_or_else(|_| "http://localhost:11434".to_string());
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model: model.to_string(),
+            label: format!("local:{}", model),
+            template: templates.for_model(model),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for LocalProvider {
+    fn name(&self) -> &str {
+        &self.label
+    }
+
+    async fn generate(&self, http: &HttpClient, request: &LlmRequest) -> Result<String, LlmError> {
+        // `raw` stops Ollama applying its own template on top of ours
+        let prompt = build_prompt(&self.template, request)?;
+        let mut body = json!({
+            "model": self.model,
+            "prompt": prompt,
+            "raw": true,
+            "stream": false,
+            "options": {
+                "stop": self.template.stop_sequences,
+                "num_predict": request.params.max_tokens,
+                "temperature": request.params.temperature,
+                "top_p": request.params.top_p,
+            },
+        });
+
+        // Ollama constrains decoding to a JSON schema given as `format`
+        if let Some(schema) = &request.output_schema {
+            body["format"] = schema.clone();
+        }
+
+        info!("Calling local model: {}", self.model);
+        let url = format!("{}/api/generate", self.base_url);
+        let res = http.send(http.post(&url).json(&body), Duration::from_secs(60)).await?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let error_text = res.text().await.unwrap_or_default();
+            error!("Local model error ({}): {}", status, error_text);
+            return Err(LlmError::UpstreamHttp { status: Some(status.as_u16()), message: error_text });
+        }
+
+        let json_resp: serde_json::Value = res.json().await?;
+        match json_resp["response"].as_str().map(str::trim) {
+            Some(text) if !text.is_empty() => Ok(text.to_string()),
+            _ => Err(LlmError::EmptyOutput),
+        }
+    }
+
+    fn passages_kept(&self, request: &LlmRequest) -> usize {
+        fit_request(&self.template, request).map_or(0, |fitted| fitted.passages_used)
+    }
+}
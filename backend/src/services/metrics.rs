@@ -0,0 +1,50 @@
+//! Process-wide counters, served as JSON from `GET /api/metrics`
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Default)]
+pub struct Metrics {
+    chat_requests: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    degraded_answers: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct MetricsSnapshot {
+    pub chat_requests: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Hits over lookups, 0 before the first lookup
+    pub cache_hit_rate: f64,
+    pub degraded_answers: u64,
+}
+
+impl Metrics {
+    pub fn chat_request(&self) {
+        self.chat_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_lookup(&self, hit: bool) {
+        let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn degraded_answer(&self) {
+        self.degraded_answers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let cache_hits = self.cache_hits.load(Ordering::Relaxed);
+        let cache_misses = self.cache_misses.load(Ordering::Relaxed);
+        let lookups = cache_hits + cache_misses;
+        MetricsSnapshot {
+            chat_requests: self.chat_requests.load(Ordering::Relaxed),
+            cache_hits,
+            cache_misses,
+            cache_hit_rate: if lookups == 0 { 0.0 } else { cache_hits as f64 / lookups as f64 },
+            degraded_answers: self.degraded_answers.load(Ordering::Relaxed),
+        }
+    }
+}
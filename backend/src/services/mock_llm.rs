@@ -0,0 +1,52 @@
+//! Deterministic model for tests and for running the backend without a Replicate token
+//! (`LLM_CHAIN=mock:echo`). Scripted responses are returned in order; once the script
+//! runs out, the answer is built from the top passage so it is stable and cites `[1]`.
+
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::error::LlmError;
+use super::http::HttpClient;
+use super::llm::{LlmProvider, LlmRequest};
+
+pub struct MockProvider {
+    label: String,
+    script: Mutex<VecDeque<Result<String, LlmError>>>,
+}
+
+impl MockProvider {
+    pub fn new(name: &str) -> Self {
+        Self::scripted(name, Vec::new())
+    }
+
+    pub fn scripted(name: &str, responses: Vec<Result<String, LlmError>>) -> Self {
+        Self {
+            label: format!("mock:{}", name),
+            script: Mutex::new(responses.into()),
+        }
+    }
+}
+
+/// "[1] Title: first sentence. ..." -> "first sentence"
+fn first_sentence(passage: &str) -> &str {
+    let body = passage.split_once(": ").map(|(_, b)| b).unwrap_or(passage);
+    body.split_terminator(". ").next().unwrap_or(body).trim_end_matches('.')
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &str {
+        &self.label
+    }
+
+    async fn generate(&self, _http: &HttpClient, request: &LlmRequest) -> Result<String, LlmError> {
+        if let Some(scripted) = self.script.lock().unwrap().pop_front() {
+            return scripted;
+        }
+        Ok(match request.passages.first() {
+            Some(passage) => format!("{}. [1]", first_sentence(passage)),
+            None => format!("Mock answer to: {}", request.query),
+        })
+    }
+}
@@ -1,2 +1,24 @@
+pub mod cassette;
+pub mod circuit_breaker;
+pub mod error;
+pub mod generation;
+pub mod http;
+pub mod image_intake;
+pub mod image_quality;
+pub mod ibm_cloud;
 pub mod ibm_granite;
+pub mod jobs;
+pub mod json_schema;
+pub mod llm;
+pub mod llm_chain;
+pub mod local_llm;
+pub mod metrics;
+pub mod mock_llm;
+pub mod pest_reports;
+pub mod photo_metadata;
+pub mod replicate_webhook;
+pub mod response_cache;
+pub mod sessions;
+pub mod speech;
+pub mod tools;
 pub mod translator;
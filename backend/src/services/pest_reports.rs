@@ -0,0 +1,224 @@
+//! Located pest and disease sightings.
+//! Every confident diagnosis from a photo that came with a shared location is kept here, so
+//! outbreaks can be followed by district and season. Coordinates are the rounded ones from
+//! `PhotoContext`. Reports are appended to a JSON-lines file and reloaded at startup; only
+//! per-district counts ever leave the server, never a farmer's position or photo date.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::rag::locality::Season;
+use crate::services::error::LlmError;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PestReport {
+    pub crop: String,
+    /// Finding name, e.g. "Early blight"
+    pub name: String,
+    pub kind: String,
+    pub confidence: f32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub district: Option<String>,
+    pub state: Option<String>,
+    pub season: Option<Season>,
+    /// Capture date from the photo, `YYYY-MM-DD`
+    pub observed_on: Option<String>,
+    pub reported_at: u64, // unix seconds
+}
+
+/// Sightings of one pest on one crop in a district and season
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PestSummary {
+    pub district: String,
+    pub state: Option<String>,
+    pub crop: String,
+    /// Finding name, e.g. "Early blight"
+    pub name: String,
+    pub kind: String,
+    pub season: Option<Season>,
+    pub reports: usize,
+    pub mean_confidence: f32,
+}
+
+/// District, crop and pest, lowercased, and season
+type GroupKey = (String, String, String, Option<Season>);
+
+pub struct PestReportStore {
+    reports: RwLock<VecDeque<PestReport>>,
+    capacity: usize,
+    /// Where reports are saved; `None` keeps them in memory only
+    file: Option<PathBuf>,
+}
+
+impl PestReportStore {
+    /// In memory only
+    pub fn new(capacity: usize) -> Self {
+        Self { reports: RwLock::new(VecDeque::new()), capacity, file: None }
+    }
+
+    /// Keeps the latest `PEST_REPORTS_MAX` (default 10000) in `PEST_REPORTS_FILE` (default
+    /// `data/pest-reports.jsonl`; empty keeps them in memory only)
+    pub fn from_env() -> Result<Self, LlmError> {
+        let capacity = env::var("PEST_REPORTS_MAX").ok().and_then(|v| v.parse().ok()).unwrap_or(10_000);
+        let path = env::var("PEST_REPORTS_FILE").unwrap_or_else(|_| "data/pest-reports.jsonl".to_string());
+        if path.is_empty() {
+            return Ok(Self::new(capacity));
+        }
+        Self::open(PathBuf::from(&path), capacity)
+            .map_err(|e| LlmError::Config(format!("could not open pest reports {}: {}", path, e)))
+    }
+
+    /// The reports saved in `path`, which new ones are appended to. Lines that can't be read
+    /// are skipped, and the file is rewritten when it holds more than `capacity`.
+    pub fn open(path: PathBuf, capacity: usize) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let saved = match fs::read_to_string(&path) {
+            Ok(saved) => saved,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut reports = VecDeque::new();
+        let mut lines = 0;
+        for line in saved.lines().filter(|l| !l.trim().is_empty()) {
+            lines += 1;
+            match serde_json::from_str(line) {
+                Ok(report) => reports.push_back(report),
+                Err(e) => warn!("Skipping unreadable pest report in {}: {}", path.display(), e),
+            }
+        }
+        while reports.len() > capacity {
+            reports.pop_front();
+        }
+        if reports.len() < lines {
+            fs::write(&path, reports.iter().map(to_line).collect::<String>())?;
+        }
+        info!("Loaded {} pest reports from {}", reports.len(), path.display());
+        Ok(Self { reports: RwLock::new(reports), capacity, file: Some(path) })
+    }
+
+    pub fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+
+    pub async fn record(&self, report: PestReport) {
+        let mut reports = self.reports.write().await;
+        // Appended under the lock, so the file keeps the order of the queue
+        if let Some(path) = &self.file {
+            if let Err(e) = append(path, &report).await {
+                warn!("Could not save pest report to {}: {}", path.display(), e);
+            }
+        }
+        reports.push_back(report);
+        while reports.len() > self.capacity {
+            reports.pop_front();
+        }
+    }
+
+    /// Reports counted by district, crop, pest and season, most reported first; optionally
+    /// only one district (case-insensitive). Reports without a district are left out.
+    pub async fn summary(&self, district: Option<&str>) -> Vec<PestSummary> {
+        let reports = self.reports.read().await;
+        let mut groups: HashMap<GroupKey, (PestSummary, f32)> = HashMap::new();
+        for report in reports.iter() {
+            let Some(report_district) = &report.district else { continue };
+            if district.is_some_and(|d| !d.eq_ignore_ascii_case(report_district)) {
+                continue;
+            }
+            let key = (report_district.to_lowercase(), report.crop.to_lowercase(), report.name.to_lowercase(), report.season);
+            let (summary, total) = groups.entry(key).or_insert_with(|| {
+                let summary = PestSummary {
+                    district: report_district.clone(),
+                    state: report.state.clone(),
+                    crop: report.crop.clone(),
+                    name: report.name.clone(),
+                    kind: report.kind.clone(),
+                    season: report.season,
+                    reports: 0,
+                    mean_confidence: 0.0,
+                };
+                (summary, 0.0)
+            });
+            summary.reports += 1;
+            *total += report.confidence;
+        }
+        let mut summaries: Vec<PestSummary> = groups
+            .into_values()
+            .map(|(summary, total)| PestSummary { mean_confidence: total / summary.reports as f32, ..summary })
+            .collect();
+        summaries.sort_by(|a, b| b.reports.cmp(&a.reports).then_with(|| (&a.district, &a.name).cmp(&(&b.district, &b.name))));
+        summaries
+    }
+}
+
+fn to_line(report: &PestReport) -> String {
+    format!("{}\n", serde_json::to_string(report).unwrap_or_default())
+}
+
+async fn append(path: &Path, report: &PestReport) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(to_line(report).as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(name: &str, district: &str) -> PestReport {
+        PestReport {
+            crop: "tomato".to_string(),
+            name: name.to_string(),
+            kind: "disease".to_string(),
+            confidence: 0.8,
+            latitude: 19.99,
+            longitude: 73.79,
+            district: Some(district.to_string()),
+            state: Some("Maharashtra".to_string()),
+            season: Some(Season::Kharif),
+            observed_on: None,
+            reported_at: PestReportStore::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_counted_by_district_and_capped() {
+        let store = PestReportStore::new(3);
+        store.record(report("Early blight", "Nashik")).await;
+        store.record(report("Aphid", "Pune")).await;
+        store.record(report("Fruit fly", "Nashik")).await;
+        store.record(PestReport { confidence: 0.6, ..report("Fruit fly", "nashik") }).await;
+
+        let all: Vec<(String, usize)> = store.summary(None).await.into_iter().map(|s| (s.name, s.reports)).collect();
+        assert_eq!(all, vec![("Fruit fly".to_string(), 2), ("Aphid".to_string(), 1)]);
+        let nashik = store.summary(Some("NASHIK")).await;
+        assert_eq!(nashik.len(), 1);
+        assert!((nashik[0].mean_confidence - 0.7).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_reports_survive_a_restart() {
+        let path = env::temp_dir().join(format!("kisan-pest-reports-{}", uuid::Uuid::new_v4())).join("reports.jsonl");
+        let store = PestReportStore::open(path.clone(), 2).unwrap();
+        store.record(report("Early blight", "Nashik")).await;
+        store.record(report("Aphid", "Pune")).await;
+        store.record(report("Fruit fly", "Nashik")).await;
+        drop(store);
+
+        let reopened = PestReportStore::open(path.clone(), 2).unwrap();
+        let names: Vec<String> = reopened.summary(None).await.into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["Fruit fly", "Aphid"]);
+        // Trimmed to the newest two on reopening
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -0,0 +1,149 @@
+//! GPS position and capture time from photo EXIF.
+//! Read only when the farmer has agreed to share their location; the upstream copy of every
+//! photo is re-encoded without EXIF either way (see `image_intake`).
+
+use exif::{In, Reader, Tag, Value};
+use std::io::Cursor;
+use tracing::debug;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PhotoMetadata {
+    pub location: Option<GeoPoint>,
+    /// Camera-local capture date, `YYYY-MM-DD`
+    pub taken_on: Option<String>,
+    /// 1-12, from the capture date
+    pub month: Option<u32>,
+}
+
+impl PhotoMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.location.is_none() && self.taken_on.is_none()
+    }
+}
+
+/// Whatever GPS and date EXIF the photo carries; nothing when it has none or it is unreadable
+pub fn read(bytes: &[u8]) -> PhotoMetadata {
+    let exif = match Reader::new().read_from_container(&mut Cursor::new(bytes)) {
+        Ok(exif) => exif,
+        Err(e) => {
+            debug!("No EXIF read from photo: {}", e);
+            return PhotoMetadata::default();
+        }
+    };
+    let field = |tag: Tag| exif.get_field(tag, In::PRIMARY).map(|f| &f.value);
+
+    let coordinate = |value: Tag, reference: Tag, negative: u8| -> Option<f64> {
+        let Some(Value::Rational(parts)) = field(value) else { return None };
+        if parts.len() < 3 || parts.iter().any(|p| p.denom == 0) {
+            return None;
+        }
+        let degrees = parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0;
+        let sign = match field(reference) {
+            Some(Value::Ascii(refs)) if refs.first().and_then(|r| r.first()) == Some(&negative) => -1.0,
+            _ => 1.0,
+        };
+        Some(sign * degrees)
+    };
+    let location = match (
+        coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S'),
+        coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W'),
+    ) {
+        // Phones write 0,0 when they had no fix
+        (Some(latitude), Some(longitude))
+            if latitude.abs() <= 90.0 && longitude.abs() <= 180.0 && (latitude, longitude) != (0.0, 0.0) =>
+        {
+            Some(GeoPoint { latitude, longitude })
+        }
+        _ => None,
+    };
+
+    // "YYYY:MM:DD HH:MM:SS"
+    let taken = [Tag::DateTimeOriginal, Tag::DateTime].into_iter().find_map(|tag| match field(tag) {
+        Some(Value::Ascii(values)) => values.first().and_then(|v| std::str::from_utf8(v).ok()).map(str::to_string),
+        _ => None,
+    });
+    let (taken_on, month) = match taken.as_deref().and_then(parse_date) {
+        Some((year, month, day)) => (Some(format!("{:04}-{:02}-{:02}", year, month, day)), Some(month)),
+        None => (None, None),
+    };
+
+    PhotoMetadata { location, taken_on, month }
+}
+
+fn parse_date(text: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = text.get(..10)?.split(':');
+    let year: u32 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    ((1..=12).contains(&month) && (1..=31).contains(&day) && year > 1990).then_some((year, month, day))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use exif::{Field, Rational};
+
+    fn dms(value: f64) -> Value {
+        let degrees = value.trunc();
+        let minutes = ((value - degrees) * 60.0).trunc();
+        let seconds = ((value - degrees) * 60.0 - minutes) * 60.0;
+        Value::Rational(vec![
+            Rational { num: degrees as u32, denom: 1 },
+            Rational { num: minutes as u32, denom: 1 },
+            Rational { num: (seconds * 100.0).round() as u32, denom: 100 },
+        ])
+    }
+
+    /// `jpeg` with an EXIF APP1 segment holding a GPS fix (southern/western when negative) and a capture time
+    pub fn with_gps(jpeg: &[u8], latitude: f64, longitude: f64, taken: &str) -> Vec<u8> {
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let fields = [
+            Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: ascii(if latitude < 0.0 { "S" } else { "N" }) },
+            Field { tag: Tag::GPSLatitude, ifd_num: In::PRIMARY, value: dms(latitude.abs()) },
+            Field { tag: Tag::GPSLongitudeRef, ifd_num: In::PRIMARY, value: ascii(if longitude < 0.0 { "W" } else { "E" }) },
+            Field { tag: Tag::GPSLongitude, ifd_num: In::PRIMARY, value: dms(longitude.abs()) },
+            Field { tag: Tag::DateTimeOriginal, ifd_num: In::PRIMARY, value: ascii(taken) },
+        ];
+        let mut writer = exif::experimental::Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend(tiff.into_inner());
+        let mut out = jpeg[..2].to_vec();
+        out.extend([0xFF, 0xE1]);
+        out.extend(((app1.len() + 2) as u16).to_be_bytes());
+        out.extend(app1);
+        out.extend(&jpeg[2..]);
+        out
+    }
+
+    fn jpeg() -> Vec<u8> {
+        let mut out = Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(16, 16).write_to(&mut out, image::ImageFormat::Jpeg).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn test_gps_and_date_are_read() {
+        let metadata = read(&with_gps(&jpeg(), 19.9975, 73.7898, "2024:07:14 09:30:00"));
+        let location = metadata.location.unwrap();
+        assert!((location.latitude - 19.9975).abs() < 1e-4);
+        assert!((location.longitude - 73.7898).abs() < 1e-4);
+        assert_eq!(metadata.taken_on.as_deref(), Some("2024-07-14"));
+        assert_eq!(metadata.month, Some(7));
+
+        let south_west = read(&with_gps(&jpeg(), -12.5, -45.25, "2024:01:02 10:00:00")).location.unwrap();
+        assert_eq!((south_west.latitude, south_west.longitude), (-12.5, -45.25));
+        assert!(read(&jpeg()).is_empty());
+    }
+}
@@ -0,0 +1,226 @@
+//! Replicate webhook support.
+//! When configured, predictions are created with a `webhook` URL and completion is
+//! pushed to `/api/webhooks/replicate` instead of polling `urls.get` every second. A
+//! delivery that hasn't come after `poll_after` is assumed lost and polling starts too.
+//! Signatures follow Replicate's scheme: HMAC-SHA256 over `{id}.{timestamp}.{body}`
+//! with the base64 key from the `whsec_...` secret.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+/// Reject deliveries whose timestamp is further than this from our clock
+const TIMESTAMP_TOLERANCE_SECS: i64 = 5 * 60;
+/// How long a completion that arrived before anyone waited for it is kept
+const EARLY_COMPLETION_TTL: Duration = Duration::from_secs(5 * 60);
+/// Default wait for a delivery before polling the prediction as well
+const DEFAULT_POLL_AFTER: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum WebhookError {
+    #[error("missing header {0}")]
+    MissingHeader(&'static str),
+    #[error("timestamp outside tolerance")]
+    StaleTimestamp,
+    #[error("signature mismatch")]
+    BadSignature,
+}
+
+/// Where a prediction's completion stands, keyed by prediction ID
+enum Delivery {
+    /// A request is waiting for the webhook
+    Waiting(oneshot::Sender<serde_json::Value>),
+    /// The webhook beat the creating request back
+    Early(Instant, serde_json::Value),
+}
+
+pub struct ReplicateWebhooks {
+    /// Public URL Replicate should call, e.g. `https://example.org/api/webhooks/replicate`
+    pub callback_url: String,
+    /// How long to wait for a delivery before also polling the prediction
+    pub poll_after: Duration,
+    key: Vec<u8>,
+    /// One map, so a delivery can't slip in between checking for it and starting to wait
+    deliveries: Mutex<HashMap<String, Delivery>>,
+}
+
+/// Removes a waiter whose `wait_for` ended or was dropped without a delivery
+struct WaitGuard<'a> {
+    deliveries: &'a Mutex<HashMap<String, Delivery>>,
+    prediction_id: &'a str,
+}
+
+impl Drop for WaitGuard<'_> {
+    fn drop(&mut self) {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        if let Some(Delivery::Waiting(_)) = deliveries.get(self.prediction_id) {
+            deliveries.remove(self.prediction_id);
+        }
+    }
+}
+
+impl ReplicateWebhooks {
+    pub fn new(callback_url: &str, secret: &str) -> Option<Self> {
+        let encoded = secret.trim().strip_prefix("whsec_").unwrap_or(secret.trim());
+        let key = BASE64.decode(encoded).ok()?;
+        Some(Self {
+            callback_url: callback_url.to_string(),
+            poll_after: DEFAULT_POLL_AFTER,
+            key,
+            deliveries: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Enabled when both `REPLICATE_WEBHOOK_URL` and `REPLICATE_WEBHOOK_SECRET` are set;
+    /// `REPLICATE_WEBHOOK_POLL_AFTER_SECS` (default 15) sets `poll_after`
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("REPLICATE_WEBHOOK_URL").ok().filter(|v| !v.is_empty())?;
+        let secret = env::var("REPLICATE_WEBHOOK_SECRET").ok().filter(|v| !v.is_empty())?;
+        let Some(webhooks) = Self::new(&url, &secret) else {
+            warn!("REPLICATE_WEBHOOK_SECRET is not valid base64; falling back to polling");
+            return None;
+        };
+        let poll_after = env::var("REPLICATE_WEBHOOK_POLL_AFTER_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map_or(DEFAULT_POLL_AFTER, Duration::from_secs);
+        Some(Self { poll_after, ..webhooks })
+    }
+
+    /// Verify the `webhook-*` headers against the raw request body
+    pub fn verify(&self, id: Option<&str>, timestamp: Option<&str>, signatures: Option<&str>, body: &[u8]) -> Result<(), WebhookError> {
+        let id = id.ok_or(WebhookError::MissingHeader("webhook-id"))?;
+        let timestamp = timestamp.ok_or(WebhookError::MissingHeader("webhook-timestamp"))?;
+        let signatures = signatures.ok_or(WebhookError::MissingHeader("webhook-signature"))?;
+
+        let sent_at: i64 = timestamp.parse().map_err(|_| WebhookError::StaleTimestamp)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        if (now - sent_at).abs() > TIMESTAMP_TOLERANCE_SECS {
+            return Err(WebhookError::StaleTimestamp);
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(id.as_bytes());
+        mac.update(b".");
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(body);
+
+        // Header holds space-separated `v1,<base64>` entries, any of which may match
+        let matched = signatures
+            .split_whitespace()
+            .filter_map(|entry| entry.strip_prefix("v1,"))
+            .filter_map(|sig| BASE64.decode(sig).ok())
+            .any(|sig| mac.clone().verify_slice(&sig).is_ok());
+
+        if matched { Ok(()) } else { Err(WebhookError::BadSignature) }
+    }
+
+    /// Wait for the webhook delivering this prediction's final state
+    pub async fn wait_for(&self, prediction_id: &str, timeout: Duration) -> Option<serde_json::Value> {
+        let rx = {
+            let mut deliveries = self.deliveries.lock().unwrap();
+            if let Some(Delivery::Early(_, prediction)) = deliveries.remove(prediction_id) {
+                return Some(prediction);
+            }
+            let (tx, rx) = oneshot::channel();
+            deliveries.insert(prediction_id.to_string(), Delivery::Waiting(tx));
+            rx
+        };
+        let _guard = WaitGuard { deliveries: &self.deliveries, prediction_id };
+        tokio::time::timeout(timeout, rx).await.ok().and_then(Result::ok)
+    }
+
+    /// Hand a finished prediction to whoever is waiting for it
+    pub fn complete(&self, prediction_id: &str, prediction: serde_json::Value) {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        match deliveries.remove(prediction_id) {
+            Some(Delivery::Waiting(tx)) => {
+                let _ = tx.send(prediction);
+            }
+            _ => {
+                // Nobody is waiting yet; keep it briefly
+                deliveries.retain(|_, d| !matches!(d, Delivery::Early(at, _) if at.elapsed() >= EARLY_COMPLETION_TTL));
+                deliveries.insert(prediction_id.to_string(), Delivery::Early(Instant::now(), prediction));
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn pending(&self) -> usize {
+        self.deliveries.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+
+    fn sign(id: &str, timestamp: &str, body: &[u8]) -> String {
+        let key = BASE64.decode(SECRET.strip_prefix("whsec_").unwrap()).unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).unwrap();
+        mac.update(format!("{}.{}.", id, timestamp).as_bytes());
+        mac.update(body);
+        format!("v1,{}", BASE64.encode(mac.finalize().into_bytes()))
+    }
+
+    fn now() -> String {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string()
+    }
+
+    #[test]
+    fn test_accepts_valid_signature() {
+        let hooks = ReplicateWebhooks::new("http://localhost/hook", SECRET).unwrap();
+        let body = br#"{"id":"abc","status":"succeeded"}"#;
+        let ts = now();
+        let sig = format!("v1,bm9wZQ== {}", sign("msg_1", &ts, body));
+        assert_eq!(hooks.verify(Some("msg_1"), Some(&ts), Some(&sig), body), Ok(()));
+    }
+
+    #[test]
+    fn test_rejects_tampered_body_and_stale_timestamp() {
+        let hooks = ReplicateWebhooks::new("http://localhost/hook", SECRET).unwrap();
+        let ts = now();
+        let sig = sign("msg_1", &ts, b"original");
+        assert_eq!(hooks.verify(Some("msg_1"), Some(&ts), Some(&sig), b"tampered"), Err(WebhookError::BadSignature));
+
+        let old = "1000000000";
+        let sig = sign("msg_1", old, b"original");
+        assert_eq!(hooks.verify(Some("msg_1"), Some(old), Some(&sig), b"original"), Err(WebhookError::StaleTimestamp));
+    }
+
+    #[tokio::test]
+    async fn test_completion_before_wait_is_kept() {
+        let hooks = ReplicateWebhooks::new("http://localhost/hook", SECRET).unwrap();
+        hooks.complete("p1", serde_json::json!({"status": "succeeded"}));
+        let prediction = hooks.wait_for("p1", Duration::from_millis(10)).await.unwrap();
+        assert_eq!(prediction["status"], "succeeded");
+    }
+
+    #[tokio::test]
+    async fn test_waiter_is_removed_when_dropped_or_timed_out() {
+        let hooks = ReplicateWebhooks::new("http://localhost/hook", SECRET).unwrap();
+        assert!(hooks.wait_for("p1", Duration::from_millis(5)).await.is_none());
+        assert_eq!(hooks.pending(), 0);
+
+        let abandoned = tokio::time::timeout(Duration::from_millis(5), hooks.wait_for("p2", Duration::from_secs(60))).await;
+        assert!(abandoned.is_err());
+        assert_eq!(hooks.pending(), 0);
+
+        let (waited, ()) = tokio::join!(hooks.wait_for("p3", Duration::from_secs(5)), async {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            hooks.complete("p3", serde_json::json!({"status": "succeeded"}));
+        });
+        assert_eq!(waited.unwrap()["status"], "succeeded");
+        assert_eq!(hooks.pending(), 0);
+    }
+}
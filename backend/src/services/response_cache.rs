@@ -0,0 +1,216 @@
+//! Semantic response cache.
+//! Many farmers ask the same question in slightly different words ("wheat sowing time?",
+//! "When to sow wheat"), so answers are cached by the normalized standalone query, scoped
+//! to language and response mode. A near-duplicate with enough term overlap
+//! (`CACHE_SIMILARITY`) also hits, but only when it names the same crops, places and
+//! numbers, so a rice question is never served the wheat answer. Price answers expire quickly.
+//! Similarity is lexical only (shared words, not meaning): "sowing time for wheat" and
+//! "when to plant wheat" do not match, and synonyms or another script each get their own entry.
+//! Callers put the knowledge base revision in the scope so an edited corpus never serves
+//! answers drawn from the old one.
+
+use lazy_static::lazy_static;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::rag::locality::place_names;
+use crate::rag::retriever::STOPWORDS;
+use crate::rag::rewriter::crop_names;
+
+lazy_static! {
+    /// Terms that change what a question is about, lowercased word by word
+    static ref ENTITY_WORDS: HashSet<String> = crop_names()
+        .chain(place_names())
+        .flat_map(str::split_whitespace)
+        .map(str::to_lowercase)
+        .collect();
+}
+
+struct Entry<V> {
+    terms: BTreeSet<String>,
+    value: V,
+    stored: Instant,
+    ttl: Duration,
+}
+
+impl<V> Entry<V> {
+    fn is_live(&self) -> bool {
+        self.stored.elapsed() < self.ttl
+    }
+}
+
+pub struct ResponseCache<V> {
+    /// Keyed by scope, then by normalized query
+    entries: RwLock<HashMap<String, HashMap<String, Entry<V>>>>,
+    ttl: Duration,
+    price_ttl: Duration,
+    weather_ttl: Duration,
+    /// Minimum term overlap (Jaccard) for a near-duplicate hit; 1.0 means exact only
+    similarity: f32,
+    max_entries: usize,
+}
+
+/// Query terms that matter for matching: lowercased, no punctuation or stopwords
+fn terms(query: &str) -> BTreeSet<String> {
+    query
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty() && !STOPWORDS.contains(t))
+        .map(|t| t.strip_suffix('s').filter(|s| s.len() > 2).unwrap_or(t).to_string())
+        .collect()
+}
+
+/// Crops, places and numbers among `terms`
+fn entities(terms: &BTreeSet<String>) -> BTreeSet<&str> {
+    terms
+        .iter()
+        .filter(|t| ENTITY_WORDS.contains(*t) || t.chars().any(|c| c.is_ascii_digit()))
+        .map(String::as_str)
+        .collect()
+}
+
+fn similarity(a: &BTreeSet<String>, b: &BTreeSet<String>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+impl<V: Clone> ResponseCache<V> {
+    pub fn new(ttl: Duration, price_ttl: Duration, weather_ttl: Duration, similarity: f32, max_entries: usize) -> Self {
+        Self { entries: RwLock::new(HashMap::new()), ttl, price_ttl, weather_ttl, similarity, max_entries }
+    }
+
+    /// `CACHE_TTL_SECS` (default one day, 0 disables the cache), `CACHE_PRICE_TTL_SECS` (15 minutes),
+    /// `CACHE_WEATHER_TTL_SECS` (3 hours), `CACHE_SIMILARITY` (0.8) and `CACHE_MAX_ENTRIES` (1000)
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            Duration::from_secs(env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default))
+        };
+        Self::new(
+            secs("CACHE_TTL_SECS", 86_400),
+            secs("CACHE_PRICE_TTL_SECS", 900),
+            secs("CACHE_WEATHER_TTL_SECS", 10_800),
+            env::var("CACHE_SIMILARITY").ok().and_then(|v| v.parse().ok()).unwrap_or(0.8),
+            env::var("CACHE_MAX_ENTRIES").ok().and_then(|v| v.parse().ok()).unwrap_or(1000),
+        )
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    /// How long an answer drawn from a `category` document stays fresh
+    fn ttl_for(&self, category: Option<&str>) -> Duration {
+        match category {
+            Some("market_prices") => self.price_ttl.min(self.ttl),
+            Some("weather") => self.weather_ttl.min(self.ttl),
+            _ => self.ttl,
+        }
+    }
+
+    /// Cached answer for `query` in `scope`, exact or near-duplicate
+    pub async fn get(&self, scope: &str, query: &str) -> Option<V> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let wanted = terms(query);
+        if wanted.is_empty() {
+            return None;
+        }
+        let entries = self.entries.read().await;
+        let scoped = entries.get(scope)?;
+        if let Some(entry) = scoped.get(&key(&wanted)).filter(|e| e.is_live()) {
+            return Some(entry.value.clone());
+        }
+        let wanted_entities = entities(&wanted);
+        scoped
+            .values()
+            .filter(|e| e.is_live() && entities(&e.terms) == wanted_entities)
+            .map(|e| (similarity(&wanted, &e.terms), e))
+            .filter(|(score, _)| *score >= self.similarity)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, e)| e.value.clone())
+    }
+
+    /// Store an answer; `category` is that of the top retrieved document
+    pub async fn put(&self, scope: &str, query: &str, category: Option<&str>, value: V) {
+        let terms = terms(query);
+        if !self.is_enabled() || terms.is_empty() {
+            return;
+        }
+        let mut entries = self.entries.write().await;
+        for scoped in entries.values_mut() {
+            scoped.retain(|_, e| e.is_live());
+        }
+        entries.retain(|_, scoped| !scoped.is_empty());
+
+        // Still full after dropping expired answers: evict the oldest
+        let total: usize = entries.values().map(HashMap::len).sum();
+        if total >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .flat_map(|(scope, scoped)| scoped.iter().map(move |(k, e)| (e.stored, scope.clone(), k.clone())))
+                .min_by_key(|(stored, _, _)| *stored);
+            if let Some((_, scope, k)) = oldest {
+                entries.get_mut(&scope).map(|scoped| scoped.remove(&k));
+            }
+        }
+
+        entries.entry(scope.to_string()).or_default().insert(key(&terms), Entry {
+            terms,
+            value,
+            stored: Instant::now(),
+            ttl: self.ttl_for(category),
+        });
+    }
+}
+
+fn key(terms: &BTreeSet<String>) -> String {
+    terms.iter().cloned().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> ResponseCache<String> {
+        ResponseCache::new(Duration::from_secs(60), Duration::from_millis(0), Duration::from_secs(60), 0.6, 10)
+    }
+
+    #[tokio::test]
+    async fn test_rephrased_query_hits() {
+        let cache = cache();
+        cache.put("en|normal", "When should I sow wheat?", Some("crops"), "Oct-Nov".to_string()).await;
+
+        assert_eq!(cache.get("en|normal", "wheat: when to sow").await.as_deref(), Some("Oct-Nov"));
+        assert_eq!(cache.get("en|normal", "how to store onions").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_near_duplicate_needs_the_same_crop_and_place() {
+        let cache = cache();
+        cache.put("en|normal", "best time to sow wheat seed in rabi", Some("crops"), "Oct-Nov".to_string()).await;
+        assert_eq!(cache.get("en|normal", "best time to sow rice seed in rabi").await, None);
+        assert_eq!(cache.get("en|normal", "best time to sow wheat seed in rabi in Punjab").await, None);
+        assert_eq!(cache.get("en|normal", "best time sow wheat seeds rabi").await.as_deref(), Some("Oct-Nov"));
+    }
+
+    #[tokio::test]
+    async fn test_scope_separates_language_and_mode() {
+        let cache = cache();
+        cache.put("en|normal", "wheat sowing time", Some("crops"), "Oct-Nov".to_string()).await;
+        assert_eq!(cache.get("hi|normal", "wheat sowing time").await, None);
+        assert_eq!(cache.get("en|brief", "wheat sowing time").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_price_answers_expire_sooner() {
+        let cache = cache();
+        cache.put("en|normal", "onion price today", Some("market_prices"), "₹1,800".to_string()).await;
+        assert_eq!(cache.get("en|normal", "onion price today").await, None);
+    }
+}
@@ -0,0 +1,162 @@
+//! Server-side conversation history with a TTL.
+//! Lets follow-ups like "what about for onion?" see the earlier turns.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+use crate::rag::tokens::estimate_tokens;
+
+/// Oldest turns are dropped beyond this many per session
+const MAX_TURNS: usize = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Turn {
+    pub role: Role,
+    pub content: String,
+    pub at: u64, // unix seconds
+}
+
+impl Turn {
+    pub fn new(role: Role, content: &str) -> Self {
+        let at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Self { role, content: content.to_string(), at }
+    }
+}
+
+struct Session {
+    turns: Vec<Turn>,
+    last_active: Instant,
+}
+
+pub struct SessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+    ttl: Duration,
+    /// Token budget for prior turns included in a prompt
+    pub history_tokens: usize,
+}
+
+impl SessionStore {
+    pub fn new(ttl: Duration, history_tokens: usize) -> Self {
+        Self { sessions: RwLock::new(HashMap::new()), ttl, history_tokens }
+    }
+
+    /// `SESSION_TTL_SECS` (default 30 minutes) and `SESSION_HISTORY_TOKENS` (default 600)
+    pub fn from_env() -> Self {
+        let ttl = env::var("SESSION_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(1800);
+        let tokens = env::var("SESSION_HISTORY_TOKENS").ok().and_then(|v| v.parse().ok()).unwrap_or(600);
+        Self::new(Duration::from_secs(ttl), tokens)
+    }
+
+    pub fn new_id() -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+
+    /// The client's session ID if that session is live, otherwise a fresh one, so IDs
+    /// are always minted here rather than chosen by the client
+    pub async fn resume(&self, requested: Option<&str>) -> String {
+        match requested.map(str::trim).filter(|id| !id.is_empty()) {
+            Some(id) if self.history(id).await.is_some() => id.to_string(),
+            _ => Self::new_id(),
+        }
+    }
+
+    /// All live turns of a session, oldest first
+    pub async fn history(&self, id: &str) -> Option<Vec<Turn>> {
+        let sessions = self.sessions.read().await;
+        sessions.get(id)
+            .filter(|s| s.last_active.elapsed() < self.ttl)
+            .map(|s| s.turns.clone())
+    }
+
+    /// Most recent turns that fit in `budget` tokens, oldest first and starting on a user turn
+    pub async fn window(&self, id: &str, budget: usize) -> Vec<Turn> {
+        let turns = self.history(id).await.unwrap_or_default();
+        let mut used = 0;
+        let mut window: Vec<Turn> = turns
+            .into_iter()
+            .rev()
+            .take_while(|turn| {
+                used += estimate_tokens(&turn.content);
+                used <= budget
+            })
+            .collect();
+        window.reverse();
+        // An answer without the question it answers only confuses the model
+        let first_user = window.iter().position(|turn| turn.role == Role::User).unwrap_or(window.len());
+        window.drain(..first_user);
+        window
+    }
+
+    pub async fn append(&self, id: &str, turns: Vec<Turn>) {
+        let mut sessions = self.sessions.write().await;
+        let ttl = self.ttl;
+        sessions.retain(|_, s| s.last_active.elapsed() < ttl);
+
+        let session = sessions.entry(id.to_string()).or_insert_with(|| Session {
+            turns: Vec::new(),
+            last_active: Instant::now(),
+        });
+        session.turns.extend(turns);
+        if session.turns.len() > MAX_TURNS {
+            let excess = session.turns.len() - MAX_TURNS;
+            session.turns.drain(..excess);
+        }
+        session.last_active = Instant::now();
+    }
+
+    pub async fn delete(&self, id: &str) -> bool {
+        self.sessions.write().await.remove(id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_window_keeps_latest_turns_within_budget() {
+        let store = SessionStore::new(Duration::from_secs(60), 100);
+        store.append("s1", vec![
+            Turn::new(Role::User, &"old question ".repeat(20)),
+            Turn::new(Role::Assistant, "wheat needs 4-5 irrigations"),
+            Turn::new(Role::User, "what about onion?"),
+        ]).await;
+
+        let window = store.window("s1", 20).await;
+        assert_eq!(window.len(), 1);
+        assert_eq!(window[0].role, Role::User);
+        assert_eq!(window[0].content, "what about onion?");
+
+        let window = store.window("s1", 200).await;
+        assert_eq!(window.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_session_ids_are_replaced() {
+        let store = SessionStore::new(Duration::from_secs(60), 100);
+        store.append("live", vec![Turn::new(Role::User, "hello")]).await;
+        assert_eq!(store.resume(Some("live")).await, "live");
+
+        let minted = store.resume(Some("chosen-by-client")).await;
+        assert_ne!(minted, "chosen-by-client");
+        assert!(uuid::Uuid::parse_str(&minted).is_ok());
+        assert!(uuid::Uuid::parse_str(&store.resume(None).await).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_expired_session_has_no_history() {
+        let store = SessionStore::new(Duration::from_millis(0), 100);
+        store.append("s1", vec![Turn::new(Role::User, "hello")]).await;
+        assert!(store.history("s1").await.is_none());
+    }
+}
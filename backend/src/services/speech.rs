@@ -0,0 +1,236 @@
+//! Speech-to-text for voice queries.
+//! Many farmers would rather speak than type in Devanagari. Voice notes (Ogg/Opus or WAV) are
+//! transcribed by a `SpeechToText` backend chosen with `STT_BACKEND`: `whisper` for any server
+//! with the OpenAI-style `/audio/transcriptions` endpoint (whisper.cpp, faster-whisper, ...),
+//! or `mock` for a fixed transcript.
+
+use async_trait::async_trait;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde::Deserialize;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+use super::error::LlmError;
+use super::http::HttpClient;
+
+/// A recorded clip, as uploaded
+pub struct Audio<'a> {
+    pub bytes: &'a [u8],
+    /// "audio/ogg" or "audio/wav"
+    pub content_type: &'a str,
+}
+
+#[derive(Clone, Debug)]
+pub struct Transcript {
+    pub text: String,
+    /// Language the backend heard as "en", "hi" or "mr"; `None` when it doesn't say or heard
+    /// another one
+    pub language: Option<String>,
+}
+
+/// "hi" for Whisper's "hindi" or "hi", and so on for the languages the app answers in
+fn language_code(reported: &str) -> Option<&'static str> {
+    match reported.trim().to_lowercase().as_str() {
+        "en" | "english" => Some("en"),
+        "hi" | "hindi" => Some("hi"),
+        "mr" | "marathi" => Some("mr"),
+        _ => None,
+    }
+}
+
+#[async_trait]
+pub trait SpeechToText: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// `language` is a hint ("hi", "mr", "en"); backends may detect it themselves without one
+    async fn transcribe(&self, http: &HttpClient, audio: Audio<'_>, language: Option<&str>) -> Result<Transcript, LlmError>;
+}
+
+/// The backend named by `STT_BACKEND` (`whisper` or `mock`); `None` when voice is switched off
+pub fn from_env() -> Option<Arc<dyn SpeechToText>> {
+    match env::var("STT_BACKEND").unwrap_or_default().trim() {
+        "whisper" => Some(Arc::new(WhisperHttp::from_env())),
+        "mock" => {
+            let mock = MockSpeechToText::new(&env::var("STT_MOCK_TRANSCRIPT").unwrap_or_else(|_| "When should I sow wheat?".to_string()));
+            Some(Arc::new(match env::var("STT_MOCK_LANGUAGE") {
+                Ok(language) => mock.heard_in(&language),
+                Err(_) => mock,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// An OpenAI-compatible transcription server
+pub struct WhisperHttp {
+    /// Up to and including the API version, e.g. `http://localhost:9000/v1`
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+    pub deadline: Duration,
+}
+
+impl WhisperHttp {
+    /// `WHISPER_API_URL` (default `http://localhost:9000/v1`), `WHISPER_API_KEY`,
+    /// `WHISPER_MODEL` (default `whisper-1`) and `STT_TIMEOUT_SECS` (default 60)
+    pub fn from_env() -> Self {
+        Self {
+            base_url: env::var("WHISPER_API_URL").unwrap_or_else(|_| "http://localhost:9000/v1".to_string()),
+            api_key: env::var("WHISPER_API_KEY").ok().filter(|k| !k.is_empty()),
+            model: env::var("WHISPER_MODEL").unwrap_or_else(|_| "whisper-1".to_string()),
+            deadline: Duration::from_secs(env::var("STT_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct WhisperResponse {
+    text: String,
+    #[serde(default)]
+    language: Option<String>,
+}
+
+/// A `multipart/form-data` body built in memory, so the request can be retried
+fn form(boundary: &str, fields: &[(&str, &str)], audio: &Audio<'_>) -> Vec<u8> {
+    let mut body = Vec::with_capacity(audio.bytes.len() + 512);
+    for (name, value) in fields {
+        body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value).bytes());
+    }
+    let extension = if audio.content_type == "audio/wav" { "wav" } else { "ogg" };
+    body.extend(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"voice.{}\"\r\nContent-Type: {}\r\n\r\n",
+            boundary, extension, audio.content_type
+        )
+        .bytes(),
+    );
+    body.extend_from_slice(audio.bytes);
+    body.extend(format!("\r\n--{}--\r\n", boundary).bytes());
+    body
+}
+
+#[async_trait]
+impl SpeechToText for WhisperHttp {
+    fn name(&self) -> &str {
+        "whisper"
+    }
+
+    async fn transcribe(&self, http: &HttpClient, audio: Audio<'_>, language: Option<&str>) -> Result<Transcript, LlmError> {
+        let boundary = format!("kisan-{}", uuid::Uuid::new_v4().simple());
+        // Only the verbose format says which language was heard
+        let mut fields = vec![("model", self.model.as_str()), ("response_format", "verbose_json")];
+        if let Some(language) = language {
+            fields.push(("language", language));
+        }
+        let mut request = http
+            .post(&format!("{}/audio/transcriptions", self.base_url.trim_end_matches('/')))
+            .header(CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary))
+            .body(form(&boundary, &fields, &audio));
+        if let Some(key) = &self.api_key {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", key));
+        }
+
+        let res = http.send(request, self.deadline).await?;
+        let status = res.status();
+        if status.as_u16() == 401 || status.as_u16() == 403 {
+            return Err(LlmError::Auth { status: status.as_u16() });
+        }
+        if !status.is_success() {
+            let message = res.text().await.unwrap_or_default();
+            return Err(LlmError::UpstreamHttp { status: Some(status.as_u16()), message });
+        }
+        let body: WhisperResponse = res.json().await?;
+        let text = body.text.trim().to_string();
+        if text.is_empty() {
+            return Err(LlmError::EmptyOutput);
+        }
+        info!("Transcribed {} bytes of {} into {} chars", audio.bytes.len(), audio.content_type, text.chars().count());
+        Ok(Transcript { text, language: body.language.as_deref().and_then(language_code).map(str::to_string) })
+    }
+}
+
+/// Returns the same transcript for every clip
+pub struct MockSpeechToText {
+    transcript: String,
+    /// Reported when no language is asked for
+    language: Option<String>,
+}
+
+impl MockSpeechToText {
+    pub fn new(transcript: &str) -> Self {
+        Self { transcript: transcript.to_string(), language: None }
+    }
+
+    pub fn heard_in(self, language: &str) -> Self {
+        Self { language: Some(language.to_string()), ..self }
+    }
+}
+
+#[async_trait]
+impl SpeechToText for MockSpeechToText {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn transcribe(&self, _http: &HttpClient, _audio: Audio<'_>, language: Option<&str>) -> Result<Transcript, LlmError> {
+        Ok(Transcript { text: self.transcript.clone(), language: language.map(str::to_string).or_else(|| self.language.clone()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::http;
+    use axum::extract::Multipart;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::collections::HashMap;
+
+    /// Stand-in for a Whisper server: checks the key, echoes the form back as the transcript
+    async fn whisper_server() -> String {
+        async fn transcribe(headers: HeaderMap, mut form: Multipart) -> (StatusCode, Json<serde_json::Value>) {
+            if headers.get("authorization").and_then(|v| v.to_str().ok()) != Some("Bearer secret") {
+                return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({ "error": "bad key" })));
+            }
+            let mut fields = HashMap::new();
+            while let Some(field) = form.next_field().await.unwrap() {
+                let name = field.name().unwrap().to_string();
+                let file = field.file_name().map(str::to_string);
+                let bytes = field.bytes().await.unwrap();
+                fields.insert(name, file.unwrap_or_else(|| String::from_utf8_lossy(&bytes).to_string()));
+            }
+            let text = format!("{} {} {}", fields["model"], fields["language"], fields["file"]);
+            (StatusCode::OK, Json(serde_json::json!({ "text": format!(" {} ", text), "language": "hindi" })))
+        }
+        let app = Router::new().route("/v1/audio/transcriptions", post(transcribe));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/v1", addr)
+    }
+
+    fn whisper(base_url: String, api_key: &str) -> WhisperHttp {
+        WhisperHttp { base_url, api_key: Some(api_key.to_string()), model: "large-v3".to_string(), deadline: Duration::from_secs(5) }
+    }
+
+
+    const CLIP: Audio<'static> = Audio { bytes: b"OggS\0\x02 opus", content_type: "audio/ogg" };
+
+    #[tokio::test]
+    async fn test_whisper_server_transcribes() {
+        let base = whisper_server().await;
+        let transcript = whisper(base, "secret").transcribe(&http(), CLIP, Some("hi")).await.unwrap();
+        assert_eq!(transcript.text, "large-v3 hi voice.ogg");
+        assert_eq!(transcript.language.as_deref(), Some("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_whisper_auth_failure() {
+        let base = whisper_server().await;
+        let err = whisper(base, "wrong").transcribe(&http(), CLIP, Some("hi")).await.unwrap_err();
+        assert!(matches!(err, LlmError::Auth { status: 401 }));
+    }
+}
@@ -0,0 +1,497 @@
+//! Tools the agent loop can call: live mandi prices, a weather forecast, a fertilizer
+//! calculator, a unit converter and knowledge-base search. Each tool declares a JSON schema
+//! for its arguments, which is checked before the call, and returns a short text observation
+//! that goes back to the model (and is used when grounding figures in the answer).
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::http::HttpClient;
+use super::json_schema;
+use crate::rag::retriever;
+
+/// Deadline for one upstream data request
+const TOOL_DEADLINE: Duration = Duration::from_secs(10);
+
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// One line the model reads to decide when to call the tool
+    fn description(&self) -> &'static str;
+
+    /// JSON schema of the arguments object
+    fn parameters(&self) -> Value;
+
+    /// Run with already validated arguments; errors are shown to the model as the observation
+    async fn call(&self, http: &HttpClient, args: &Value) -> Result<String, String>;
+}
+
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+const MANDI_API_URL: &str = "https://api.data.gov.in/resource/9ef84268-d588-465a-a308-a864a43d0070";
+const WEATHER_API_BASE: &str = "https://api.open-meteo.com/v1";
+const GEOCODING_API_BASE: &str = "https://geocoding-api.open-meteo.com/v1";
+
+/// All built-in tools on their public endpoints, without a mandi price key
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        Self::builtin(
+            MandiPrice { url: MANDI_API_URL.to_string(), api_key: None },
+            WeatherForecast { forecast_base: WEATHER_API_BASE.to_string(), geocoding_base: GEOCODING_API_BASE.to_string() },
+        )
+    }
+}
+
+impl ToolRegistry {
+    pub fn new(tools: Vec<Arc<dyn Tool>>) -> Self {
+        Self { tools }
+    }
+
+    /// All built-in tools. `DATA_GOV_API_KEY` enables mandi prices; `MANDI_API_URL`,
+    /// `WEATHER_API_BASE` and `GEOCODING_API_BASE` override the upstream endpoints.
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: &str| env::var(name).ok().filter(|v| !v.is_empty()).unwrap_or_else(|| default.to_string());
+        Self::builtin(
+            MandiPrice {
+                url: var("MANDI_API_URL", MANDI_API_URL),
+                api_key: env::var("DATA_GOV_API_KEY").ok().filter(|k| !k.is_empty()),
+            },
+            WeatherForecast {
+                forecast_base: var("WEATHER_API_BASE", WEATHER_API_BASE),
+                geocoding_base: var("GEOCODING_API_BASE", GEOCODING_API_BASE),
+            },
+        )
+    }
+
+    fn builtin(mandi: MandiPrice, weather: WeatherForecast) -> Self {
+        Self::new(vec![
+            Arc::new(mandi),
+            Arc::new(weather),
+            Arc::new(FertilizerCalculator),
+            Arc::new(UnitConverter),
+            Arc::new(KbSearch),
+        ])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// Tool list for the prompt: name, description and argument schema per line
+    pub fn describe(&self) -> String {
+        self.tools
+            .iter()
+            .map(|t| format!("- {}: {} Arguments: {}", t.name(), t.description(), t.parameters()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Validate `args` against the tool's schema and run it
+    pub async fn call(&self, http: &HttpClient, name: &str, args: &Value) -> Result<String, String> {
+        let tool = self
+            .tools
+            .iter()
+            .find(|t| t.name() == name)
+            .ok_or_else(|| format!("unknown tool '{}'", name))?;
+        json_schema::validate(&tool.parameters(), args).map_err(|e| format!("invalid arguments: {}", e))?;
+        tool.call(http, args).await
+    }
+}
+
+fn title_case(s: &str) -> String {
+    s.split_whitespace()
+        .map(|w| {
+            let mut chars = w.chars();
+            chars.next().map_or(String::new(), |c| c.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect())
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn round1(x: f64) -> f64 {
+    (x * 10.0).round() / 10.0
+}
+
+async fn get_json(http: &HttpClient, request: reqwest::RequestBuilder) -> Result<Value, String> {
+    let res = http.send(request, TOOL_DEADLINE).await.map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        return Err(format!("data service returned {}", res.status()));
+    }
+    res.json().await.map_err(|e| e.to_string())
+}
+
+/// Today's mandi prices from the Agmarknet dataset on data.gov.in
+struct MandiPrice {
+    url: String,
+    api_key: Option<String>,
+}
+
+#[async_trait]
+impl Tool for MandiPrice {
+    fn name(&self) -> &'static str {
+        "mandi_price"
+    }
+
+    fn description(&self) -> &'static str {
+        "Latest wholesale mandi prices (₹ per quintal) for a commodity, optionally in one state or district."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "commodity": { "type": "string", "minLength": 2 },
+                "state": { "type": "string" },
+                "district": { "type": "string" }
+            },
+            "required": ["commodity"],
+            "additionalProperties": false
+        })
+    }
+
+    async fn call(&self, http: &HttpClient, args: &Value) -> Result<String, String> {
+        let api_key = self.api_key.as_deref().ok_or("mandi prices are not configured (DATA_GOV_API_KEY)")?;
+        let commodity = title_case(args["commodity"].as_str().unwrap_or_default());
+        let mut query = vec![
+            ("api-key", api_key.to_string()),
+            ("format", "json".to_string()),
+            ("limit", "10".to_string()),
+            ("filters[commodity]", commodity.clone()),
+        ];
+        for (field, filter) in [("state", "filters[state]"), ("district", "filters[district]")] {
+            if let Some(value) = args[field].as_str() {
+                query.push((filter, title_case(value)));
+            }
+        }
+        let body = get_json(http, http.get(&self.url).query(&query)).await?;
+
+        let rows: Vec<String> = body["records"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|r| {
+                let field = |name: &str| r[name].as_str().unwrap_or("?").to_string();
+                format!(
+                    "{} ({}, {}) on {}: modal ₹{}/qtl, range ₹{}-{}/qtl",
+                    field("market"), field("district"), field("state"), field("arrival_date"),
+                    field("modal_price"), field("min_price"), field("max_price"),
+                )
+            })
+            .collect();
+        if rows.is_empty() {
+            return Ok(format!("No recent mandi prices found for {}.", commodity));
+        }
+        Ok(format!("{} prices:\n{}", commodity, rows.join("\n")))
+    }
+}
+
+/// Daily forecast from Open-Meteo, after geocoding the place name
+struct WeatherForecast {
+    forecast_base: String,
+    geocoding_base: String,
+}
+
+#[async_trait]
+impl Tool for WeatherForecast {
+    fn name(&self) -> &'static str {
+        "weather_forecast"
+    }
+
+    fn description(&self) -> &'static str {
+        "Daily temperature and rain forecast for a village, town or district in India."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "location": { "type": "string", "minLength": 2 },
+                "days": { "type": "integer", "minimum": 1, "maximum": 7 }
+            },
+            "required": ["location"],
+            "additionalProperties": false
+        })
+    }
+
+    async fn call(&self, http: &HttpClient, args: &Value) -> Result<String, String> {
+        let location = args["location"].as_str().unwrap_or_default();
+        let days = args["days"].as_u64().unwrap_or(3);
+
+        let geocoding = http
+            .get(&format!("{}/search", self.geocoding_base))
+            .query(&[("name", location), ("count", "1"), ("countryCode", "IN")]);
+        let places = get_json(http, geocoding).await?;
+        let place = places["results"].get(0).ok_or_else(|| format!("could not find '{}'", location))?;
+        let (Some(lat), Some(lon)) = (place["latitude"].as_f64(), place["longitude"].as_f64()) else {
+            return Err(format!("could not find '{}'", location));
+        };
+
+        let forecast = http.get(&format!("{}/forecast", self.forecast_base)).query(&[
+            ("latitude", lat.to_string()),
+            ("longitude", lon.to_string()),
+            ("daily", "temperature_2m_max,temperature_2m_min,precipitation_sum,precipitation_probability_max".to_string()),
+            ("timezone", "Asia/Kolkata".to_string()),
+            ("forecast_days", days.to_string()),
+        ]);
+        let body = get_json(http, forecast).await?;
+        let daily = &body["daily"];
+        let dates = daily["time"].as_array().ok_or("forecast had no daily data")?;
+
+        let lines: Vec<String> = dates
+            .iter()
+            .enumerate()
+            .map(|(i, date)| {
+                let value = |name: &str| daily[name][i].as_f64().map_or("?".to_string(), |v| round1(v).to_string());
+                format!(
+                    "{}: {}-{} °C, rain {} mm ({}% chance)",
+                    date.as_str().unwrap_or("?"),
+                    value("temperature_2m_min"), value("temperature_2m_max"),
+                    value("precipitation_sum"), value("precipitation_probability_max"),
+                )
+            })
+            .collect();
+        let name = [place["name"].as_str(), place["admin1"].as_str()].into_iter().flatten().collect::<Vec<_>>().join(", ");
+        Ok(format!("Forecast for {}:\n{}", name, lines.join("\n")))
+    }
+}
+
+/// General N:P2O5:K2O recommendations in kg/ha
+const NUTRIENTS_KG_PER_HA: &[(&str, [f64; 3])] = &[
+    ("wheat", [120.0, 60.0, 40.0]),
+    ("rice", [100.0, 50.0, 50.0]),
+    ("maize", [120.0, 60.0, 40.0]),
+    ("cotton", [100.0, 50.0, 50.0]),
+    ("soybean", [20.0, 60.0, 40.0]),
+    ("mustard", [80.0, 40.0, 40.0]),
+    ("chickpea", [20.0, 40.0, 20.0]),
+    ("onion", [100.0, 50.0, 50.0]),
+    ("tomato", [100.0, 60.0, 60.0]),
+    ("potato", [150.0, 80.0, 100.0]),
+    ("sugarcane", [250.0, 100.0, 100.0]),
+];
+
+const ACRE_IN_HA: f64 = 0.404_686;
+
+/// Urea, DAP and MOP quantities for a crop's (or a soil-test's) nutrient dose
+struct FertilizerCalculator;
+
+#[async_trait]
+impl Tool for FertilizerCalculator {
+    fn name(&self) -> &'static str {
+        "fertilizer_calculator"
+    }
+
+    fn description(&self) -> &'static str {
+        "Urea, DAP and MOP needed for a field, from the crop's standard dose or given N, P2O5 and K2O in kg/ha."
+    }
+
+    fn parameters(&self) -> Value {
+        let crops: Vec<&str> = NUTRIENTS_KG_PER_HA.iter().map(|(crop, _)| *crop).collect();
+        json!({
+            "type": "object",
+            "properties": {
+                "crop": { "enum": crops },
+                "area": { "type": "number", "minimum": 0.01, "maximum": 10000 },
+                "area_unit": { "enum": ["acre", "hectare"] },
+                "n_kg_per_ha": { "type": "number", "minimum": 0, "maximum": 500 },
+                "p2o5_kg_per_ha": { "type": "number", "minimum": 0, "maximum": 300 },
+                "k2o_kg_per_ha": { "type": "number", "minimum": 0, "maximum": 300 }
+            },
+            "required": ["area", "area_unit"],
+            "additionalProperties": false
+        })
+    }
+
+    async fn call(&self, _http: &HttpClient, args: &Value) -> Result<String, String> {
+        let crop = args["crop"].as_str();
+        let standard = crop.and_then(|c| NUTRIENTS_KG_PER_HA.iter().find(|(name, _)| *name == c)).map(|(_, npk)| *npk);
+        let given = [&args["n_kg_per_ha"], &args["p2o5_kg_per_ha"], &args["k2o_kg_per_ha"]].map(Value::as_f64);
+        let [n, p, k] = match (given, standard) {
+            ([None, None, None], Some(npk)) => npk,
+            ([None, None, None], None) => return Err("give a crop or the nutrient doses".to_string()),
+            ([n, p, k], _) => [n.unwrap_or(0.0), p.unwrap_or(0.0), k.unwrap_or(0.0)],
+        };
+
+        // DAP (18-46-0) covers phosphorus, urea (46% N) the rest of the nitrogen, MOP (60% K2O) potash
+        let dap = p / 0.46;
+        let urea = (n - dap * 0.18).max(0.0) / 0.46;
+        let mop = k / 0.60;
+
+        let area = args["area"].as_f64().unwrap_or_default();
+        let (unit, per_unit_ha) = match args["area_unit"].as_str() {
+            Some("acre") => ("acre", ACRE_IN_HA),
+            _ => ("ha", 1.0),
+        };
+        let line = |name: &str, kg_per_ha: f64| {
+            format!("{} {} kg/{} ({} kg in total)", name, round1(kg_per_ha * per_unit_ha), unit, round1(kg_per_ha * per_unit_ha * area))
+        };
+        Ok(format!(
+            "For {} {} of {} at N:P2O5:K2O {}:{}:{} kg/ha: {}, {}, {}. Apply DAP, MOP and a third of the urea at sowing, the rest of the urea in two splits.",
+            area, unit, crop.unwrap_or("the crop"), n, p, k,
+            line("DAP", dap), line("Urea", urea), line("MOP", mop),
+        ))
+    }
+}
+
+/// (unit, dimension, value in the dimension's base unit)
+const UNITS: &[(&str, &str, f64)] = &[
+    ("acre", "area", 4046.86),
+    ("hectare", "area", 10_000.0),
+    ("guntha", "area", 101.17),
+    ("sq_m", "area", 1.0),
+    ("g", "mass", 0.001),
+    ("kg", "mass", 1.0),
+    ("quintal", "mass", 100.0),
+    ("tonne", "mass", 1000.0),
+    ("ml", "volume", 0.001),
+    ("litre", "volume", 1.0),
+    ("kg_per_acre", "rate", 1.0 / 0.404_686),
+    ("kg_per_hectare", "rate", 1.0),
+];
+
+struct UnitConverter;
+
+#[async_trait]
+impl Tool for UnitConverter {
+    fn name(&self) -> &'static str {
+        "unit_converter"
+    }
+
+    fn description(&self) -> &'static str {
+        "Convert between farm units of area, weight, volume and per-area rates."
+    }
+
+    fn parameters(&self) -> Value {
+        let units: Vec<&str> = UNITS.iter().map(|(unit, _, _)| *unit).collect();
+        json!({
+            "type": "object",
+            "properties": {
+                "value": { "type": "number" },
+                "from": { "enum": units },
+                "to": { "enum": units }
+            },
+            "required": ["value", "from", "to"],
+            "additionalProperties": false
+        })
+    }
+
+    async fn call(&self, _http: &HttpClient, args: &Value) -> Result<String, String> {
+        let unit = |field: &str| UNITS.iter().find(|(name, _, _)| Some(*name) == args[field].as_str()).copied();
+        let (Some((from, from_dim, from_base)), Some((to, to_dim, to_base))) = (unit("from"), unit("to")) else {
+            return Err("unknown unit".to_string());
+        };
+        if from_dim != to_dim {
+            return Err(format!("cannot convert {} ({}) to {} ({})", from, from_dim, to, to_dim));
+        }
+        let value = args["value"].as_f64().unwrap_or_default();
+        let converted = value * from_base / to_base;
+        Ok(format!("{} {} = {} {}", value, from, (converted * 1000.0).round() / 1000.0, to))
+    }
+}
+
+/// The same keyword retrieval the chat pipeline uses, for follow-up lookups
+struct KbSearch;
+
+#[async_trait]
+impl Tool for KbSearch {
+    fn name(&self) -> &'static str {
+        "kb_search"
+    }
+
+    fn description(&self) -> &'static str {
+        "Search the farming knowledge base (crops, pests, soil, seasonal advice, support prices)."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": { "query": { "type": "string", "minLength": 2 } },
+            "required": ["query"],
+            "additionalProperties": false
+        })
+    }
+
+    async fn call(&self, _http: &HttpClient, args: &Value) -> Result<String, String> {
+        let passages = retriever::retrieve(args["query"].as_str().unwrap_or_default()).await;
+        if passages.is_empty() {
+            return Ok("Nothing relevant in the knowledge base.".to_string());
+        }
+        Ok(passages
+            .iter()
+            .map(|p| format!("{} ({}): {}", p.doc.title, p.doc.source, p.doc.content))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::http;
+    use axum::{extract::Query, routing::get, Json, Router};
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_arguments_are_validated_before_the_call() {
+        let tools = ToolRegistry::default();
+        let err = tools.call(&http(), "unit_converter", &json!({ "value": 2, "from": "acre" })).await.unwrap_err();
+        assert_eq!(err, "invalid arguments: $.to is required");
+        assert_eq!(tools.call(&http(), "horoscope", &json!({})).await.unwrap_err(), "unknown tool 'horoscope'");
+    }
+
+    #[tokio::test]
+    async fn test_calculators() {
+        let tools = ToolRegistry::default();
+        let converted = tools.call(&http(), "unit_converter", &json!({ "value": 2, "from": "acre", "to": "hectare" })).await;
+        assert_eq!(converted.unwrap(), "2 acre = 0.809 hectare");
+        let mismatch = tools.call(&http(), "unit_converter", &json!({ "value": 2, "from": "acre", "to": "kg" })).await;
+        assert!(mismatch.unwrap_err().starts_with("cannot convert"));
+
+        let dose = tools
+            .call(&http(), "fertilizer_calculator", &json!({ "crop": "wheat", "area": 2, "area_unit": "hectare" }))
+            .await
+            .unwrap();
+        assert!(dose.contains("DAP 130.4 kg/ha (260.9 kg in total)"), "{}", dose);
+        assert!(dose.contains("Urea 209.8 kg/ha"), "{}", dose);
+        assert!(dose.contains("MOP 66.7 kg/ha"), "{}", dose);
+    }
+
+    #[tokio::test]
+    async fn test_weather_forecast_geocodes_then_forecasts() {
+        let app = Router::new()
+            .route("/v1/search", get(|Query(q): Query<HashMap<String, String>>| async move {
+                assert_eq!(q["name"], "Nashik");
+                Json(json!({ "results": [{ "name": "Nashik", "admin1": "Maharashtra", "latitude": 20.0, "longitude": 73.8 }] }))
+            }))
+            .route("/v1/forecast", get(|Query(q): Query<HashMap<String, String>>| async move {
+                assert_eq!(q["forecast_days"], "2");
+                Json(json!({ "daily": {
+                    "time": ["2026-10-18", "2026-10-19"],
+                    "temperature_2m_min": [21.4, 20.9], "temperature_2m_max": [31.0, 29.6],
+                    "precipitation_sum": [0.0, 12.5], "precipitation_probability_max": [10, 80]
+                } }))
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let tool = WeatherForecast { forecast_base: base.clone(), geocoding_base: base };
+        let forecast = tool.call(&http(), &json!({ "location": "Nashik", "days": 2 })).await.unwrap();
+        assert_eq!(
+            forecast,
+            "Forecast for Nashik, Maharashtra:\n2026-10-18: 21.4-31 °C, rain 0 mm (10% chance)\n2026-10-19: 20.9-29.6 °C, rain 12.5 mm (80% chance)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mandi_price_without_key_is_reported() {
+        let tool = MandiPrice { url: "http://127.0.0.1:9".to_string(), api_key: None };
+        assert!(tool.call(&http(), &json!({ "commodity": "onion" })).await.unwrap_err().contains("DATA_GOV_API_KEY"));
+    }
+}
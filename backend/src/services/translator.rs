@@ -20,11 +20,19 @@ const MARATHI_MARKERS: &[&str] = &[
 
 /// Detect the language of input text
 pub fn detect_language(text: &str) -> String {
+    detect_language_with_certainty(text).0
+}
+
+/// Detected language plus how sure we are (0.5-1.0). Devanagari without
+/// Hindi or Marathi markers, or with both in similar numbers, is uncertain.
+pub fn detect_language_with_certainty(text: &str) -> (String, f32) {
     // Check for Devanagari script
     let has_devanagari = text.chars().any(|c| ('\u{0900}'..='\u{097F}').contains(&c));
     
     if !has_devanagari {
-        return "en".to_string();
+        // Romanised Hindi ("gehu ka bhav") also lands here, so never fully certain
+        let certainty = if text.chars().any(|c| c.is_ascii_alphabetic()) { 0.9 } else { 0.5 };
+        return ("en".to_string(), certainty);
     }
 
     // Count Hindi vs Marathi markers
@@ -38,13 +46,18 @@ pub fn detect_language(text: &str) -> String {
 
     debug!("Language detection - Hindi markers: {}, Marathi markers: {}", hindi_count, marathi_count);
 
+    let total = hindi_count + marathi_count;
+    let certainty = if total == 0 {
+        0.6
+    } else {
+        0.5 + 0.5 * hindi_count.abs_diff(marathi_count) as f32 / total as f32
+    };
+
     // If Marathi markers are more common, it's likely Marathi
     if marathi_count > hindi_count {
-        "mr".to_string()
-    } else if has_devanagari {
-        "hi".to_string()  // Default Devanagari to Hindi
+        ("mr".to_string(), certainty)
     } else {
-        "en".to_string()
+        ("hi".to_string(), certainty)  // Default Devanagari to Hindi
     }
 }
 
@@ -122,6 +135,7 @@ pub fn translate_to_english(text: &str, _from_lang: &str) -> String {
 
 /// Translate response from English to user's language
 /// For MVP, we keep English with key terms translated
+#[allow(dead_code)]
 pub fn translate_from_english(text: &str, to_lang: &str) -> String {
     if to_lang == "en" {
         return text.to_string();
@@ -158,4 +172,12 @@ mod tests {
     fn test_detect_english() {
         assert_eq!(detect_language("What is the price of tomato today?"), "en");
     }
+
+    #[test]
+    fn test_certainty_drops_when_markers_are_mixed() {
+        let (lang, clear) = detect_language_with_certainty("शेतकरी पीक काय आहे");
+        assert_eq!(lang, "mr");
+        let (_, mixed) = detect_language_with_certainty("आज भाव रोग");
+        assert!(clear > mixed);
+    }
 }
@@ -0,0 +1,112 @@
+//! Shared application state handed to every axum handler
+
+use std::sync::Arc;
+
+use crate::api::chat::ChatResponse;
+use crate::api::upload::UploadLimits;
+
+use crate::rag::agent::Agent;
+
+use crate::rag::system_prompt::SystemPrompt;
+use crate::rag::templates::TemplateRegistry;
+use crate::services::error::LlmError;
+use crate::services::generation::GenerationLimits;
+use crate::services::http::HttpClient;
+use crate::services::image_intake::ImageLimits;
+use crate::services::image_quality::QualityLimits;
+use crate::services::jobs::JobStore;
+use crate::services::llm_chain::LlmChain;
+use crate::services::metrics::Metrics;
+use crate::services::pest_reports::PestReportStore;
+use crate::services::replicate_webhook::ReplicateWebhooks;
+use crate::services::response_cache::ResponseCache;
+use crate::services::sessions::SessionStore;
+use crate::services::speech::{self, SpeechToText};
+
+#[derive(Clone)]
+pub struct AppState {
+    /// Pooled client for all upstream calls
+    pub http: HttpClient,
+    /// Ordered model chain for text queries
+    pub text_chain: Arc<LlmChain>,
+    /// Ordered model chain for queries with an image
+    pub vision_chain: Arc<LlmChain>,
+    /// Background chat jobs
+    pub jobs: Arc<JobStore>,
+    /// Webhook delivery for Replicate predictions; `None` means poll instead
+    pub replicate_webhooks: Option<Arc<ReplicateWebhooks>>,
+    /// Deployment persona loaded from `PROMPT_PROFILE`
+    pub system_prompt: Arc<SystemPrompt>,
+    /// Conversation history keyed by session ID
+    pub sessions: Arc<SessionStore>,
+    /// Per-mode caps on generation parameters
+    pub generation: Arc<GenerationLimits>,
+    /// Answers to recently asked questions
+    pub response_cache: Arc<ResponseCache<ChatResponse>>,
+    pub metrics: Arc<Metrics>,
+    /// Diagnosed pests and diseases with the location they were photographed at
+    pub pest_reports: Arc<PestReportStore>,
+    /// Tools the model may call while answering, and the step limit
+    pub agent: Arc<Agent>,
+    /// Size caps for uploaded photos and for what is sent upstream
+    pub image_limits: ImageLimits,
+    /// Sharpness, exposure and size a photo needs before it is worth a vision prediction
+    pub photo_quality: QualityLimits,
+    /// Transcribes voice notes; `None` when `STT_BACKEND` is not set
+    pub speech: Option<Arc<dyn SpeechToText>>,
+    /// Caps and temp directory for `multipart/form-data` uploads
+    pub upload_limits: UploadLimits,
+}
+
+impl AppState {
+    /// State around the given clients and chain, with no response cache and default limits
+    /// whatever the environment, for handler tests
+    #[cfg(test)]
+    pub fn for_tests(http: HttpClient, text_chain: LlmChain) -> Self {
+        use crate::services::tools::ToolRegistry;
+        use std::time::Duration;
+        Self {
+            http,
+            text_chain: Arc::new(text_chain),
+            vision_chain: Arc::new(LlmChain::new(Vec::new())),
+            jobs: Arc::new(JobStore::new(Duration::from_secs(3600))),
+            replicate_webhooks: None,
+            system_prompt: Arc::new(SystemPrompt::builtin()),
+            sessions: Arc::new(SessionStore::new(Duration::from_secs(1800), 600)),
+            generation: Arc::new(GenerationLimits::default()),
+            response_cache: Arc::new(ResponseCache::new(Duration::ZERO, Duration::ZERO, Duration::ZERO, 1.0, 0)),
+            metrics: Arc::new(Metrics::default()),
+            pest_reports: Arc::new(PestReportStore::new(100)),
+            agent: Arc::new(Agent::new(ToolRegistry::default(), 4)),
+            image_limits: ImageLimits::default(),
+            photo_quality: QualityLimits::default(),
+            speech: None,
+            upload_limits: UploadLimits::new(&ImageLimits::default()),
+        }
+    }
+
+    /// Fails on configuration the server must not start without
+    pub fn from_env() -> Result<Self, LlmError> {
+        let replicate_webhooks = ReplicateWebhooks::from_env().map(Arc::new);
+        let templates = TemplateRegistry::from_env()?;
+        let image_limits = ImageLimits::from_env();
+        Ok(Self {
+            http: HttpClient::from_env()?,
+            text_chain: Arc::new(LlmChain::text_from_env(&templates, replicate_webhooks.clone())),
+            vision_chain: Arc::new(LlmChain::vision_from_env(&templates, replicate_webhooks.clone())),
+            jobs: Arc::new(JobStore::from_env()),
+            replicate_webhooks,
+            system_prompt: Arc::new(SystemPrompt::from_env()?),
+            sessions: Arc::new(SessionStore::from_env()),
+            generation: Arc::new(GenerationLimits::from_env()?),
+            response_cache: Arc::new(ResponseCache::from_env()),
+            metrics: Arc::new(Metrics::default()),
+            pest_reports: Arc::new(PestReportStore::from_env()?),
+            agent: Arc::new(Agent::from_env()),
+            image_limits,
+            photo_quality: QualityLimits::from_env(),
+            speech: speech::from_env(),
+            upload_limits: UploadLimits::from_env(&image_limits),
+        })
+    }
+}
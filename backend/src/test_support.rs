@@ -0,0 +1,136 @@
+//! Fixtures shared by the unit tests: an HTTP client that never retries, scripted mock
+//! model chains, handler state built around them, and photos and forms to upload.
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header;
+use base64::Engine;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::services::circuit_breaker::CircuitBreaker;
+use crate::services::error::LlmError;
+use crate::services::generation::{GenerationParams, ResponseMode};
+use crate::services::http::{HttpClient, RetryPolicy};
+use crate::services::llm::LlmRequest;
+use crate::services::llm_chain::{ChainLink, LlmChain};
+use crate::services::mock_llm::MockProvider;
+use crate::state::AppState;
+
+/// No retries and a short connect timeout, so failing tests fail fast
+pub fn http() -> HttpClient {
+    HttpClient::new(Duration::from_secs(1), RetryPolicy { max_retries: 0, ..RetryPolicy::default() }).unwrap()
+}
+
+/// Successful replies, in order
+pub fn replies(script: &[&str]) -> Vec<Result<String, LlmError>> {
+    script.iter().map(|s| Ok(s.to_string())).collect()
+}
+
+/// A one-link chain around `MockProvider` named `mock:<name>`; once the script runs out it
+/// answers from the top passage
+pub fn mock_chain(name: &str, script: Vec<Result<String, LlmError>>) -> LlmChain {
+    LlmChain::new(vec![ChainLink::new(
+        Arc::new(MockProvider::scripted(name, script)),
+        CircuitBreaker::new(3, Duration::from_secs(30)),
+        Duration::from_secs(5),
+    )])
+}
+
+/// Handler state with `text_chain` for text queries and no vision models
+pub fn state(text_chain: LlmChain) -> AppState {
+    AppState::for_tests(http(), text_chain)
+}
+
+/// An English text request with no passages, history or schema
+pub fn llm_request(query: &str) -> LlmRequest {
+    LlmRequest {
+        system_prompt: "You are Kisan Mitra.".to_string(),
+        history: Vec::new(),
+        query: query.to_string(),
+        passages: Vec::new(),
+        image: None,
+        target_lang: "en".to_string(),
+        mode: ResponseMode::Normal,
+        params: GenerationParams::default(),
+        output_schema: None,
+        tracker: None,
+    }
+}
+
+/// A sharp, well-lit 480x360 leaf photo as JPEG when `light` is 1.0; lower is darker
+pub fn leaf_jpeg(light: f32) -> Vec<u8> {
+    let leaf = image::RgbImage::from_fn(480, 360, |x, y| {
+        let g = if (x + y) % 6 < 2 { 180.0 } else { 110.0 } * light;
+        image::Rgb([(g * 0.5) as u8, g as u8, (g * 0.4) as u8])
+    });
+    let mut jpeg = Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgb8(leaf).write_to(&mut jpeg, image::ImageFormat::Jpeg).unwrap();
+    jpeg.into_inner()
+}
+
+pub fn data_uri(jpeg: &[u8]) -> String {
+    format!("data:image/jpeg;base64,{}", base64::engine::general_purpose::STANDARD.encode(jpeg))
+}
+
+pub const BOUNDARY: &str = "kisan-test-boundary";
+
+/// A `multipart/form-data` body from (name, file name, content) parts
+pub fn form(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (name, file_name, content) in parts {
+        body.extend(format!("--{}\r\n", BOUNDARY).bytes());
+        match file_name {
+            Some(file) => body.extend(
+                format!("Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n", name, file).bytes(),
+            ),
+            None => body.extend(format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).bytes()),
+        }
+        body.extend_from_slice(content);
+        body.extend(b"\r\n");
+    }
+    body.extend(format!("--{}--\r\n", BOUNDARY).bytes());
+    body
+}
+
+pub fn form_request(body: Vec<u8>) -> Request {
+    Request::builder()
+        .method("POST")
+        .uri("/api/chat")
+        .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Log lines written on this thread while the guard is held
+#[derive(Clone, Default)]
+pub struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    pub fn start() -> (Self, tracing::subscriber::DefaultGuard) {
+        let logs = Self::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).to_string()
+    }
+}
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -6,3 +6,13 @@ pub fn init() {
     // Tracing is initialized in main.rs
     // This is a no-op for backward compatibility
 }
+
+/// The farmer's words as they go in a log line: quoted when typed, only their length when
+/// transcribed from a voice note
+pub fn query_text(text: &str, transcribed: bool) -> String {
+    if transcribed {
+        format!("<{} transcribed chars>", text.chars().count())
+    } else {
+        format!("'{}'", text)
+    }
+}
@@ -4,13 +4,56 @@ export interface ChatRequest {
   query: string;
   language: string;
   image?: string | null;
+  session_id?: string | null;
+  mode?: 'brief' | 'normal' | 'detailed';
+  params?: { max_tokens?: number; temperature?: number; top_p?: number };
+  /** Let the server read the photo's GPS position and date; they are never forwarded */
+  share_location?: boolean;
+}
+
+export interface Citation {
+  id: number;
+  title: string;
+  source: string;
+  snippet: string;
+  url: string | null;
+}
+
+export interface ToolCall {
+  step: number;
+  tool: string;
+  arguments: Record<string, unknown>;
+  output?: string;
+  error?: string;
+  duration_ms: number;
 }
 
 export interface ChatResponse {
   answer: string;
   sources: string[];
+  citations: Citation[];
   confidence: 'low' | 'medium' | 'high';
+  confidence_score: number;
+  unverified_figures: string[];
   detected_language: string;
+  degraded: boolean;
+  fallback_reason: string | null;
+  answered_by: string;
+  session_id: string;
+  cached: boolean;
+  tool_calls: ToolCall[];
+  photo_issues: PhotoIssue[];
+  photo_context: PhotoContext | null;
+  transcript: string | null;
+}
+
+export interface PhotoContext {
+  latitude: number | null;
+  longitude: number | null;
+  district: string | null;
+  state: string | null;
+  season: 'kharif' | 'rabi' | 'zaid' | null;
+  taken_on: string | null;
 }
 
 export interface ApiError {
@@ -67,3 +110,182 @@ export async function translateText(request: TranslateRequest): Promise<Translat
 
   return res.json();
 }
+
+export interface CropPlan {
+  crop: string;
+  season: string;
+  stages: { stage: string; timing: string; tasks: string[] }[];
+}
+
+export interface FertilizerSchedule {
+  crop: string;
+  area_unit: 'acre' | 'hectare';
+  applications: { timing: string; fertilizer: string; quantity_kg: number; notes?: string | null }[];
+}
+
+interface StructuredKinds {
+  crop_plan: CropPlan;
+  fertilizer_schedule: FertilizerSchedule;
+}
+
+export interface StructuredResponse<K extends keyof StructuredKinds> {
+  kind: K;
+  data: StructuredKinds[K];
+  sources: string[];
+  answered_by: string;
+  attempts: number;
+  citations: Citation[];
+  confidence: 'low' | 'medium' | 'high';
+  confidence_score: number;
+  unverified_figures: string[];
+}
+
+export async function fetchStructured<K extends keyof StructuredKinds>(
+  kind: K,
+  query: string,
+  language = 'en',
+): Promise<StructuredResponse<K>> {
+  const res = await fetch(`${BACKEND_URL}/api/structured`, {
+    method: 'POST',
+    headers: {
+      'Content-Type': 'application/json',
+    },
+    body: JSON.stringify({ kind, query, language }),
+  });
+
+  if (!res.ok) {
+    const errorData: ApiError = await res.json().catch(() => ({ error: 'Unknown error' }));
+    throw new Error(errorData.error || `HTTP error: ${res.status}`);
+  }
+
+  return res.json();
+}
+
+export interface DiagnoseRequest {
+  crop: string;
+  images: string[];
+  notes?: string | null;
+  language?: string;
+  share_location?: boolean;
+}
+
+export interface Finding {
+  name: string;
+  kind: 'disease' | 'pest' | 'deficiency' | 'other';
+  confidence: number;
+  symptoms: string[];
+  controls: string[];
+  kb_title: string | null;
+  source: string | null;
+  url: string | null;
+}
+
+export type PhotoIssue = 'blurry' | 'too_dark' | 'too_bright' | 'too_small';
+
+export interface RejectedPhoto {
+  photo: number;
+  issues: PhotoIssue[];
+}
+
+export interface Diagnosis {
+  status: 'diagnosed' | 'needs_clarification' | 'healthy' | 'retake_photo';
+  findings: Finding[];
+  clarifying_questions: string[];
+  answered_by: string;
+  images_analyzed: number;
+  rejected_photos: RejectedPhoto[];
+  photo_context: PhotoContext | null;
+}
+
+export async function diagnose(request: DiagnoseRequest): Promise<Diagnosis> {
+  const res = await fetch(`${BACKEND_URL}/api/diagnose`, {
+    method: 'POST',
+    headers: {
+      'Content-Type': 'application/json',
+    },
+    body: JSON.stringify(request),
+  });
+
+  if (!res.ok) {
+    const errorData: ApiError = await res.json().catch(() => ({ error: 'Unknown error' }));
+    throw new Error(errorData.error || `HTTP error: ${res.status}`);
+  }
+
+  return res.json();
+}
+
+/** Diagnose straight from picked files, sent as multipart/form-data without base64 */
+export async function diagnoseFiles(
+  crop: string,
+  photos: File[],
+  options: { notes?: string; language?: string; voice?: Blob; shareLocation?: boolean } = {}
+): Promise<Diagnosis> {
+  const form = new FormData();
+  form.append('crop', crop);
+  photos.forEach((photo) => form.append('image', photo));
+  if (options.notes) form.append('notes', options.notes);
+  if (options.language) form.append('language', options.language);
+  if (options.shareLocation) form.append('share_location', 'true');
+  if (options.voice) form.append('voice', options.voice, 'voice-note');
+
+  const res = await fetch(`${BACKEND_URL}/api/diagnose`, {
+    method: 'POST',
+    body: form,
+  });
+
+  if (!res.ok) {
+    const errorData: ApiError = await res.json().catch(() => ({ error: 'Unknown error' }));
+    throw new Error(errorData.error || `HTTP error: ${res.status}`);
+  }
+
+  return res.json();
+}
+
+/** Sightings of one pest on one crop in a district and season */
+export interface PestSummary {
+  district: string;
+  state: string | null;
+  crop: string;
+  name: string;
+  kind: string;
+  season: PhotoContext['season'];
+  reports: number;
+  mean_confidence: number;
+}
+
+export async function fetchPestReports(district?: string, limit = 100): Promise<PestSummary[]> {
+  const params = new URLSearchParams({ limit: String(limit) });
+  if (district) params.set('district', district);
+  const res = await fetch(`${BACKEND_URL}/api/pest-reports?${params}`);
+
+  if (!res.ok) {
+    throw new Error(`HTTP error: ${res.status}`);
+  }
+
+  return res.json();
+}
+
+/** Ask by voice: the clip is transcribed server-side and answered like a typed question */
+export async function askByVoice(
+  clip: Blob,
+  language: string,
+  options: { sessionId?: string; mode?: ChatRequest['mode'] } = {}
+): Promise<ChatResponse> {
+  const form = new FormData();
+  form.append('voice', clip, 'question');
+  form.append('language', language);
+  if (options.sessionId) form.append('session_id', options.sessionId);
+  if (options.mode) form.append('mode', options.mode);
+
+  const res = await fetch(`${BACKEND_URL}/api/voice`, {
+    method: 'POST',
+    body: form,
+  });
+
+  if (!res.ok) {
+    const errorData: ApiError = await res.json().catch(() => ({ error: 'Unknown error' }));
+    throw new Error(errorData.error || `HTTP error: ${res.status}`);
+  }
+
+  return res.json();
+}